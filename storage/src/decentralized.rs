@@ -0,0 +1,501 @@
+//! Content-addressed block store, share files between group members.
+//!
+//! Data is split into chunks, every block is addressed by its blake3 hash.
+//! Chunks are linked by manifest blocks (a Merkle DAG), and the root hash is
+//! the address of the whole data. Pinned roots are kept when `gc`.
+//!
+//! Missing blocks can be fetched from other peers, `fetch` builds the want
+//! request, and `handle` answers the requests and stores received blocks,
+//! the messages are sended by `SendType::Event` in the group.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tdn_types::{
+    message::SendType,
+    primitives::{new_io_error, PeerId, Result},
+};
+use tokio::fs;
+
+/// the max bytes of a data chunk.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// the max links of a manifest block.
+pub const MANIFEST_LINKS: usize = 1024;
+
+/// Type: blake3 hash of the block.
+pub type BlockHash = [u8; 32];
+
+const BLOCK_CHUNK: u8 = 0;
+const BLOCK_MANIFEST: u8 = 1;
+
+const MESSAGE_WANT: u8 = 0;
+const MESSAGE_BLOCK: u8 = 1;
+
+/// block hash to hex string, it is the file name of the block.
+pub fn to_hex(hash: &BlockHash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// hex string to block hash.
+pub fn from_hex(s: &str) -> Result<BlockHash> {
+    // non-ASCII string can not be sliced by bytes.
+    if s.len() != 64 || !s.is_ascii() {
+        return Err(new_io_error("block hash is invalid").into());
+    }
+
+    let mut hash = [0u8; 32];
+    for (i, pair) in s.as_bytes().chunks(2).enumerate() {
+        let pair = std::str::from_utf8(pair)?;
+        hash[i] = u8::from_str_radix(pair, 16)?;
+    }
+    Ok(hash)
+}
+
+fn hash(block: &[u8]) -> BlockHash {
+    *blake3::hash(block).as_bytes()
+}
+
+/// Block in the DAG, a data chunk or a manifest links to children.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Block {
+    /// raw data chunk.
+    Chunk(Vec<u8>),
+    /// total data size, children block hashes.
+    Manifest(u64, Vec<BlockHash>),
+}
+
+impl Block {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Block::Chunk(data) => {
+                let mut bytes = vec![BLOCK_CHUNK];
+                bytes.extend(data);
+                bytes
+            }
+            Block::Manifest(size, links) => {
+                let mut bytes = vec![BLOCK_MANIFEST];
+                bytes.extend(&size.to_be_bytes());
+                for link in links {
+                    bytes.extend(link);
+                }
+                bytes
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Block> {
+        if bytes.len() == 0 {
+            return Err(new_io_error("block is empty").into());
+        }
+
+        match bytes[0] {
+            BLOCK_CHUNK => Ok(Block::Chunk(bytes[1..].to_vec())),
+            BLOCK_MANIFEST => {
+                if bytes.len() < 9 || (bytes.len() - 9) % 32 != 0 {
+                    return Err(new_io_error("manifest is invalid").into());
+                }
+                let mut size_bytes = [0u8; 8];
+                size_bytes.copy_from_slice(&bytes[1..9]);
+                let links = bytes[9..]
+                    .chunks(32)
+                    .map(|c| {
+                        let mut link = [0u8; 32];
+                        link.copy_from_slice(c);
+                        link
+                    })
+                    .collect();
+                Ok(Block::Manifest(u64::from_be_bytes(size_bytes), links))
+            }
+            _ => Err(new_io_error("block type is invalid").into()),
+        }
+    }
+}
+
+/// block exchange message between peers.
+#[derive(Debug, Eq, PartialEq)]
+pub enum BlockMessage {
+    /// request these blocks.
+    Want(Vec<BlockHash>),
+    /// response a block's bytes.
+    Block(Vec<u8>),
+}
+
+impl BlockMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            BlockMessage::Want(hashes) => {
+                let mut bytes = vec![MESSAGE_WANT];
+                for h in hashes {
+                    bytes.extend(h);
+                }
+                bytes
+            }
+            BlockMessage::Block(block) => {
+                let mut bytes = vec![MESSAGE_BLOCK];
+                bytes.extend(block);
+                bytes
+            }
+        }
+    }
+
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Result<BlockMessage> {
+        if bytes.len() == 0 {
+            return Err(new_io_error("block message is empty").into());
+        }
+
+        match bytes.remove(0) {
+            MESSAGE_WANT => {
+                if bytes.len() % 32 != 0 {
+                    return Err(new_io_error("want message is invalid").into());
+                }
+                Ok(BlockMessage::Want(
+                    bytes
+                        .chunks(32)
+                        .map(|c| {
+                            let mut h = [0u8; 32];
+                            h.copy_from_slice(c);
+                            h
+                        })
+                        .collect(),
+                ))
+            }
+            MESSAGE_BLOCK => Ok(BlockMessage::Block(bytes)),
+            _ => Err(new_io_error("block message type is invalid").into()),
+        }
+    }
+}
+
+/// local block store, blocks saved as files in `path/blocks`,
+/// pinned roots saved in `path/pins`.
+pub struct BlockStore {
+    blocks: PathBuf,
+    pins: PathBuf,
+    /// blocks wanted from other peers, only store these blocks when received.
+    wants: Mutex<HashSet<BlockHash>>,
+}
+
+impl BlockStore {
+    pub async fn open(path: PathBuf) -> Result<BlockStore> {
+        let mut blocks = path.clone();
+        blocks.push("blocks");
+        let mut pins = path;
+        pins.push("pins");
+        fs::create_dir_all(&blocks).await?;
+        fs::create_dir_all(&pins).await?;
+
+        Ok(BlockStore {
+            blocks,
+            pins,
+            wants: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn block_path(&self, hash: &BlockHash) -> PathBuf {
+        let mut path = self.blocks.clone();
+        path.push(to_hex(hash));
+        path
+    }
+
+    fn pin_path(&self, hash: &BlockHash) -> PathBuf {
+        let mut path = self.pins.clone();
+        path.push(to_hex(hash));
+        path
+    }
+
+    /// save the block, return the block hash.
+    pub async fn put_block(&self, block: &Block) -> Result<BlockHash> {
+        let bytes = block.to_bytes();
+        let h = hash(&bytes);
+        let path = self.block_path(&h);
+        if !path.exists() {
+            fs::write(path, bytes).await?;
+        }
+        Ok(h)
+    }
+
+    /// read the block, and check the hash. if not found, return None.
+    pub async fn get_block(&self, hash: &BlockHash) -> Result<Option<Block>> {
+        match self.get_block_bytes(hash).await? {
+            Some(bytes) => Ok(Some(Block::from_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_block_bytes(&self, h: &BlockHash) -> Result<Option<Vec<u8>>> {
+        let path = self.block_path(h);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let bytes = fs::read(path).await?;
+        if &hash(&bytes) != h {
+            return Err(new_io_error("block is broken").into());
+        }
+        Ok(Some(bytes))
+    }
+
+    pub fn has_block(&self, hash: &BlockHash) -> bool {
+        self.block_path(hash).exists()
+    }
+
+    /// split data to blocks, build the DAG, return the root hash.
+    pub async fn add(&self, data: &[u8]) -> Result<BlockHash> {
+        let mut level: Vec<(u64, BlockHash)> = vec![];
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let h = self.put_block(&Block::Chunk(chunk.to_vec())).await?;
+            level.push((chunk.len() as u64, h));
+        }
+
+        if level.len() == 0 {
+            level.push((0, self.put_block(&Block::Chunk(vec![])).await?));
+        }
+
+        // build manifests until only one root.
+        loop {
+            let mut next = vec![];
+            for links in level.chunks(MANIFEST_LINKS) {
+                let size = links.iter().map(|(s, _)| s).sum();
+                let block = Block::Manifest(size, links.iter().map(|(_, h)| *h).collect());
+                next.push((size, self.put_block(&block).await?));
+            }
+            level = next;
+            if level.len() == 1 {
+                break;
+            }
+        }
+
+        Ok(level[0].1)
+    }
+
+    /// read the whole data of the root. if has missing blocks, return error.
+    pub async fn cat(&self, root: &BlockHash) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        let mut stack = vec![*root];
+        while let Some(h) = stack.pop() {
+            match self.get_block(&h).await? {
+                Some(Block::Chunk(chunk)) => data.extend(chunk),
+                Some(Block::Manifest(_, links)) => stack.extend(links.iter().rev()),
+                None => return Err(new_io_error("block is missing").into()),
+            }
+        }
+        Ok(data)
+    }
+
+    /// all the missing blocks which can be known now.
+    /// children of a missing manifest are unknown until it is fetched.
+    pub async fn missing(&self, root: &BlockHash) -> Result<Vec<BlockHash>> {
+        let mut missing = vec![];
+        let mut stack = vec![*root];
+        while let Some(h) = stack.pop() {
+            match self.get_block(&h).await? {
+                Some(Block::Manifest(_, links)) => stack.extend(links),
+                Some(Block::Chunk(_)) => {}
+                None => missing.push(h),
+            }
+        }
+        Ok(missing)
+    }
+
+    /// all the blocks linked by the root, include itself.
+    async fn reachable(&self, root: &BlockHash, set: &mut HashSet<BlockHash>) -> Result<()> {
+        let mut stack = vec![*root];
+        while let Some(h) = stack.pop() {
+            if !set.insert(h) {
+                continue;
+            }
+            if let Some(Block::Manifest(_, links)) = self.get_block(&h).await? {
+                stack.extend(links);
+            }
+        }
+        Ok(())
+    }
+
+    /// pin the root, it will not be removed when `gc`.
+    pub async fn pin(&self, root: &BlockHash) -> Result<()> {
+        Ok(fs::write(self.pin_path(root), vec![]).await?)
+    }
+
+    pub async fn unpin(&self, root: &BlockHash) -> Result<()> {
+        let path = self.pin_path(root);
+        if path.exists() {
+            fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// all pinned roots.
+    pub async fn pins(&self) -> Result<Vec<BlockHash>> {
+        let mut pins = vec![];
+        let mut dir = fs::read_dir(&self.pins).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if let Ok(h) = from_hex(&entry.file_name().to_string_lossy()) {
+                pins.push(h);
+            }
+        }
+        Ok(pins)
+    }
+
+    /// remove all blocks not linked by pinned roots, return the removed number.
+    pub async fn gc(&self) -> Result<usize> {
+        let mut keeps = HashSet::new();
+        for root in self.pins().await? {
+            self.reachable(&root, &mut keeps).await?;
+        }
+
+        let mut removed = 0;
+        let mut dir = fs::read_dir(&self.blocks).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            match from_hex(&entry.file_name().to_string_lossy()) {
+                Ok(h) if keeps.contains(&h) => {}
+                _ => {
+                    fs::remove_file(entry.path()).await?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// build the request of missing blocks of root to the peer.
+    /// if no missing blocks, return None.
+    pub async fn fetch(&self, peer_id: PeerId, root: &BlockHash) -> Result<Option<SendType>> {
+        let missing = self.missing(root).await?;
+        if missing.len() == 0 {
+            return Ok(None);
+        }
+
+        self.wants.lock().unwrap().extend(missing.iter());
+        let msg = BlockMessage::Want(missing);
+        Ok(Some(SendType::Event(0, peer_id, msg.to_bytes())))
+    }
+
+    /// handle the block message from the peer, return messages need send.
+    /// when receive `Want`, send the blocks which has,
+    /// when receive `Block`, save it and request its missing children.
+    pub async fn handle(&self, peer_id: PeerId, data: Vec<u8>) -> Result<Vec<SendType>> {
+        let mut results = vec![];
+
+        match BlockMessage::from_bytes(data)? {
+            BlockMessage::Want(hashes) => {
+                for h in hashes {
+                    if let Some(bytes) = self.get_block_bytes(&h).await? {
+                        let msg = BlockMessage::Block(bytes);
+                        results.push(SendType::Event(0, peer_id, msg.to_bytes()));
+                    }
+                }
+            }
+            BlockMessage::Block(bytes) => {
+                let h = hash(&bytes);
+                if !self.wants.lock().unwrap().remove(&h) {
+                    return Ok(results); // not wanted, ignore it.
+                }
+
+                let block = Block::from_bytes(&bytes)?;
+                self.put_block(&block).await?;
+                if let Block::Manifest(_, links) = block {
+                    let missing: Vec<BlockHash> =
+                        links.into_iter().filter(|l| !self.has_block(l)).collect();
+                    if missing.len() > 0 {
+                        self.wants.lock().unwrap().extend(missing.iter());
+                        let msg = BlockMessage::Want(missing);
+                        results.push(SendType::Event(0, peer_id, msg.to_bytes()));
+                    }
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_hex() {
+        let hash = hash(b"tdn");
+        assert_eq!(from_hex(&to_hex(&hash)).unwrap(), hash);
+        assert!(from_hex("00").is_err());
+        assert!(from_hex(&"g".repeat(64)).is_err());
+        // 64 bytes, but not ASCII.
+        assert!(from_hex(&format!("{}é", "0".repeat(62))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_block_store() {
+        let path = PathBuf::from("./.test_block_store");
+        let _ = std::fs::remove_dir_all(&path);
+        let store = BlockStore::open(path.clone()).await.unwrap();
+
+        let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+        let root = store.add(&data).await.unwrap();
+        assert_eq!(store.cat(&root).await.unwrap(), data);
+        assert_eq!(store.missing(&root).await.unwrap().len(), 0);
+
+        let other = store.add(b"other").await.unwrap();
+        store.pin(&root).await.unwrap();
+        assert_eq!(store.pins().await.unwrap(), vec![root]);
+        assert_eq!(store.gc().await.unwrap(), 2); // other's chunk & manifest.
+        assert!(!store.has_block(&other));
+        assert_eq!(store.cat(&root).await.unwrap(), data);
+
+        store.unpin(&root).await.unwrap();
+        assert_eq!(store.gc().await.unwrap(), 4);
+        assert!(!store.has_block(&root));
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_block_fetch() {
+        let a_path = PathBuf::from("./.test_block_fetch_a");
+        let b_path = PathBuf::from("./.test_block_fetch_b");
+        let _ = std::fs::remove_dir_all(&a_path);
+        let _ = std::fs::remove_dir_all(&b_path);
+        let a = BlockStore::open(a_path.clone()).await.unwrap();
+        let b = BlockStore::open(b_path.clone()).await.unwrap();
+        let a_id = PeerId::default();
+        let b_id = PeerId::default();
+
+        let data: Vec<u8> = (0..CHUNK_SIZE * 3).map(|i| (i % 7) as u8).collect();
+        let root = a.add(&data).await.unwrap();
+
+        // channel pair, as the group events between a & b.
+        let (a_send, mut a_recv) = mpsc::channel::<SendType>(128);
+        let (b_send, mut b_recv) = mpsc::channel::<SendType>(128);
+
+        b_send
+            .send(b.fetch(a_id, &root).await.unwrap().unwrap())
+            .await
+            .unwrap();
+
+        loop {
+            tokio::select! {
+                Some(SendType::Event(_, _, data)) = b_recv.recv() => {
+                    for msg in a.handle(b_id, data).await.unwrap() {
+                        a_send.send(msg).await.unwrap();
+                    }
+                }
+                Some(SendType::Event(_, _, data)) = a_recv.recv() => {
+                    for msg in b.handle(a_id, data).await.unwrap() {
+                        b_send.send(msg).await.unwrap();
+                    }
+                }
+                else => break,
+            }
+
+            if b.missing(&root).await.unwrap().len() == 0 && b.has_block(&root) {
+                break;
+            }
+        }
+
+        assert_eq!(b.cat(&root).await.unwrap(), data);
+        assert!(b.fetch(a_id, &root).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(a_path).unwrap();
+        std::fs::remove_dir_all(b_path).unwrap();
+    }
+}
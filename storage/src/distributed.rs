@@ -1,18 +1,12 @@
-use postcard::{from_bytes, to_allocvec};
+use bincode::{deserialize as from_bytes, serialize as to_allocvec};
 use serde::{de::DeserializeOwned, Serialize};
-use std::{
-    io::{Error, ErrorKind, Result},
-    path::PathBuf,
-};
-use tdn_types::primitives::DEFAULT_STORAGE_DIR;
-use tdn_types::storage::Storage;
-
-fn new_error(s: &str) -> Error {
-    Error::new(ErrorKind::Other, s)
-}
+use std::path::PathBuf;
+use tdn_types::primitives::{new_io_error, Result, DEFAULT_STORAGE_DIR_NAME};
+use tdn_types::storage::{Batch, BatchOp, Page, Storage, StorageEvent};
+use tokio::sync::mpsc::{self, Receiver};
 
 pub fn open_db(name: &str) -> Result<LocalDB> {
-    let mut path = DEFAULT_STORAGE_DIR.clone();
+    let mut path = PathBuf::from(DEFAULT_STORAGE_DIR_NAME);
     path.push(name);
     LocalDB::open_absolute(&path)
 }
@@ -22,104 +16,318 @@ pub fn open_absolute_db(mut path: PathBuf, name: &str) -> Result<LocalDB> {
     LocalDB::open_absolute(&path)
 }
 
+/// sled db, changes are flushed by sled in background (every 500ms),
+/// or call `flush` when need persist immediately.
 pub struct LocalDB {
     tree: sled::Db,
 }
 
+/// decode at most `limit` items, the key of the next item is the next page.
+fn page<T: DeserializeOwned>(
+    mut items: Vec<(Vec<u8>, sled::IVec)>,
+    limit: usize,
+) -> Result<Page<Vec<u8>, T>> {
+    let next = if items.len() > limit {
+        items.truncate(limit + 1);
+        items.pop().map(|(k, _)| k)
+    } else {
+        None
+    };
+    let mut page = Page {
+        items: vec![],
+        next,
+    };
+    for (k, v) in items {
+        page.items.push((k, from_bytes(&v)?));
+    }
+    Ok(page)
+}
+
+/// take `limit` and one more items, the more one is the start of the next page.
+fn take(
+    iter: impl Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>,
+    limit: usize,
+) -> Result<Vec<(Vec<u8>, sled::IVec)>> {
+    let mut items = vec![];
+    for item in iter.take(limit.saturating_add(1)) {
+        let (k, v) = item.map_err(|_e| new_io_error("db read failure!"))?;
+        items.push((k.to_vec(), v));
+    }
+    Ok(items)
+}
+
 impl Storage for LocalDB {
     type Key = Vec<u8>;
 
-    fn read<T: Serialize + DeserializeOwned>(&self, k: &Self::Key) -> Option<T> {
-        self.tree
-            .get(k)
-            .ok()
-            .map(|v| v)
-            .flatten()
-            .map(|v| from_bytes(&v).ok())
-            .flatten()
+    async fn read<T: Serialize + DeserializeOwned + Send>(&self, k: &Self::Key) -> Option<T> {
+        let k = k.clone();
+        self.blocking(move |tree| {
+            tree.get(k)
+                .map_err(|_e| new_io_error("db read failure!").into())
+        })
+        .await
+        .ok()
+        .flatten()
+        .and_then(|v| from_bytes(&v).ok())
+    }
+
+    async fn write<T: Serialize + DeserializeOwned + Sync>(
+        &self,
+        k: &Self::Key,
+        t: &T,
+    ) -> Result<()> {
+        let bytes = to_allocvec(t).map_err(|_e| new_io_error("db serialize error!"))?;
+        let k = k.clone();
+        self.blocking(move |tree| {
+            tree.insert(k, bytes)
+                .map_err(|_e| new_io_error("db write failure!"))?;
+            Ok(())
+        })
+        .await
     }
 
-    fn write<T: Serialize + DeserializeOwned>(&self, k: &Self::Key, t: &T) -> Result<()> {
-        to_allocvec(t)
-            .map_err(|_e| new_error("db serialize error!"))
-            .and_then(|bytes| {
-                self.tree
-                    .insert(k, bytes)
-                    .map_err(|_e| new_error("db write failure!"))
-                    .and_then(|_| {
-                        self.tree.flush()?;
-                        Ok(())
-                    })
+    async fn update<T: Serialize + DeserializeOwned + Sync>(
+        &self,
+        k: &Self::Key,
+        t: &T,
+    ) -> Result<()> {
+        let bytes = to_allocvec(t).map_err(|_e| new_io_error("db serialize error!"))?;
+        let k = k.clone();
+        self.blocking(move |tree| {
+            let old = tree.get(&k).ok().flatten();
+            if old.is_none() {
+                tree.insert(k, bytes)
+                    .map_err(|_e| new_io_error("db write failure!"))?;
+            } else {
+                tree.compare_and_swap(k, old, Some(bytes))
+                    .map_err(|_e| new_io_error("db swap failure!"))?
+                    .map_err(|_e| new_io_error("db swap failure!"))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete<T: Serialize + DeserializeOwned + Send>(&self, k: &Self::Key) -> Result<T> {
+        let k = k.clone();
+        let old = self
+            .blocking(move |tree| {
+                Ok(tree
+                    .remove(k)
+                    .map_err(|_e| new_io_error("db delete error"))?
+                    .ok_or(new_io_error("db delete key not found!"))?)
             })
+            .await?;
+        Ok(from_bytes(&old)?)
     }
 
-    fn update<T: Serialize + DeserializeOwned>(&self, k: &Vec<u8>, t: &T) -> Result<()> {
-        to_allocvec(&t)
-            .map_err(|_e| new_error("db serialize error!"))
-            .and_then(|bytes| {
-                let old = self.tree.get(&k).ok().map(|v| v).flatten();
-                if old.is_none() {
-                    self.tree
-                        .insert(k, bytes)
-                        .map_err(|_e| new_error("db write failure!"))
-                        .and_then(|_| {
-                            self.tree.flush()?;
-                            Ok(())
-                        })
-                } else {
-                    self.tree
-                        .compare_and_swap(k, Some(old.unwrap()), Some(bytes))
-                        .map_err(|_e| new_error("db swap failure!"))
-                        .and_then(|_| {
-                            self.tree.flush()?;
-                            Ok(())
-                        })
-                }
+    async fn scan_prefix<T: Serialize + DeserializeOwned + Send>(
+        &self,
+        prefix: &Self::Key,
+        start: Option<&Self::Key>,
+        limit: usize,
+    ) -> Result<Page<Self::Key, T>> {
+        let (prefix, start) = (prefix.clone(), start.cloned());
+        let items = self
+            .blocking(move |tree| match start {
+                Some(start) => take(
+                    tree.range(start.max(prefix.clone())..).take_while(|item| {
+                        item.as_ref().map_or(true, |(k, _)| k.starts_with(&prefix))
+                    }),
+                    limit,
+                ),
+                None => take(tree.scan_prefix(prefix), limit),
             })
+            .await?;
+        page(items, limit)
+    }
+
+    async fn range<T: Serialize + DeserializeOwned + Send>(
+        &self,
+        start: &Self::Key,
+        end: &Self::Key,
+        limit: usize,
+    ) -> Result<Page<Self::Key, T>> {
+        let (start, end) = (start.clone(), end.clone());
+        let items = self
+            .blocking(move |tree| take(tree.range(start..end), limit))
+            .await?;
+        page(items, limit)
     }
 
-    fn delete<T: Serialize + DeserializeOwned>(&self, k: &Self::Key) -> Result<T> {
-        let result = self.read::<T>(k);
-        if result.is_some() {
-            self.tree
-                .remove(k)
-                .map_err(|_e| new_error("db delete error"))
-                .and_then(|_| {
-                    self.tree.flush()?;
-                    Ok(())
-                })?;
-            Ok(result.unwrap())
-        } else {
-            Err(new_error("db delete key not found!"))
+    async fn batch(&self, batch: Batch<Self::Key>) -> Result<()> {
+        let mut sled_batch = sled::Batch::default();
+        for op in batch.ops {
+            match op {
+                BatchOp::Write(k, v) => sled_batch.insert(k, v),
+                BatchOp::Delete(k) => sled_batch.remove(k),
+            }
         }
+        self.blocking(move |tree| {
+            tree.apply_batch(sled_batch)
+                .map_err(|_e| new_io_error("db batch failure!"))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn watch(&self, prefix: &Self::Key) -> Result<Receiver<StorageEvent<Self::Key>>> {
+        let (sender, receiver) = mpsc::channel(128);
+        let mut subscriber = self.tree.watch_prefix(prefix);
+
+        // sled's subscriber is also a future, forward it until receiver closed.
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = &mut subscriber => match event {
+                        Some(sled::Event::Insert { key, .. }) => StorageEvent::Insert(key.to_vec()),
+                        Some(sled::Event::Remove { key }) => StorageEvent::Remove(key.to_vec()),
+                        None => break,
+                    },
+                    _ = sender.closed() => break,
+                };
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.tree
+            .flush_async()
+            .await
+            .map_err(|_e| new_io_error("db flush failure!"))?;
+        Ok(())
     }
 }
 
 impl LocalDB {
     pub fn open_absolute(path: &PathBuf) -> Result<LocalDB> {
-        let tree = sled::open(path).map_err(|_e| new_error("db open failure!"))?;
+        let tree = sled::open(path).map_err(|_e| new_io_error("db open failure!"))?;
         Ok(LocalDB { tree })
     }
 
-    fn _flush(&self) -> Result<()> {
-        //smol::spawn(self.tree.flush_async());
-        //Ok(())
-        todo!();
+    /// sled blocks on the disk io, run it out of the async runtime.
+    async fn blocking<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(sled::Db) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        let tree = self.tree.clone();
+        tokio::task::spawn_blocking(move || f(tree))
+            .await
+            .map_err(|_e| new_io_error("db task failure!"))?
     }
 }
 
-#[test]
-fn test_db_file() {
+#[tokio::test]
+async fn test_db_file() {
     let name = "test_db";
     let key = vec![1, 2, 3, 4];
     let value = "A".to_owned();
     let value_b = "B".to_owned();
 
     let db = open_db(name).unwrap();
-    assert_eq!(db.write(&key, &value).ok(), Some(()));
-    assert_eq!(db.read::<String>(&key), Some(value.clone()));
-    assert_eq!(db.update::<String>(&key, &value_b).ok(), Some(()));
-    assert_eq!(db.read::<String>(&key), Some(value_b.clone()));
-    assert_eq!(db.delete::<String>(&key).ok(), Some(value_b));
-    assert_eq!(db.read::<String>(&key), None);
+    assert_eq!(db.write(&key, &value).await.ok(), Some(()));
+    assert_eq!(db.read::<String>(&key).await, Some(value.clone()));
+    assert_eq!(db.update::<String>(&key, &value_b).await.ok(), Some(()));
+    assert_eq!(db.read::<String>(&key).await, Some(value_b.clone()));
+    assert_eq!(db.delete::<String>(&key).await.ok(), Some(value_b));
+    assert_eq!(db.read::<String>(&key).await, None);
+}
+
+#[tokio::test]
+async fn test_db_scan() {
+    let path = PathBuf::from("./.test_db_scan");
+    let _ = std::fs::remove_dir_all(&path);
+    let db = LocalDB::open_absolute(&path).unwrap();
+    let mut watcher = db.watch(&vec![1]).await.unwrap();
+
+    let mut batch = Batch::new();
+    batch.write(vec![1, 1], &1u32).unwrap();
+    batch.write(vec![1, 2], &2u32).unwrap();
+    batch.write(vec![1, 3], &3u32).unwrap();
+    batch.write(vec![2, 1], &4u32).unwrap();
+    db.batch(batch).await.unwrap();
+    db.flush().await.unwrap();
+
+    let page = db.scan_prefix::<u32>(&vec![1], None, 10).await.unwrap();
+    assert_eq!(page.items.len(), 3);
+    assert_eq!(page.items[0], (vec![1, 1], 1));
+    assert_eq!(page.next, None);
+
+    // scan by pages.
+    let page = db.scan_prefix::<u32>(&vec![1], None, 2).await.unwrap();
+    assert_eq!(page.items, vec![(vec![1, 1], 1), (vec![1, 2], 2)]);
+    assert_eq!(page.next, Some(vec![1, 3]));
+    let page = db
+        .scan_prefix::<u32>(&vec![1], page.next.as_ref(), 2)
+        .await
+        .unwrap();
+    assert_eq!(page.items, vec![(vec![1, 3], 3)]);
+    assert_eq!(page.next, None);
+
+    let page = db.range::<u32>(&vec![1, 2], &vec![2, 1], 10).await.unwrap();
+    assert_eq!(page.items, vec![(vec![1, 2], 2), (vec![1, 3], 3)]);
+    let page = db.range::<u32>(&vec![1, 2], &vec![2, 1], 1).await.unwrap();
+    assert_eq!(page.items, vec![(vec![1, 2], 2)]);
+    assert_eq!(page.next, Some(vec![1, 3]));
+
+    // events in one batch are not ordered.
+    let mut events = vec![];
+    for _ in 0..3 {
+        events.push(watcher.recv().await.unwrap());
+    }
+    events.sort_by_key(|e| match e {
+        StorageEvent::Insert(k) | StorageEvent::Remove(k) => k.clone(),
+    });
+    assert_eq!(events[0], StorageEvent::Insert(vec![1, 1]));
+    assert_eq!(events[2], StorageEvent::Insert(vec![1, 3]));
+
+    let mut batch = Batch::new();
+    batch.delete(vec![1, 1]);
+    batch.delete(vec![1, 2]);
+    batch.delete(vec![1, 3]);
+    batch.delete(vec![2, 1]);
+    db.batch(batch).await.unwrap();
+    let page = db.scan_prefix::<u32>(&vec![], None, 10).await.unwrap();
+    assert_eq!(page.items.len(), 0);
+
+    drop(db);
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_db_membership() {
+    use tdn_types::group::Group;
+    use tdn_types::membership::{Membership, Permissionless};
+    use tdn_types::message::RecvType;
+    use tdn_types::primitives::{Peer, PeerId};
+
+    let path = PathBuf::from("./.test_db_membership");
+    let _ = std::fs::remove_dir_all(&path);
+    let db = LocalDB::open_absolute(&path).unwrap();
+    let a = Peer::peer(PeerId::from_hex("55fdd55633c578c7f2fb3e299f3d3bc88f8a9908").unwrap());
+    let b = Peer::peer(PeerId::from_hex("b9f86efea43016debe9436c2d98fa273789ee81b").unwrap());
+
+    let mut group = Membership::new(1, Permissionless);
+    group.handle(RecvType::Connect(a.clone(), vec![])).unwrap();
+    group.handle(RecvType::Connect(b.clone(), vec![])).unwrap();
+    group.handle(RecvType::Leave(a.clone())).unwrap();
+    group.persist(&db).await.unwrap();
+
+    let mut new_group = Membership::new(1, Permissionless);
+    new_group.load(&db).await.unwrap();
+    assert!(!new_group.is_member(&a.id));
+    assert!(new_group.is_member(&b.id));
+
+    group.remove(&b.id);
+    group.persist(&db).await.unwrap();
+    let page = db.scan_prefix::<String>(&vec![], None, 10).await.unwrap();
+    assert_eq!(page.items.len(), 0);
+
+    drop(db);
+    std::fs::remove_dir_all(path).unwrap();
 }
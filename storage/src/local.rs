@@ -1,6 +1,10 @@
-use rusqlite::{params, types::Value, Connection};
+use rusqlite::{
+    params, params_from_iter,
+    types::{ToSqlOutput, Value, ValueRef},
+    Connection, ToSql, Transaction,
+};
 use std::path::PathBuf;
-use tdn_types::primitives::Result;
+use tdn_types::primitives::{new_io_error, Result};
 
 pub enum DsValue {
     Null,
@@ -61,6 +65,61 @@ impl DsValue {
     }
 }
 
+impl ToSql for DsValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            DsValue::Null => ValueRef::Null,
+            DsValue::Integer(i) => ValueRef::Integer(*i),
+            DsValue::Real(f) => ValueRef::Real(*f),
+            DsValue::Text(s) => ValueRef::Text(s.as_bytes()),
+            DsValue::Blob(v) => ValueRef::Blob(v),
+        };
+        Ok(ToSqlOutput::Borrowed(value))
+    }
+}
+
+impl From<i64> for DsValue {
+    fn from(i: i64) -> DsValue {
+        DsValue::Integer(i)
+    }
+}
+
+impl From<bool> for DsValue {
+    fn from(b: bool) -> DsValue {
+        DsValue::Integer(if b { 1 } else { 0 })
+    }
+}
+
+impl From<f64> for DsValue {
+    fn from(f: f64) -> DsValue {
+        DsValue::Real(f)
+    }
+}
+
+impl From<String> for DsValue {
+    fn from(s: String) -> DsValue {
+        DsValue::Text(s)
+    }
+}
+
+impl From<&str> for DsValue {
+    fn from(s: &str) -> DsValue {
+        DsValue::Text(s.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for DsValue {
+    fn from(v: Vec<u8>) -> DsValue {
+        DsValue::Blob(v)
+    }
+}
+
+impl<T: Into<DsValue>> From<Option<T>> for DsValue {
+    fn from(v: Option<T>) -> DsValue {
+        v.map(|v| v.into()).unwrap_or(DsValue::Null)
+    }
+}
+
 impl From<Value> for DsValue {
     fn from(value: Value) -> DsValue {
         match value {
@@ -73,6 +132,40 @@ impl From<Value> for DsValue {
     }
 }
 
+/// map a row (values ordered by the selected columns) into a struct.
+pub trait FromRow: Sized {
+    fn from_row(values: Vec<DsValue>) -> Result<Self>;
+}
+
+impl FromRow for Vec<DsValue> {
+    fn from_row(values: Vec<DsValue>) -> Result<Self> {
+        Ok(values)
+    }
+}
+
+/// execute the sql with bound params, statement is cached and reused.
+fn execute_with(connect: &Connection, sql: &str, params: &[DsValue]) -> Result<usize> {
+    let mut stmt = connect.prepare_cached(sql)?;
+    Ok(stmt.execute(params_from_iter(params.iter()))?)
+}
+
+/// query the sql with bound params, statement is cached and reused.
+fn query_with<T: FromRow>(connect: &Connection, sql: &str, params: &[DsValue]) -> Result<Vec<T>> {
+    let mut stmt = connect.prepare_cached(sql)?;
+    let mut rows = stmt.query(params_from_iter(params.iter()))?;
+
+    let mut items: Vec<T> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut values: Vec<DsValue> = Vec::new();
+        for i in 0..row.as_ref().column_count() {
+            values.push(row.get::<usize, Value>(i)?.into());
+        }
+        items.push(T::from_row(values)?);
+    }
+
+    Ok(items)
+}
+
 pub struct DStorage {
     connect: Connection,
 }
@@ -128,6 +221,111 @@ impl DStorage {
         self.execute(sql)
     }
 
+    /// execute the sql with bound params (`?1`, `?2`...), return changed rows.
+    #[inline]
+    pub fn execute_with(&self, sql: &str, params: &[DsValue]) -> Result<usize> {
+        execute_with(&self.connect, sql, params)
+    }
+
+    /// query the sql with bound params.
+    #[inline]
+    pub fn query_with(&self, sql: &str, params: &[DsValue]) -> Result<Vec<Vec<DsValue>>> {
+        query_with(&self.connect, sql, params)
+    }
+
+    /// query the sql with bound params, and map every row by `FromRow`.
+    #[inline]
+    pub fn query_as<T: FromRow>(&self, sql: &str, params: &[DsValue]) -> Result<Vec<T>> {
+        query_with(&self.connect, sql, params)
+    }
+
+    /// insert with bound params, return the insert id.
+    #[inline]
+    pub fn insert_with(&self, sql: &str, params: &[DsValue]) -> Result<i64> {
+        self.execute_with(sql, params)?;
+        Ok(self.connect.last_insert_rowid())
+    }
+
+    /// set the capacity of the prepared statements cache.
+    #[inline]
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        self.connect.set_prepared_statement_cache_capacity(capacity);
+    }
+
+    /// start a transaction, it will rollback when dropped without commit.
+    #[inline]
+    pub fn transaction(&mut self) -> Result<DTransaction<'_>> {
+        Ok(DTransaction {
+            tx: self.connect.transaction()?,
+        })
+    }
+
+    /// open the db and run the migrations, it will refuse the db which
+    /// schema version is newer than the migrations.
+    pub fn open_with_migrations(
+        path: PathBuf,
+        passwd: &str,
+        migrations: &Migrations,
+    ) -> Result<Self> {
+        let mut db = Self::open(path, passwd)?;
+        migrations.run(&mut db)?;
+        Ok(db)
+    }
+
+    /// check the passwd is right, wrong key cannot read the schema.
+    #[inline]
+    pub fn verify_key(&self) -> bool {
+        self.connect
+            .query_row("SELECT count(*) FROM sqlite_master", params![], |_| Ok(()))
+            .is_ok()
+    }
+
+    /// change the passwd of the db.
+    pub fn rekey(&self, new_passwd: &str) -> Result<()> {
+        if !self.verify_key() {
+            return Err(new_io_error("db key is invalid").into());
+        }
+        self.connect.pragma_update(None, "rekey", new_passwd)?;
+        Ok(())
+    }
+
+    /// export all data to a new db encrypted by new passwd.
+    pub fn export_encrypted(&self, path: PathBuf, new_passwd: &str) -> Result<()> {
+        self.sqlcipher_export(path, new_passwd)
+    }
+
+    /// export all data to a plaintext (not encrypted) db.
+    pub fn export(&self, path: PathBuf) -> Result<()> {
+        self.sqlcipher_export(path, "")
+    }
+
+    /// import the plaintext db (from `export`) to a new encrypted db.
+    pub fn import(plain_path: PathBuf, path: PathBuf, passwd: &str) -> Result<Self> {
+        let plain = Self::open(plain_path, "")?;
+        plain.export_encrypted(path.clone(), passwd)?;
+        plain.close()?;
+        Self::open(path, passwd)
+    }
+
+    fn sqlcipher_export(&self, path: PathBuf, passwd: &str) -> Result<()> {
+        let path = path
+            .to_str()
+            .ok_or(new_io_error("db export path is invalid"))?
+            .to_owned();
+        self.connect.execute(
+            "ATTACH DATABASE ?1 AS tdn_export KEY ?2",
+            params![path, passwd],
+        )?;
+        let res =
+            self.connect
+                .query_row("SELECT sqlcipher_export('tdn_export')", params![], |_| {
+                    Ok(())
+                });
+        self.connect
+            .execute("DETACH DATABASE tdn_export", params![])?;
+        Ok(res?)
+    }
+
     /// tmp use.
     #[inline]
     pub fn flush(&self) -> Result<()> {
@@ -144,6 +342,131 @@ impl DStorage {
     }
 }
 
+/// the transaction of DStorage.
+pub struct DTransaction<'a> {
+    tx: Transaction<'a>,
+}
+
+impl<'a> DTransaction<'a> {
+    #[inline]
+    pub fn execute_with(&self, sql: &str, params: &[DsValue]) -> Result<usize> {
+        execute_with(&self.tx, sql, params)
+    }
+
+    #[inline]
+    pub fn query_with(&self, sql: &str, params: &[DsValue]) -> Result<Vec<Vec<DsValue>>> {
+        query_with(&self.tx, sql, params)
+    }
+
+    #[inline]
+    pub fn query_as<T: FromRow>(&self, sql: &str, params: &[DsValue]) -> Result<Vec<T>> {
+        query_with(&self.tx, sql, params)
+    }
+
+    #[inline]
+    pub fn insert_with(&self, sql: &str, params: &[DsValue]) -> Result<i64> {
+        self.execute_with(sql, params)?;
+        Ok(self.tx.last_insert_rowid())
+    }
+
+    #[inline]
+    pub fn commit(self) -> Result<()> {
+        Ok(self.tx.commit()?)
+    }
+
+    #[inline]
+    pub fn rollback(self) -> Result<()> {
+        Ok(self.tx.rollback()?)
+    }
+}
+
+/// the table which saved the applied migration versions.
+const MIGRATIONS_TABLE: &str = "tdn_migrations";
+
+/// Ordered schema up-migrations.
+/// versions start from 1, and must be registered in increasing order.
+#[derive(Default)]
+pub struct Migrations {
+    migrations: Vec<(i64, String)>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register a migration, the sql can have multiple statements.
+    pub fn add(mut self, version: i64, sql: impl ToString) -> Self {
+        self.migrations.push((version, sql.to_string()));
+        self
+    }
+
+    /// the latest schema version which this binary knows.
+    pub fn latest(&self) -> i64 {
+        self.migrations.last().map(|(v, _)| *v).unwrap_or(0)
+    }
+
+    /// the schema version of the db, 0 if none migration applied.
+    /// it is read only, the migrations table is created by `run`.
+    pub fn version(db: &DStorage) -> Result<i64> {
+        let tables = db.query_with(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            &[MIGRATIONS_TABLE.into()],
+        )?;
+        if tables.is_empty() {
+            return Ok(0);
+        }
+
+        let mut matrix = db.query(&format!("SELECT MAX(version) FROM {}", MIGRATIONS_TABLE))?;
+        Ok(matrix
+            .pop()
+            .and_then(|mut values| values.pop())
+            .map(|v| v.as_i64())
+            .unwrap_or(0))
+    }
+
+    /// apply all pending migrations, every migration in its own transaction.
+    /// return the schema version after migrated.
+    pub fn run(&self, db: &mut DStorage) -> Result<i64> {
+        let mut last = 0;
+        for (version, _) in &self.migrations {
+            if *version <= last {
+                return Err(new_io_error("migrations version must be increasing from 1").into());
+            }
+            last = *version;
+        }
+
+        let current = Self::version(db)?;
+        if current > self.latest() {
+            return Err(new_io_error(&format!(
+                "db schema version {} is newer than supported {}",
+                current,
+                self.latest()
+            ))
+            .into());
+        }
+
+        db.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {}(version INTEGER PRIMARY KEY NOT NULL, applied_at INTEGER NOT NULL);",
+            MIGRATIONS_TABLE
+        ))?;
+        for (version, sql) in self.migrations.iter().filter(|(v, _)| *v > current) {
+            let tx = db.transaction()?;
+            tx.tx.execute_batch(sql)?;
+            tx.execute_with(
+                &format!(
+                    "INSERT INTO {} (version, applied_at) values (?1, strftime('%s', 'now'))",
+                    MIGRATIONS_TABLE
+                ),
+                &[(*version).into()],
+            )?;
+            tx.commit()?;
+        }
+
+        Ok(std::cmp::max(current, self.latest()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,4 +514,177 @@ mod tests {
 
         std::fs::remove_file("./test.db").unwrap();
     }
+
+    #[derive(Debug, PartialEq)]
+    struct User {
+        id: i64,
+        name: String,
+        age: i64,
+    }
+
+    impl FromRow for User {
+        fn from_row(mut values: Vec<DsValue>) -> Result<Self> {
+            Ok(User {
+                age: values.pop().unwrap().as_i64(),
+                name: values.pop().unwrap().as_string(),
+                id: values.pop().unwrap().as_i64(),
+            })
+        }
+    }
+
+    #[test]
+    fn params_local() {
+        let _ = std::fs::remove_file("./test_params.db");
+        let mut db = DStorage::open(PathBuf::from("./test_params.db"), "test123").unwrap();
+        db.execute("CREATE TABLE users(id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, name TEXT NOT NULL, age INTEGER NOT NULL);").unwrap();
+
+        let name = "sun'); DROP TABLE users; --";
+        let id = db
+            .insert_with(
+                "INSERT INTO users (name, age) values (?1, ?2)",
+                &[name.into(), 18.into()],
+            )
+            .unwrap();
+        let users: Vec<User> = db
+            .query_as(
+                "SELECT id, name, age FROM users WHERE id = ?1",
+                &[id.into()],
+            )
+            .unwrap();
+        assert_eq!(
+            users,
+            vec![User {
+                id,
+                name: name.to_owned(),
+                age: 18
+            }]
+        );
+
+        let tx = db.transaction().unwrap();
+        tx.execute_with(
+            "UPDATE users SET age = ?1 WHERE id = ?2",
+            &[20.into(), id.into()],
+        )
+        .unwrap();
+        tx.rollback().unwrap();
+        let users: Vec<User> = db
+            .query_as(
+                "SELECT id, name, age FROM users WHERE id = ?1",
+                &[id.into()],
+            )
+            .unwrap();
+        assert_eq!(users[0].age, 18);
+
+        let tx = db.transaction().unwrap();
+        tx.insert_with(
+            "INSERT INTO users (name, age) values (?1, ?2)",
+            &["moon".into(), 20.into()],
+        )
+        .unwrap();
+        tx.commit().unwrap();
+        let rows = db
+            .query_with("SELECT name FROM users WHERE age > ?1", &[10.into()])
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+
+        db.close().unwrap();
+        std::fs::remove_file("./test_params.db").unwrap();
+    }
+
+    #[test]
+    fn migrations_local() {
+        let _ = std::fs::remove_file("./test_migrations.db");
+        let path = PathBuf::from("./test_migrations.db");
+        let v1 = Migrations::new()
+            .add(1, "CREATE TABLE users(id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, name TEXT NOT NULL);");
+        let db = DStorage::open(path.clone(), "test123").unwrap();
+        assert_eq!(Migrations::version(&db).unwrap(), 0);
+        assert_eq!(db.query("SELECT name FROM sqlite_master").unwrap().len(), 0);
+        db.close().unwrap();
+        let db = DStorage::open_with_migrations(path.clone(), "test123", &v1).unwrap();
+        assert_eq!(Migrations::version(&db).unwrap(), 1);
+        db.close().unwrap();
+
+        // failure migration will rollback, and keep the version.
+        let bad = Migrations::new().add(1, "").add(
+            2,
+            "ALTER TABLE users ADD COLUMN age INTEGER NOT NULL DEFAULT 0; BAD SQL;",
+        );
+        assert!(DStorage::open_with_migrations(path.clone(), "test123", &bad).is_err());
+
+        let v2 = Migrations::new().add(1, "").add(
+            2,
+            "ALTER TABLE users ADD COLUMN age INTEGER NOT NULL DEFAULT 0;",
+        );
+        let db = DStorage::open_with_migrations(path.clone(), "test123", &v2).unwrap();
+        assert_eq!(Migrations::version(&db).unwrap(), 2);
+        db.insert_with(
+            "INSERT INTO users (name, age) values (?1, ?2)",
+            &["sun".into(), 18.into()],
+        )
+        .unwrap();
+        db.close().unwrap();
+
+        // newer schema db.
+        assert!(DStorage::open_with_migrations(path.clone(), "test123", &v1).is_err());
+        let unordered = Migrations::new().add(2, "").add(1, "");
+        assert!(DStorage::open_with_migrations(path, "test123", &unordered).is_err());
+
+        std::fs::remove_file("./test_migrations.db").unwrap();
+    }
+
+    #[test]
+    fn rekey_local() {
+        for f in [
+            "./test_rekey.db",
+            "./test_rekey_plain.db",
+            "./test_rekey_import.db",
+            "./test_rekey_export.db",
+        ] {
+            let _ = std::fs::remove_file(f);
+        }
+
+        let db = DStorage::open(PathBuf::from("./test_rekey.db"), "test123").unwrap();
+        db.execute("CREATE TABLE users(id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, name TEXT NOT NULL);").unwrap();
+        db.insert_with("INSERT INTO users (name) values (?1)", &["sun".into()])
+            .unwrap();
+        assert!(db.verify_key());
+        db.export(PathBuf::from("./test_rekey_plain.db")).unwrap();
+        db.rekey("test456").unwrap();
+        db.close().unwrap();
+
+        let db = DStorage::open(PathBuf::from("./test_rekey.db"), "test123").unwrap();
+        assert!(!db.verify_key());
+        assert!(db.rekey("test789").is_err());
+        db.close().unwrap();
+
+        let db = DStorage::open(PathBuf::from("./test_rekey.db"), "test456").unwrap();
+        assert!(db.verify_key());
+        db.export_encrypted(PathBuf::from("./test_rekey_export.db"), "test789")
+            .unwrap();
+        db.close().unwrap();
+
+        let db = DStorage::open(PathBuf::from("./test_rekey_export.db"), "test789").unwrap();
+        assert_eq!(db.query("SELECT name FROM users").unwrap().len(), 1);
+        db.close().unwrap();
+
+        let db = DStorage::import(
+            PathBuf::from("./test_rekey_plain.db"),
+            PathBuf::from("./test_rekey_import.db"),
+            "test000",
+        )
+        .unwrap();
+        let mut matrix = db.query("SELECT name FROM users").unwrap();
+        assert_eq!(matrix.pop().unwrap().pop().unwrap().as_str(), "sun");
+        db.close().unwrap();
+
+        for f in [
+            "./test_rekey.db",
+            "./test_rekey_plain.db",
+            "./test_rekey_import.db",
+            "./test_rekey_export.db",
+        ] {
+            std::fs::remove_file(f).unwrap();
+        }
+    }
 }
@@ -1,36 +1,42 @@
+use std::path::PathBuf;
 use tdn::prelude::*;
-//use tdn_permission::PermissionlessGroup;
+use tdn::types::group::Group;
+use tdn::types::membership::{Membership, Permissionless};
+use tdn::types::rpc::RpcHandler;
+
+/// the members join by the permissionless policy, and handle their events.
+struct PermissionlessGroup(Membership<Permissionless>);
+
+impl Group for PermissionlessGroup {
+    fn id(&self) -> &GroupId {
+        self.0.id()
+    }
+
+    fn guard(&self, addr: &PeerId) -> bool {
+        self.0.guard(addr)
+    }
+
+    fn handle(&mut self, msg: RecvType) -> Result<HandleResult> {
+        match msg {
+            RecvType::Event(peer_id, _data) if self.0.is_member(&peer_id) => {
+                println!("receive group event from {}", peer_id.short_show());
+                Ok(HandleResult::new())
+            }
+            msg => self.0.handle(msg),
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    //let mut group = PermissionlessGroup::default();
-    let (peer_addr, _send, mut out_recv) = start().await.unwrap();
+    let config = Config::load(PathBuf::from("./")).await;
+    let gid = config.group_ids.first().copied().unwrap_or_default();
+
+    let mut app = App::new(RpcHandler::new(()));
+    app.add_group(PermissionlessGroup(Membership::new(gid, Permissionless)));
+    let (peer_addr, shutdown) = app.start(config, None).await.unwrap();
     println!("Example: peer id: {}", peer_addr.short_show());
 
-    while let Some(message) = out_recv.recv().await {
-        match message {
-            ReceiveMessage::Group(msg) => match msg {
-                RecvType::Connect(_peer, _data) => {
-                    //group.join(peer, data, send.clone()).await;
-                }
-                RecvType::Result(..) => {
-                    //
-                }
-                RecvType::ResultConnect(_peer, _data) => {
-                    //group.join(peer, data, send.clone()).await;
-                }
-                RecvType::Leave(_peer) => {
-                    //group.leave(&peer);
-                }
-                RecvType::Event(peer_id, _data) => {
-                    println!("receive group event from {}", peer_id.short_show());
-                }
-                RecvType::Stream(..) => {
-                    //
-                }
-                _ => {}
-            },
-            _ => {}
-        }
-    }
+    let _ = tokio::signal::ctrl_c().await;
+    let _ = shutdown.shutdown().await;
 }
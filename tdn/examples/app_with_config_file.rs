@@ -7,7 +7,7 @@ async fn main() {
     let dir_path = PathBuf::from(".");
     let config = Config::load(dir_path).await;
 
-    let (peer_addr, _send, mut out_recv) = start_with_config(config).await.unwrap();
+    let (peer_addr, _send, mut out_recv, _shutdown) = start_with_config(config).await.unwrap();
     println!("Example: peer id: {}", peer_addr.short_show());
 
     while let Some(message) = out_recv.recv().await {
@@ -1,93 +1,77 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 use tdn::prelude::*;
+use tdn::types::group::Group;
 use tdn::types::rpc::{json, RpcError, RpcHandler, RpcParam};
 
 struct State(u32);
 
+/// the group of the app, `App` dispatches its results to TDN.
+struct SimpleGroup(GroupId);
+
+impl Group for SimpleGroup {
+    fn id(&self) -> &GroupId {
+        &self.0
+    }
+
+    fn guard(&self, _addr: &PeerId) -> bool {
+        true // permissionless.
+    }
+
+    fn handle(&mut self, msg: RecvType) -> Result<HandleResult> {
+        match msg {
+            RecvType::Connect(peer, _data) => {
+                println!("receive group peer {} join", peer.id.short_show());
+                // agree the connection, the unified result is same in all modes.
+                let result = SendType::Result(0, peer, true, false, vec![]);
+                return Ok(unified::HandleResult::group(self.0, result).into_legacy());
+            }
+            RecvType::Leave(peer) => {
+                println!("receive group peer {} leave", peer.id.short_show());
+            }
+            RecvType::Event(peer_id, _data) => {
+                println!("receive group event from {}", peer_id.short_show());
+            }
+            _ => {}
+        }
+        Ok(HandleResult::new())
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let (peer_addr, send, mut out_recv) = start().await.unwrap();
-    println!("Example: peer id: {:?}", peer_addr);
+    let config = Config::load(PathBuf::from("./")).await;
+    let gid = config.group_ids.first().copied().unwrap_or_default();
 
     let mut rpc_handler = RpcHandler::new(State(1));
-    rpc_handler.add_method(
+    // the unified methods have the group id of the request in all modes.
+    rpc_handler.add_unified_method(
         "echo",
-        |params: Vec<RpcParam>, state: Arc<State>| async move {
+        |_gid, params: Vec<RpcParam>, state: Arc<State>| async move {
             let _value = params[0].as_str().ok_or(RpcError::ParseError)?;
             assert_eq!(1, state.0);
-            Ok(HandleResult::rpc(json!(params)))
+            Ok(unified::HandleResult::rpc(json!(params)))
         },
     );
 
-    rpc_handler.add_method("say_hello", |_params, state: Arc<State>| async move {
+    rpc_handler.add_unified_method("say_hello", |_gid, _params, state: Arc<State>| async move {
         assert_eq!(1, state.0);
-        Ok(HandleResult::rpc(json!("hello")))
+        Ok(unified::HandleResult::rpc(json!("hello")))
     });
 
-    while let Some(message) = out_recv.recv().await {
-        match message {
-            ReceiveMessage::Own(msg) => match msg {
-                RecvType::Connect(peer, _data) => {
-                    println!("receive own peer {} join", peer.id.short_show());
-                }
-                RecvType::Leave(peer) => {
-                    println!("receive own peer {} leave", peer.id.short_show());
-                }
-                RecvType::Event(peer_id, _data) => {
-                    println!("receive own event from {}", peer_id.short_show());
-                }
-                _ => {
-                    println!("nerver here!")
-                }
-            },
-            ReceiveMessage::Group(msg) => match msg {
-                RecvType::Connect(peer, _data) => {
-                    println!("receive group peer {} join", peer.id.short_show());
-                }
-                RecvType::Result(..) => {
-                    //
-                }
-                RecvType::Leave(peer) => {
-                    println!("receive group peer {} leave", peer.id.short_show());
-                }
-                RecvType::Event(peer_id, _data) => {
-                    println!("receive group event from {}", peer_id.short_show());
-                }
-                _ => {}
-            },
-            ReceiveMessage::Layer(fgid, _tgid, msg) => match msg {
-                RecvType::Connect(peer, _data) => {
-                    println!("Layer Join: {}, Addr: {}.", fgid, peer.id.short_show());
-                }
-                RecvType::Result(..) => {
-                    //
-                }
-                _ => {}
-            },
-            ReceiveMessage::Rpc(uid, params, is_ws) => {
-                if let Ok(HandleResult {
-                    mut rpcs,
-                    owns: _,
-                    groups: _,
-                    layers: _,
-                    networks: _,
-                }) = rpc_handler.handle(params).await
-                {
-                    loop {
-                        if rpcs.len() != 0 {
-                            let msg = rpcs.remove(0);
-                            send.send(SendMessage::Rpc(uid, msg, is_ws))
-                                .await
-                                .expect("TDN channel closed");
-                        } else {
-                            break;
-                        }
-                    }
-                }
-            }
-            ReceiveMessage::NetworkLost => {
-                println!("No network connections");
-            }
-        }
+    // params can be `["tdn"]` or `{"name": "tdn"}`.
+    #[derive(serde::Deserialize)]
+    struct Hello {
+        name: String,
     }
+    rpc_handler.add_unified_typed_method("hello", |_gid, params: Hello, _state| async move {
+        Ok(format!("hello {}", params.name))
+    });
+
+    let mut app = App::new(rpc_handler);
+    app.add_group(SimpleGroup(gid));
+    let (peer_id, shutdown) = app.start(config, None).await.unwrap();
+    println!("Example: peer id: {:?}", peer_id);
+
+    let _ = tokio::signal::ctrl_c().await;
+    let _ = shutdown.shutdown().await;
 }
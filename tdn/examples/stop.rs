@@ -4,13 +4,16 @@ use tdn::prelude::*;
 #[tokio::main]
 async fn main() {
     let config = Config::load(PathBuf::from("./")).await;
-    let (_secret, ids, p2p_config, _rpc_config) = config.split();
+    let (_secret, ids, p2p_config, _rpc_config) = config.split().unwrap();
     let (send_send, send_recv) = new_send_channel();
-    let (recv_send, mut recv_recv) = new_receive_channel();
+    let (recv_send, _recv_recv) = new_receive_channel();
+    let shutdown = Shutdown::new();
 
-    let peer_id = start_main(ids, p2p_config, recv_send, send_recv, None, None)
-        .await
-        .unwrap();
+    let peer_id = start_main(
+        ids, p2p_config, recv_send, send_recv, None, None, None, &shutdown,
+    )
+    .await
+    .unwrap();
     println!("Example: peer id: {:?}", peer_id);
 
     println!("Network will stop after 5s...");
@@ -20,9 +23,8 @@ async fn main() {
         .send(SendMessage::Network(NetworkType::NetworkStop))
         .await;
 
-    while let Some(_) = recv_recv.recv().await {
-        //
-    }
+    // or `shutdown.shutdown()` without the message.
+    shutdown.wait().await;
 
     println!("Network is stopped.");
 }
@@ -0,0 +1,249 @@
+//! the application runtime: drive the received messages into the groups,
+//! layer and rpc handlers, and dispatch their `HandleResult`s to TDN.
+
+use futures_util::FutureExt;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use tdn_types::{
+    group::{Group, GroupId},
+    layer::Layer,
+    message::{RecvType, SendType},
+    primitives::{PeerId, PeerKey, Result},
+    rpc::RpcHandler,
+    unified::{HandleResult, ReceiveMessage, SendMessage},
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::config::Config;
+use crate::prelude::start_unified_with_config;
+use crate::shutdown::Shutdown;
+
+/// the application of TDN, include groups, layer and rpc handlers.
+/// a handler's error or panic is logged, and the app keeps running.
+pub struct App<S: 'static + Send + Sync> {
+    groups: HashMap<GroupId, Box<dyn Group + Send>>,
+    default_group: Option<GroupId>,
+    layer: Option<Box<dyn Layer + Send>>,
+    rpc: Arc<RpcHandler<S>>,
+}
+
+impl<S: 'static + Send + Sync> App<S> {
+    pub fn new(rpc: RpcHandler<S>) -> Self {
+        App {
+            groups: HashMap::new(),
+            default_group: None,
+            layer: None,
+            rpc: Arc::new(rpc),
+        }
+    }
+
+    /// add the group handler, the first one is the group of rpc requests without `gid`.
+    pub fn add_group<G: Group + Send + 'static>(&mut self, group: G) {
+        let gid = *group.id();
+        self.default_group.get_or_insert(gid);
+        self.groups.insert(gid, Box::new(group));
+    }
+
+    /// set the layer handler, if not set, the layer messages are dropped.
+    pub fn set_layer<L: Layer + Send + 'static>(&mut self, layer: L) {
+        self.layer = Some(Box::new(layer));
+    }
+
+    /// start TDN with config, and run the app on it.
+    pub async fn start(self, config: Config, key: Option<PeerKey>) -> Result<(PeerId, Shutdown)> {
+        let (peer_id, send, recv, shutdown) = start_unified_with_config(config, key).await?;
+        tokio::spawn(self.run(send, recv));
+        Ok((peer_id, shutdown))
+    }
+
+    /// run the app on the unified channels, until the receiver closed.
+    pub async fn run(mut self, send: Sender<SendMessage>, mut recv: Receiver<ReceiveMessage>) {
+        while let Some(message) = recv.recv().await {
+            match message {
+                ReceiveMessage::Group(gid, msg) => {
+                    let group = match self.groups.get_mut(&gid) {
+                        Some(group) => group,
+                        None => {
+                            debug!("App has no group {}, drop the message", gid);
+                            continue;
+                        }
+                    };
+                    let peer_id = match &msg {
+                        RecvType::Connect(peer, _) => Some(peer.id),
+                        RecvType::Event(peer_id, _) => Some(*peer_id),
+                        _ => None,
+                    };
+                    if let Some(peer_id) = peer_id.filter(|p| !group.guard(p)) {
+                        debug!("Group {} guard reject {}", gid, peer_id.short_show());
+                        if let RecvType::Connect(peer, _) = msg {
+                            let reject = SendType::Result(0, peer, false, false, vec![]);
+                            dispatch(&send, HandleResult::group(gid, reject), 0, true).await;
+                        }
+                        continue;
+                    }
+
+                    match catch_unwind(AssertUnwindSafe(|| group.handle(msg))) {
+                        Ok(Ok(result)) => {
+                            let result = HandleResult::from_legacy(result, gid);
+                            dispatch(&send, result, 0, true).await;
+                        }
+                        Ok(Err(e)) => error!("Group {} handle: {:?}", gid, e),
+                        Err(_) => error!("Group {} handler panicked", gid),
+                    }
+                }
+                ReceiveMessage::Layer(fgid, tgid, msg) => {
+                    let layer = match self.layer.as_mut() {
+                        Some(layer) => layer,
+                        None => {
+                            debug!("App has no layer, drop the message");
+                            continue;
+                        }
+                    };
+
+                    match catch_unwind(AssertUnwindSafe(|| layer.handle(fgid, tgid, msg))) {
+                        Ok(Ok(result)) => {
+                            let result = HandleResult::from_legacy(result, tgid);
+                            dispatch(&send, result, 0, true).await;
+                        }
+                        Ok(Err(e)) => error!("Layer {} -> {} handle: {:?}", fgid, tgid, e),
+                        Err(_) => error!("Layer {} -> {} handler panicked", fgid, tgid),
+                    }
+                }
+                ReceiveMessage::Rpc(uid, params, is_ws) => {
+                    let rpc = self.rpc.clone();
+                    let send = send.clone();
+                    let gid = self.default_group.unwrap_or_default();
+                    tokio::spawn(async move {
+                        let handle = AssertUnwindSafe(rpc.handle_unified(params, gid));
+                        match handle.catch_unwind().await {
+                            Ok(Ok(result)) => dispatch(&send, result, uid, is_ws).await,
+                            Ok(Err(e)) => error!("Rpc {} handle: {:?}", uid, e),
+                            Err(_) => error!("Rpc {} handler panicked", uid),
+                        }
+                    });
+                }
+                ReceiveMessage::Own(msg) => {
+                    debug!("App not handle own message: {:?}", msg);
+                }
+                ReceiveMessage::NetworkLost => {
+                    warn!("No network connections");
+                }
+            }
+        }
+    }
+}
+
+/// send all the tasks of the result to TDN, rpcs are sended to the
+/// connection `uid`, the group/layer's are sended to all ws (uid is 0).
+pub async fn dispatch(send: &Sender<SendMessage>, result: HandleResult, uid: u64, is_ws: bool) {
+    let HandleResult {
+        owns,
+        rpcs,
+        groups,
+        layers,
+        networks,
+    } = result;
+
+    let messages = owns
+        .into_iter()
+        .map(SendMessage::Own)
+        .chain(rpcs.into_iter().map(|p| SendMessage::Rpc(uid, p, is_ws)))
+        .chain(
+            groups
+                .into_iter()
+                .map(|(gid, m)| SendMessage::Group(gid, m)),
+        )
+        .chain(
+            layers
+                .into_iter()
+                .map(|(fgid, tgid, m)| SendMessage::Layer(fgid, tgid, m)),
+        )
+        .chain(networks.into_iter().map(SendMessage::Network));
+
+    for message in messages {
+        if let Err(e) = send.send(message).await {
+            error!("Outside channel: {:?}", e);
+            break;
+        }
+    }
+}
+
+#[cfg(all(test, tdn_mode = "std"))]
+mod tests {
+    use super::*;
+    use tdn_types::primitives::{HandleResult as LegacyResult, Peer};
+    use tdn_types::rpc::json;
+    use tokio::sync::mpsc;
+
+    /// echo the event, panic when it is empty.
+    struct EchoGroup;
+
+    impl Group for EchoGroup {
+        fn id(&self) -> &GroupId {
+            &1
+        }
+
+        fn guard(&self, addr: &PeerId) -> bool {
+            addr == &PeerId::default()
+        }
+
+        fn handle(&mut self, msg: RecvType) -> Result<LegacyResult> {
+            match msg {
+                RecvType::Event(peer_id, data) => {
+                    assert!(!data.is_empty());
+                    Ok(LegacyResult::group(SendType::Event(0, peer_id, data)))
+                }
+                _ => Ok(LegacyResult::new()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app() {
+        let mut rpc = RpcHandler::new(());
+        rpc.add_method("echo", |params, _| async move {
+            Ok(LegacyResult::rpc(json!(params)))
+        });
+        let mut app = App::new(rpc);
+        app.add_group(EchoGroup);
+
+        let (in_send, in_recv) = mpsc::channel(8);
+        let (out_send, mut out_recv) = mpsc::channel(8);
+        tokio::spawn(app.run(out_send, in_recv));
+
+        let peer_id = PeerId::default();
+        let event = |data| ReceiveMessage::Group(1, RecvType::Event(peer_id, data));
+        in_send.send(event(vec![])).await.unwrap();
+        in_send.send(event(vec![1])).await.unwrap();
+        match out_recv.recv().await.unwrap() {
+            SendMessage::Group(1, SendType::Event(_, p, data)) => {
+                assert_eq!((p, data), (peer_id, vec![1]));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        let stranger = PeerId::from_hex("55fdd55633c578c7f2fb3e299f3d3bc88f8a9908").unwrap();
+        let connect = RecvType::Connect(Peer::peer(stranger), vec![]);
+        in_send
+            .send(ReceiveMessage::Group(1, connect))
+            .await
+            .unwrap();
+        match out_recv.recv().await.unwrap() {
+            SendMessage::Group(1, SendType::Result(_, p, is_ok, _, _)) => {
+                assert!(p.id == stranger && !is_ok);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        let params = json!({"jsonrpc": "2.0", "id": 1, "method": "echo", "params": [1]});
+        in_send
+            .send(ReceiveMessage::Rpc(7, params, false))
+            .await
+            .unwrap();
+        match out_recv.recv().await.unwrap() {
+            SendMessage::Rpc(7, _, false) => {}
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+}
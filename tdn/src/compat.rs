@@ -0,0 +1,67 @@
+use tdn_types::{
+    group::GroupId,
+    message::{ReceiveMessage, SendMessage},
+    unified,
+};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::prelude::{Service, UnifiedService};
+
+/// forward the legacy messages to the unified channel,
+/// `gid` is the group of single and std.
+pub(crate) fn send_shim(
+    legacy_recv: Receiver<SendMessage>,
+    gid: GroupId,
+) -> Receiver<unified::SendMessage> {
+    let (send, recv) = mpsc::channel(1024);
+    forward_send(legacy_recv, send, gid);
+    recv
+}
+
+/// forward the unified messages to the legacy channel,
+/// the messages which the legacy not has are dropped.
+pub(crate) fn recv_shim(legacy_send: Sender<ReceiveMessage>) -> Sender<unified::ReceiveMessage> {
+    let (send, recv) = mpsc::channel(1024);
+    forward_recv(recv, legacy_send);
+    send
+}
+
+/// the legacy service of the unified service.
+pub(crate) fn legacy_service(service: UnifiedService, gid: GroupId) -> Service {
+    let (peer_id, unified_send, unified_recv, shutdown) = service;
+    let (send, legacy_recv) = mpsc::channel(1024);
+    let (legacy_send, recv) = mpsc::channel(1024);
+    forward_send(legacy_recv, unified_send, gid);
+    forward_recv(unified_recv, legacy_send);
+    (peer_id, send, recv, shutdown)
+}
+
+fn forward_send(
+    mut legacy_recv: Receiver<SendMessage>,
+    unified_send: Sender<unified::SendMessage>,
+    gid: GroupId,
+) {
+    tokio::spawn(async move {
+        while let Some(msg) = legacy_recv.recv().await {
+            let msg = unified::SendMessage::from_legacy(msg, gid);
+            if unified_send.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn forward_recv(
+    mut unified_recv: Receiver<unified::ReceiveMessage>,
+    legacy_send: Sender<ReceiveMessage>,
+) {
+    tokio::spawn(async move {
+        while let Some(msg) = unified_recv.recv().await {
+            if let Some(msg) = msg.into_legacy() {
+                if legacy_send.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
@@ -15,15 +15,20 @@ use tokio::{
 use chamomile::prelude::Config as P2pConfig;
 use tdn_types::{
     group::GroupId,
-    primitives::{Peer, PeerId, Result, CONFIG_FILE_NAME, DEFAULT_SECRET, P2P_ADDR, RPC_HTTP},
+    primitives::{
+        new_io_error, Peer, PeerId, Result, CONFIG_FILE_NAME, DEFAULT_SECRET, P2P_ADDR, RPC_HTTP,
+    },
+    unified::Mode,
 };
 
-use crate::rpc::{ChannelAddr, RpcConfig};
+use crate::rpc::{ChannelAddr, RateLimit, RpcAuth, RpcConfig, RpcLimits, RpcTlsConfig};
 
 /// load config from config file.
 pub struct Config {
     pub db_path: Option<PathBuf>,
     pub secret: [u8; 32],
+    /// run-time mode: single, std, multiple, full. default is the features'.
+    pub mode: Mode,
     pub group_ids: Vec<GroupId>,
     pub permission: bool,
     pub only_stable_data: bool,
@@ -38,15 +43,29 @@ pub struct Config {
     pub rpc_ws: Option<SocketAddr>,
     pub rpc_channel: Option<ChannelAddr>,
     pub rpc_index: Option<PathBuf>,
+    pub rpc_static: Option<PathBuf>,
+    /// rpc authentication: "none", "bearer" or "hmac", key derived from the secret.
+    /// others are invalid, and bearer/hmac need a secret which is not default.
+    pub rpc_auth: Option<String>,
+    pub rpc_tls_cert: Option<PathBuf>,
+    pub rpc_tls_key: Option<PathBuf>,
+    pub rpc_tls_client_ca: Option<PathBuf>,
+    /// rpc max concurrent HTTP and WS connections.
+    pub rpc_max_connections: Option<usize>,
+    /// rpc requests per second of every IP.
+    pub rpc_rate_limit: Option<u32>,
 }
 
 impl Config {
-    pub fn split(self) -> ([u8; 32], Vec<GroupId>, P2pConfig, RpcConfig) {
-        let delivery_length = tdn_types::group::GROUP_BYTES_LENGTH * 2;
+    /// split to p2p & rpc config, the invalid rpc auth is an error.
+    pub fn split(self) -> Result<([u8; 32], Vec<GroupId>, P2pConfig, RpcConfig)> {
+        // keep the versioned frame header, the legacy one is shorter.
+        let delivery_length = tdn_types::frame::FRAME_HEADER_LENGTH;
 
         let Config {
             db_path,
             secret,
+            mode: _,
             group_ids,
 
             permission,
@@ -61,8 +80,28 @@ impl Config {
             rpc_ws,
             rpc_channel,
             rpc_index,
+            rpc_static,
+            rpc_auth,
+            rpc_tls_cert,
+            rpc_tls_key,
+            rpc_tls_client_ca,
+            rpc_max_connections,
+            rpc_rate_limit,
         } = self;
 
+        let auth = match rpc_auth.as_deref() {
+            None | Some("none") => RpcAuth::None,
+            Some("bearer") | Some("hmac") if secret == DEFAULT_SECRET => {
+                return Err(new_io_error("rpc_auth need a secret, not the default").into());
+            }
+            Some("bearer") => RpcAuth::bearer(&secret),
+            Some("hmac") => RpcAuth::hmac(&secret),
+            Some(auth) => {
+                let info = format!("rpc_auth {:?} is invalid, use none, bearer or hmac", auth);
+                return Err(new_io_error(&info).into());
+            }
+        };
+
         let p2p_config = P2pConfig {
             db_dir: if let Some(path) = db_path {
                 path
@@ -84,9 +123,25 @@ impl Config {
             ws: rpc_ws,
             channel: rpc_channel,
             index: rpc_index,
+            static_dir: rpc_static,
+            auth,
+            tls: match (rpc_tls_cert, rpc_tls_key) {
+                (Some(cert), Some(key)) => Some(RpcTlsConfig {
+                    cert,
+                    key,
+                    client_ca: rpc_tls_client_ca,
+                }),
+                _ => None,
+            },
+            limits: RpcLimits {
+                max_connections: rpc_max_connections,
+                ip_rate: rpc_rate_limit.map(|rate| RateLimit::new(rate, rate)),
+                ..Default::default()
+            },
+            ..Default::default()
         };
 
-        (secret, group_ids, p2p_config, rpc_config)
+        Ok((secret, group_ids, p2p_config, rpc_config))
     }
 }
 
@@ -95,6 +150,7 @@ impl Config {
         Config {
             db_path: None,
             secret: DEFAULT_SECRET,
+            mode: Mode::default(),
             group_ids: vec![GroupId::default()],
             permission: false,       // default is permissionless
             only_stable_data: false, // default is permissionless
@@ -108,6 +164,13 @@ impl Config {
             rpc_ws: None,
             rpc_channel: None,
             rpc_index: None,
+            rpc_static: None,
+            rpc_auth: None,
+            rpc_tls_cert: None,
+            rpc_tls_key: None,
+            rpc_tls_client_ca: None,
+            rpc_max_connections: None,
+            rpc_rate_limit: None,
         }
     }
 
@@ -178,6 +241,7 @@ pub struct RawUpper {
 pub struct RawConfig {
     pub db_path: Option<PathBuf>,
     pub secret: String,
+    pub mode: Option<Mode>,
     pub group_id: Option<GroupId>,
     pub permission: Option<bool>,
     pub only_stable_data: Option<bool>,
@@ -192,6 +256,13 @@ pub struct RawConfig {
     pub rpc_http: Option<SocketAddr>,
     pub rpc_ws: Option<SocketAddr>,
     pub rpc_index: Option<PathBuf>,
+    pub rpc_static: Option<PathBuf>,
+    pub rpc_auth: Option<String>,
+    pub rpc_tls_cert: Option<PathBuf>,
+    pub rpc_tls_key: Option<PathBuf>,
+    pub rpc_tls_client_ca: Option<PathBuf>,
+    pub rpc_max_connections: Option<usize>,
+    pub rpc_rate_limit: Option<u32>,
 }
 
 impl RawConfig {
@@ -199,6 +270,7 @@ impl RawConfig {
         Config {
             db_path: self.db_path,
             secret: *blake3::hash(self.secret.as_bytes()).as_bytes(),
+            mode: self.mode.unwrap_or_default(),
             group_ids: if let Some(g) = self.group_id {
                 vec![g]
             } else {
@@ -240,6 +312,13 @@ impl RawConfig {
             rpc_ws: self.rpc_ws,
             rpc_channel: None,
             rpc_index: self.rpc_index,
+            rpc_static: self.rpc_static,
+            rpc_auth: self.rpc_auth,
+            rpc_tls_cert: self.rpc_tls_cert,
+            rpc_tls_key: self.rpc_tls_key,
+            rpc_tls_client_ca: self.rpc_tls_client_ca,
+            rpc_max_connections: self.rpc_max_connections,
+            rpc_rate_limit: self.rpc_rate_limit,
         }
     }
 }
@@ -262,6 +341,15 @@ group_id = {}
         }
     );
 
+    let mode_str = format!(
+        r#"## TDN mode: single, std, multiple, full. default is the cargo features'.
+## single & std have only one group, std & full have layers.
+## Example: mode = "full"
+mode = "{}"
+"#,
+        config.mode.to_str()
+    );
+
     let secret_str = format!(
         r#"## This will be random string, and you can change.
 ## if need use secret nonce or seed, it will be useful.
@@ -427,6 +515,106 @@ rpc_index = {:?}
         ),
     };
 
+    let rpc_static_str = match &config.rpc_static {
+        Some(path) => format!(
+            r#"## RPC Service static assets directory, served by HTTP GET. if has, set path, if not, comment it.
+## Example: rpc_static = "/var/www/html/"
+rpc_static = {:?}
+"#,
+            path
+        ),
+        None => format!(
+            r#"## RPC Service static assets directory, served by HTTP GET. if has, set path, if not, comment it.
+## Example: rpc_static = "/var/www/html/"
+#rpc_static = ""
+"#
+        ),
+    };
+
+    let rpc_auth_str = match &config.rpc_auth {
+        Some(auth) => format!(
+            r#"## RPC Service authentication, "bearer" or "hmac", the key is derived from secret. if not, comment it.
+## Example: rpc_auth = "bearer"
+rpc_auth = {:?}
+"#,
+            auth
+        ),
+        None => format!(
+            r#"## RPC Service authentication, "bearer" or "hmac", the key is derived from secret. if not, comment it.
+## Example: rpc_auth = "bearer"
+#rpc_auth = ""
+"#
+        ),
+    };
+
+    let rpc_tls_str = match (&config.rpc_tls_cert, &config.rpc_tls_key) {
+        (Some(cert), Some(key)) => format!(
+            r#"## RPC Service TLS (HTTPS and WSS), PEM certificate chain and private key. if not, comment it.
+## Example: rpc_tls_cert = "/etc/tdn/cert.pem"
+## Example: rpc_tls_key = "/etc/tdn/key.pem"
+rpc_tls_cert = {:?}
+rpc_tls_key = {:?}
+"#,
+            cert, key
+        ),
+        _ => format!(
+            r#"## RPC Service TLS (HTTPS and WSS), PEM certificate chain and private key. if not, comment it.
+## Example: rpc_tls_cert = "/etc/tdn/cert.pem"
+## Example: rpc_tls_key = "/etc/tdn/key.pem"
+#rpc_tls_cert = ""
+#rpc_tls_key = ""
+"#
+        ),
+    };
+
+    let rpc_tls_client_ca_str = match &config.rpc_tls_client_ca {
+        Some(path) => format!(
+            r#"## RPC Service TLS client CA, if has, clients must have certificate signed by it (mTLS).
+## Example: rpc_tls_client_ca = "/etc/tdn/ca.pem"
+rpc_tls_client_ca = {:?}
+"#,
+            path
+        ),
+        None => format!(
+            r#"## RPC Service TLS client CA, if has, clients must have certificate signed by it (mTLS).
+## Example: rpc_tls_client_ca = "/etc/tdn/ca.pem"
+#rpc_tls_client_ca = ""
+"#
+        ),
+    };
+
+    let rpc_max_connections_str = match &config.rpc_max_connections {
+        Some(max) => format!(
+            r#"## RPC Service max concurrent HTTP and WS connections, default is unlimited.
+## Example: rpc_max_connections = 1024
+rpc_max_connections = {}
+"#,
+            max
+        ),
+        None => format!(
+            r#"## RPC Service max concurrent HTTP and WS connections, default is unlimited.
+## Example: rpc_max_connections = 1024
+#rpc_max_connections = 1024
+"#
+        ),
+    };
+
+    let rpc_rate_limit_str = match &config.rpc_rate_limit {
+        Some(rate) => format!(
+            r#"## RPC Service requests per second of every IP, default is unlimited.
+## Example: rpc_rate_limit = 100
+rpc_rate_limit = {}
+"#,
+            rate
+        ),
+        None => format!(
+            r#"## RPC Service requests per second of every IP, default is unlimited.
+## Example: rpc_rate_limit = 100
+#rpc_rate_limit = 100
+"#
+        ),
+    };
+
     format!(
         r#"## TDN Configure.
 {}
@@ -443,7 +631,15 @@ rpc_index = {:?}
 {}
 {}
 {}
+{}
+{}
+{}
+{}
+{}
+{}
+{}
 "#,
+        mode_str,
         group_id_str,
         secret_str,
         db_path_str,
@@ -457,7 +653,13 @@ rpc_index = {:?}
         p2p_block_peer_str,
         rpc_http_str,
         rpc_ws_str,
-        rpc_index_str
+        rpc_index_str,
+        rpc_static_str,
+        rpc_auth_str,
+        rpc_tls_str,
+        rpc_tls_client_ca_str,
+        rpc_max_connections_str,
+        rpc_rate_limit_str
     )
 }
 
@@ -495,6 +697,12 @@ mod tests {
             ];
             config.rpc_ws = Some("127.0.0.1:8001".parse().unwrap());
             config.rpc_index = Some(PathBuf::from("/var/www/html/index.html"));
+            config.rpc_static = Some(PathBuf::from("/var/www/html/"));
+            config.rpc_auth = Some("bearer".to_owned());
+            config.rpc_tls_cert = Some(PathBuf::from("/etc/tdn/cert.pem"));
+            config.rpc_tls_key = Some(PathBuf::from("/etc/tdn/key.pem"));
+            config.rpc_rate_limit = Some(100);
+            config.mode = Mode::Full;
 
             let config = Config::load_save(path.clone(), config).await.unwrap();
             let new_config = Config::load_save(path.clone(), config).await.unwrap();
@@ -510,6 +718,16 @@ mod tests {
             );
             assert!(new_config.rpc_ws.is_some());
             assert!(new_config.rpc_index.is_some());
+            assert_eq!(new_config.rpc_static, Some(PathBuf::from("/var/www/html/")));
+            assert_eq!(new_config.rpc_auth, Some("bearer".to_owned()));
+            assert_eq!(
+                new_config.rpc_tls_key,
+                Some(PathBuf::from("/etc/tdn/key.pem"))
+            );
+            assert!(new_config.rpc_tls_client_ca.is_none());
+            assert!(new_config.rpc_max_connections.is_none());
+            assert_eq!(new_config.rpc_rate_limit, Some(100));
+            assert_eq!(new_config.mode, Mode::Full);
             std::fs::remove_dir_all(path).unwrap();
         });
     }
@@ -541,4 +759,20 @@ info = "{}"
             std::fs::remove_dir_all(path).unwrap();
         });
     }
+
+    #[test]
+    fn test_config_auth() {
+        let with_auth = |secret, auth: &str| {
+            let mut config = Config::default();
+            config.secret = secret;
+            config.rpc_auth = Some(auth.to_owned());
+            config.split()
+        };
+        assert!(with_auth([1; 32], "bearer").is_ok());
+        assert!(with_auth([1; 32], "none").is_ok());
+        assert!(with_auth([1; 32], "Bearer").is_err());
+        assert!(with_auth([1; 32], "hmac ").is_err());
+        assert!(with_auth(DEFAULT_SECRET, "hmac").is_err());
+        assert!(with_auth(DEFAULT_SECRET, "none").is_ok());
+    }
 }
@@ -0,0 +1,198 @@
+use chamomile::prelude::SendMessage;
+use std::collections::{HashMap, VecDeque};
+use tdn_types::{
+    frame::{Frame, FrameType, FRAME_VERSION},
+    group::GroupId,
+    primitives::PeerId,
+};
+use tokio::sync::{mpsc::Sender, Mutex, RwLock};
+
+/// the frame flags which can be handled now.
+const SUPPORTED_FLAGS: u8 = 0;
+
+/// the max recently sended frames which wait for the delivery.
+const DELIVERY_WINDOW: usize = 1024;
+
+/// the negotiated frame versions of peers. the peer which not sended hello
+/// (e.g. legacy TDN) will receive the legacy layout. stream has no peer in
+/// chamomile, so stream frames always use the legacy layout.
+#[derive(Default)]
+pub(crate) struct FrameVersions {
+    peers: RwLock<HashMap<PeerId, u8>>,
+    /// the versions of sended frames by tid, the delivery has no peer.
+    deliveries: Mutex<(HashMap<u64, u8>, VecDeque<u64>)>,
+}
+
+impl FrameVersions {
+    async fn version(&self, peer: &PeerId) -> u8 {
+        self.peers.read().await.get(peer).copied().unwrap_or(0)
+    }
+
+    /// the frame header of sending to the peer, and record its version for
+    /// the delivery of `tid`.
+    pub async fn frame(&self, peer: &PeerId, tid: u64, fgid: GroupId, tgid: GroupId) -> Frame {
+        let version = self.version(peer).await;
+        let mut deliveries = self.deliveries.lock().await;
+        let (versions, tids) = &mut *deliveries;
+        if versions.insert(tid, version).is_none() {
+            tids.push_back(tid);
+            if tids.len() > DELIVERY_WINDOW {
+                if let Some(tid) = tids.pop_front() {
+                    versions.remove(&tid);
+                }
+            }
+        }
+        Frame::new(version, fgid, tgid)
+    }
+
+    /// the frame header of stream, it is always legacy.
+    pub fn stream_frame(&self, fgid: GroupId, tgid: GroupId) -> Frame {
+        Frame::new(0, fgid, tgid)
+    }
+
+    /// send hello to the stable connected peer, if not negotiated.
+    pub async fn hello(&self, peer: &PeerId, p2p_send: &Sender<SendMessage>) {
+        if !self.peers.read().await.contains_key(peer) {
+            send_hello(peer, p2p_send).await;
+        }
+    }
+
+    /// decode the received frame by the recorded version of the peer, only
+    /// the hello changes the recorded version.
+    /// return the groups and data, none if it is hello or invalid.
+    pub async fn open(
+        &self,
+        peer: &PeerId,
+        p2p_send: &Sender<SendMessage>,
+        data: Vec<u8>,
+    ) -> Option<(GroupId, GroupId, Vec<u8>)> {
+        let version = self.version(peer).await;
+        let (frame, data) = Frame::from_bytes(version, data).ok()?;
+
+        if frame.ftype == FrameType::Hello {
+            let version = frame.version.min(FRAME_VERSION);
+            let old = self.peers.write().await.insert(*peer, version);
+            if old.is_none() {
+                // the peer maybe not know our version.
+                send_hello(peer, p2p_send).await;
+            }
+            return None;
+        }
+
+        check(frame, data)
+    }
+
+    /// decode the received stream frame, it is always legacy.
+    pub fn open_stream(&self, data: Vec<u8>) -> Option<(GroupId, GroupId, Vec<u8>)> {
+        let (frame, data) = Frame::from_bytes(0, data).ok()?;
+        check(frame, data)
+    }
+
+    /// decode the delivery of the sended frame, by the version when sended.
+    pub async fn open_delivery(&self, tid: u64, data: Vec<u8>) -> Option<(GroupId, GroupId)> {
+        let mut deliveries = self.deliveries.lock().await;
+        let (versions, tids) = &mut *deliveries;
+        let version = match versions.remove(&tid) {
+            Some(version) => {
+                tids.retain(|t| *t != tid);
+                version
+            }
+            None => 0,
+        };
+        drop(deliveries);
+
+        let (frame, data) = Frame::from_bytes(version, data).ok()?;
+        check(frame, data).map(|(fgid, tgid, _)| (fgid, tgid))
+    }
+
+    /// the peer leaved, it maybe come back with other version.
+    pub async fn leave(&self, peer: &PeerId) {
+        self.peers.write().await.remove(peer);
+    }
+}
+
+fn check(frame: Frame, data: Vec<u8>) -> Option<(GroupId, GroupId, Vec<u8>)> {
+    if frame.ftype == FrameType::Hello {
+        return None;
+    }
+    if frame.flags & !SUPPORTED_FLAGS != 0 {
+        warn!("Frame flags {:#010b} is not supported", frame.flags);
+        return None;
+    }
+
+    Some((frame.fgid, frame.tgid, data))
+}
+
+async fn send_hello(peer: &PeerId, p2p_send: &Sender<SendMessage>) {
+    let bytes = Frame::hello().to_bytes(vec![]);
+    let _ = p2p_send.send(SendMessage::Data(0, *peer, bytes)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tdn_types::frame::FLAG_ENCRYPTED;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_frame_versions() {
+        let versions = FrameVersions::default();
+        let (p2p_send, mut p2p_recv) = mpsc::channel(8);
+        let peer = PeerId::default();
+
+        // legacy peer, the versioned bytes in legacy fgid is not sniffed.
+        let bytes = Frame::new(0, 1, 2).to_bytes(vec![3]);
+        let res = versions.open(&peer, &p2p_send, bytes).await;
+        assert_eq!(res, Some((1, 2, vec![3])));
+        let bytes = Frame::new(FRAME_VERSION, 1, 2).to_bytes(vec![3]);
+        let res = versions.open(&peer, &p2p_send, bytes).await;
+        assert_ne!(res, Some((1, 2, vec![3])));
+        assert!(versions.frame(&peer, 1, 1, 2).await.is_legacy());
+
+        // negotiated by hello, and reply hello.
+        versions.hello(&peer, &p2p_send).await;
+        let hello = Frame::hello().to_bytes(vec![]);
+        assert!(versions
+            .open(&peer, &p2p_send, hello.clone())
+            .await
+            .is_none());
+        for _ in 0..2 {
+            match p2p_recv.try_recv() {
+                Ok(SendMessage::Data(0, p, data)) => assert!(p == peer && data == hello),
+                _ => panic!("need hello"),
+            }
+        }
+        versions.hello(&peer, &p2p_send).await;
+        assert!(p2p_recv.try_recv().is_err());
+        let frame = versions.frame(&peer, 2, 1, 2).await;
+        assert_eq!(frame.version, FRAME_VERSION);
+
+        let mut frame = Frame::new(FRAME_VERSION, 1, 2);
+        let res = versions
+            .open(&peer, &p2p_send, frame.to_bytes(vec![3]))
+            .await;
+        assert_eq!(res, Some((1, 2, vec![3])));
+        frame.flags = FLAG_ENCRYPTED;
+        let res = versions
+            .open(&peer, &p2p_send, frame.to_bytes(vec![3]))
+            .await;
+        assert!(res.is_none());
+
+        // the delivery is decoded by the version when sended.
+        let res = versions
+            .open_delivery(1, Frame::new(0, 1, 2).to_bytes(vec![]))
+            .await;
+        assert_eq!(res, Some((1, 2)));
+        let res = versions
+            .open_delivery(2, Frame::new(FRAME_VERSION, 1, 2).to_bytes(vec![]))
+            .await;
+        assert_eq!(res, Some((1, 2)));
+
+        // stream is legacy.
+        let bytes = versions.stream_frame(1, 2).to_bytes(vec![3]);
+        assert_eq!(versions.open_stream(bytes), Some((1, 2, vec![3])));
+
+        versions.leave(&peer).await;
+        assert!(versions.frame(&peer, 3, 1, 2).await.is_legacy());
+    }
+}
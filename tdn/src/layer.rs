@@ -1,24 +1,117 @@
 use chamomile::prelude::SendMessage;
+use std::collections::{BTreeSet, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tdn_types::{
     group::GroupId,
-    message::{ReceiveMessage, RecvType, SendType},
-    primitives::{DeliveryType, Peer, PeerId, Result, StreamType},
+    layer::{generate, sign, verify, Envelope},
+    message::{RecvType, SendType},
+    primitives::{new_io_error, PeerId, PeerKey, Result},
+    unified::ReceiveMessage,
 };
-use tokio::sync::mpsc::{error::SendError, Sender};
+use tokio::sync::{
+    mpsc::{error::SendError, Sender},
+    Mutex,
+};
+
+use crate::frame::FrameVersions;
+
+/// the max seconds between the envelope's timestamp and now.
+const MAX_CLOCK_SKEW: u64 = 60;
+
+/// the max recently seen nonces of every peer.
+const NONCE_WINDOW: usize = 1024;
+
+/// the recently seen nonces of peers, the replayed envelope is rejected.
+/// when the window is full, the nonces not greater than the evicted one
+/// are rejected (the nonce of the sender grows with its clock).
+#[derive(Default)]
+pub(crate) struct LayerNonces(Mutex<HashMap<PeerId, (u64, BTreeSet<u64>)>>);
+
+impl LayerNonces {
+    /// check the envelope is fresh, and record its nonce.
+    async fn check(&self, envelope: &Envelope) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if envelope.timestamp.abs_diff(now) > MAX_CLOCK_SKEW {
+            return false;
+        }
+
+        let mut peers = self.0.lock().await;
+        let (floor, seen) = peers.entry(envelope.peer).or_default();
+        if envelope.nonce <= *floor || !seen.insert(envelope.nonce) {
+            return false;
+        }
+        if seen.len() > NONCE_WINDOW {
+            if let Some(evicted) = seen.pop_first() {
+                *floor = evicted;
+            }
+        }
+        true
+    }
+
+    /// the peer leaved, forget its nonces.
+    pub async fn leave(&self, peer: &PeerId) {
+        self.0.lock().await.remove(peer);
+    }
+}
+
+/// sign the data with the envelope, include groups and sender.
+fn seal(fgid: GroupId, tgid: GroupId, key: &PeerKey, data: Vec<u8>) -> Vec<u8> {
+    let mut envelope = generate(fgid, tgid, key.peer_id(), &data);
+    sign(&mut envelope, key);
+    envelope.to_bytes(data)
+}
+
+/// check the envelope's groups, sender, signature and freshness, return the payload.
+/// the sender is none when the message has no peer (stream data), then the
+/// signer of the envelope is the sender.
+async fn open(
+    fgid: GroupId,
+    tgid: GroupId,
+    peer_id: Option<&PeerId>,
+    nonces: &LayerNonces,
+    data: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let (envelope, payload) = Envelope::from_bytes(data)?;
+    if envelope.fgid != fgid
+        || envelope.tgid != tgid
+        || peer_id.is_some_and(|p| p != &envelope.peer)
+        || !verify(&envelope, &payload)
+    {
+        warn!(
+            "Layer message from {} is invalid",
+            envelope.peer.short_show()
+        );
+        return Err(new_io_error("layer signature is invalid").into());
+    }
+    if !nonces.check(&envelope).await {
+        warn!(
+            "Layer message from {} is replayed",
+            envelope.peer.short_show()
+        );
+        return Err(new_io_error("layer message is replayed or expired").into());
+    }
+
+    Ok(payload)
+}
 
 #[inline]
 pub(crate) async fn layer_handle_send(
     fgid: GroupId,
     tgid: GroupId,
+    key: &PeerKey,
+    versions: &FrameVersions,
     p2p_send: &Sender<SendMessage>,
     msg: SendType,
 ) -> std::result::Result<(), SendError<SendMessage>> {
     // fgid, tgid serialize data to msg data.
-    let mut bytes = fgid.to_be_bytes().to_vec();
-    bytes.extend(&tgid.to_be_bytes());
+    // the frame version is negotiated with the peer, stream is legacy.
     match msg {
         SendType::Connect(tid, peer, data) => {
-            bytes.extend(data);
+            let frame = versions.frame(&peer.id, tid, fgid, tgid).await;
+            let bytes = frame.to_bytes(seal(fgid, tgid, key, data));
             p2p_send
                 .send(SendMessage::StableConnect(tid, peer.into(), bytes))
                 .await
@@ -27,7 +120,8 @@ pub(crate) async fn layer_handle_send(
             p2p_send.send(SendMessage::StableDisconnect(peer_id)).await
         }
         SendType::Result(tid, peer, is_ok, is_force, data) => {
-            bytes.extend(data);
+            let frame = versions.frame(&peer.id, tid, fgid, tgid).await;
+            let bytes = frame.to_bytes(seal(fgid, tgid, key, data));
             p2p_send
                 .send(SendMessage::StableResult(
                     tid,
@@ -39,120 +133,52 @@ pub(crate) async fn layer_handle_send(
                 .await
         }
         SendType::Event(tid, peer_id, data) => {
-            bytes.extend(data);
+            let frame = versions.frame(&peer_id, tid, fgid, tgid).await;
+            let bytes = frame.to_bytes(seal(fgid, tgid, key, data));
             p2p_send.send(SendMessage::Data(tid, peer_id, bytes)).await
         }
         SendType::Stream(id, stream, data) => {
-            bytes.extend(data);
+            let bytes = versions
+                .stream_frame(fgid, tgid)
+                .to_bytes(seal(fgid, tgid, key, data));
             p2p_send.send(SendMessage::Stream(id, stream, bytes)).await
         }
     }
 }
 
 #[inline]
-pub(crate) async fn layer_handle_connect(
-    fgid: GroupId,
-    tgid: GroupId,
-    out_send: &Sender<ReceiveMessage>,
-    peer: Peer,
-    data: Vec<u8>,
-) -> Result<()> {
-    let gmsg = RecvType::Connect(peer, data);
-    let msg = ReceiveMessage::Layer(fgid, tgid, gmsg);
-
-    out_send
-        .send(msg)
-        .await
-        .map_err(|e| error!("Outside channel: {:?}", e));
-
-    Ok(())
-}
-
-#[inline]
-pub(crate) async fn layer_handle_result_connect(
-    fgid: GroupId,
-    tgid: GroupId,
-    out_send: &Sender<ReceiveMessage>,
-    peer: Peer,
-    data: Vec<u8>,
-) -> Result<()> {
-    let gmsg = RecvType::ResultConnect(peer, data);
-    let msg = ReceiveMessage::Layer(fgid, tgid, gmsg);
-
-    out_send
-        .send(msg)
-        .await
-        .map_err(|e| error!("Outside channel: {:?}", e));
-
-    Ok(())
-}
-
-#[inline]
-pub(crate) async fn layer_handle_result(
-    fgid: GroupId,
-    tgid: GroupId,
-    out_send: &Sender<ReceiveMessage>,
-    peer: Peer,
-    is_ok: bool,
-    data: Vec<u8>,
-) -> Result<()> {
-    let gmsg = RecvType::Result(peer, is_ok, data);
-    let msg = ReceiveMessage::Layer(fgid, tgid, gmsg);
-
-    out_send
-        .send(msg)
-        .await
-        .map_err(|e| error!("Outside channel: {:?}", e));
-
-    Ok(())
-}
-
-#[inline]
-pub(crate) async fn layer_handle_leave(
-    fgid: GroupId,
-    out_send: &Sender<ReceiveMessage>,
-    peer: impl Into<Peer>,
-) -> Result<()> {
-    let gmsg = RecvType::Leave(peer.into());
-    let msg = ReceiveMessage::Layer(fgid, fgid, gmsg);
-
-    out_send
-        .send(msg)
-        .await
-        .map_err(|e| error!("Outside channel: {:?}", e));
-
-    Ok(())
-}
-
-#[inline]
-pub(crate) async fn layer_handle_data(
+pub(crate) async fn layer_handle_recv(
     fgid: GroupId,
     tgid: GroupId,
+    nonces: &LayerNonces,
     out_send: &Sender<ReceiveMessage>,
-    peer_id: PeerId,
-    data: Vec<u8>,
+    gmsg: RecvType,
 ) -> Result<()> {
-    let gmsg = RecvType::Event(peer_id, data);
-    let msg = ReceiveMessage::Layer(fgid, tgid, gmsg);
-
-    out_send
-        .send(msg)
-        .await
-        .map_err(|e| error!("Outside channel: {:?}", e));
-
-    Ok(())
-}
-
-#[inline]
-pub(crate) async fn layer_handle_stream(
-    fgid: GroupId,
-    tgid: GroupId,
-    out_send: &Sender<ReceiveMessage>,
-    uid: u32,
-    stream_type: StreamType,
-    data: Vec<u8>,
-) -> Result<()> {
-    let gmsg = RecvType::Stream(uid, stream_type, data);
+    // the data from peer is signed, open it.
+    let gmsg = match gmsg {
+        RecvType::Connect(peer, data) => {
+            let data = open(fgid, tgid, Some(&peer.id), nonces, data).await?;
+            RecvType::Connect(peer, data)
+        }
+        RecvType::ResultConnect(peer, data) => {
+            let data = open(fgid, tgid, Some(&peer.id), nonces, data).await?;
+            RecvType::ResultConnect(peer, data)
+        }
+        RecvType::Result(peer, is_ok, data) => {
+            let data = open(fgid, tgid, Some(&peer.id), nonces, data).await?;
+            RecvType::Result(peer, is_ok, data)
+        }
+        RecvType::Event(peer_id, data) => {
+            let data = open(fgid, tgid, Some(&peer_id), nonces, data).await?;
+            RecvType::Event(peer_id, data)
+        }
+        RecvType::Stream(id, stream, data) => {
+            // the stream has no peer, it is the signer of the envelope.
+            let data = open(fgid, tgid, None, nonces, data).await?;
+            RecvType::Stream(id, stream, data)
+        }
+        gmsg => gmsg,
+    };
     let msg = ReceiveMessage::Layer(fgid, tgid, gmsg);
 
     out_send
@@ -163,22 +189,62 @@ pub(crate) async fn layer_handle_stream(
     Ok(())
 }
 
-#[inline]
-pub(crate) async fn layer_handle_delivery(
-    fgid: GroupId,
-    tgid: GroupId,
-    out_send: &Sender<ReceiveMessage>,
-    delivery_type: DeliveryType,
-    tid: u64,
-    is_sended: bool,
-) -> Result<()> {
-    let gmsg = RecvType::Delivery(delivery_type, tid, is_sended);
-    let msg = ReceiveMessage::Layer(fgid, tgid, gmsg);
-
-    out_send
-        .send(msg)
-        .await
-        .map_err(|e| error!("Outside channel: {:?}", e));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tdn_types::primitives::{secp256k1::SecretKey, PeerSecretKey};
+
+    #[tokio::test]
+    async fn test_layer_replay() {
+        let sk = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let key = PeerKey::from_sec_key(PeerSecretKey::new(sk));
+        let peer_id = key.peer_id();
+        let nonces = LayerNonces::default();
+
+        let bytes = seal(1, 2, &key, vec![1, 2, 3]);
+        let data = open(1, 2, Some(&peer_id), &nonces, bytes.clone())
+            .await
+            .unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        // replay the same bytes.
+        assert!(open(1, 2, Some(&peer_id), &nonces, bytes.clone())
+            .await
+            .is_err());
+        // the peer leaved, the nonces are forgot.
+        nonces.leave(&peer_id).await;
+        assert!(open(1, 2, Some(&peer_id), &nonces, bytes).await.is_ok());
+
+        // expired envelope.
+        let mut envelope = generate(1, 2, peer_id, &[4]);
+        envelope.timestamp -= MAX_CLOCK_SKEW + 1;
+        sign(&mut envelope, &key);
+        let bytes = envelope.to_bytes(vec![4]);
+        assert!(open(1, 2, Some(&peer_id), &nonces, bytes).await.is_err());
+
+        // the window is full, the older nonces are rejected.
+        let mut envelopes = vec![];
+        for _ in 0..=NONCE_WINDOW {
+            let envelope = generate(1, 2, peer_id, &[5]);
+            assert!(nonces.check(&envelope).await);
+            envelopes.push(envelope);
+        }
+        assert!(!nonces.check(&envelopes[0]).await);
+        assert!(!nonces.check(&envelopes[NONCE_WINDOW]).await);
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_layer_stream() {
+        let sk = SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let key = PeerKey::from_sec_key(PeerSecretKey::new(sk));
+        let nonces = LayerNonces::default();
+
+        // the stream has no peer, the unsigned data and other groups are dropped.
+        assert!(open(1, 2, None, &nonces, vec![1, 2, 3]).await.is_err());
+        let bytes = seal(1, 3, &key, vec![1, 2, 3]);
+        assert!(open(1, 2, None, &nonces, bytes).await.is_err());
+        let bytes = seal(1, 2, &key, vec![1, 2, 3]);
+        let data = open(1, 2, None, &nonces, bytes.clone()).await.unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert!(open(1, 2, None, &nonces, bytes).await.is_err());
+    }
 }
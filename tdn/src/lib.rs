@@ -10,50 +10,51 @@
 
 #![recursion_limit = "1024"]
 
-// check features conflict
-#[cfg(any(
-    all(
-        feature = "std",
-        any(feature = "single", feature = "multiple", feature = "full")
-    ),
-    all(
-        feature = "single",
-        any(feature = "std", feature = "multiple", feature = "full")
-    ),
-    all(
-        feature = "multiple",
-        any(feature = "std", feature = "single", feature = "full")
-    ),
-    all(
-        feature = "full",
-        any(feature = "std", feature = "single", feature = "multiple")
-    ),
-))]
-panic!("feature conflict, only one feature at one time.");
+// the mode features can be combined, `tdn_mode` is the legacy message shape
+// of them (see build.rs), and the run-time mode is in `Config`.
 
 #[macro_use]
 extern crate tracing;
 
+mod compat;
 mod config;
+mod frame;
 mod group;
-mod rpc;
-
-#[cfg(any(feature = "std", feature = "full"))]
 mod layer;
+mod router;
+mod shutdown;
 
 // public mod
+pub mod app;
 pub mod error;
+pub mod rpc;
+pub mod simnet;
 
 // re-export tdn_types
 pub use tdn_types as types;
 
+/// The p2p network under TDN, include self peer id, and the channels of it.
+/// Default is chamomile, and it can be the in-process `simnet` for tests.
+pub type P2pNetwork = (
+    tdn_types::primitives::PeerId,
+    tokio::sync::mpsc::Sender<chamomile::prelude::SendMessage>,
+    tokio::sync::mpsc::Receiver<chamomile::prelude::ReceiveMessage>,
+);
+
 // public struct
 pub mod prelude {
+    pub use super::app::App;
     pub use super::config::Config;
+    pub use super::router::{DefaultRouter, Route, Router};
     pub use super::rpc::{
-        channel_rpc_channel, ChannelAddr, ChannelMessage, ChannelRpcSender, RpcConfig, RpcMessage,
+        channel_rpc_channel, ChannelAddr, ChannelMessage, ChannelRpcSender, RateLimit, RpcAuth,
+        RpcConfig, RpcLimits, RpcMessage, RpcTlsConfig,
+    };
+    pub use super::shutdown::Shutdown;
+    pub use super::P2pNetwork;
+    pub use chamomile::prelude::{
+        Config as P2pConfig, ReceiveMessage as P2pReceiveMessage, SendMessage as P2pSendMessage,
     };
-    pub use chamomile::prelude::Config as P2pConfig;
     pub use tdn_types::{
         group::{GroupId, GROUP_BYTES_LENGTH},
         message::{
@@ -61,24 +62,35 @@ pub mod prelude {
             StateResponse,
         },
         primitives::{Broadcast, HandleResult, Peer, PeerId, PeerKey, Result},
+        unified::{self, Mode},
     };
 
     use chamomile::prelude::{
-        start as chamomile_start, start_with_key as chamomile_start_with_key,
-        ReceiveMessage as ChamomileReceiveMessage, SendMessage as ChamomileSendMessage,
+        start_with_key as chamomile_start_with_key, ReceiveMessage as ChamomileReceiveMessage,
+        SendMessage as ChamomileSendMessage,
+    };
+    use rand_chacha::{
+        rand_core::{RngCore, SeedableRng},
+        ChaChaRng,
+    };
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
     };
-    use std::{path::PathBuf, sync::Arc};
     use tdn_types::message::RpcSendMessage;
+    use tdn_types::primitives::{new_io_error, secp256k1, PeerSecretKey, PEER_KEY_FILE_NAME};
     use tokio::{
+        fs, select,
         sync::mpsc::{self, Receiver, Sender},
         sync::RwLock,
     };
 
+    use super::compat::{legacy_service, recv_shim, send_shim};
+    use super::frame::FrameVersions;
     use super::group::*;
-    use super::rpc::start as rpc_start;
-
-    #[cfg(any(feature = "std", feature = "full"))]
     use super::layer::*;
+    use super::router::route_handle;
+    use super::rpc::start as rpc_start;
 
     /// new a channel, send message to TDN Message. default capacity is 1024.
     pub fn new_send_channel() -> (Sender<SendMessage>, Receiver<SendMessage>) {
@@ -90,332 +102,425 @@ pub mod prelude {
         mpsc::channel(1024)
     }
 
-    /// start a service, use config.toml file.
-    /// send a Sender<Message>, and return the peer_id, and service Sender<Message>.
-    pub async fn start() -> Result<(PeerId, Sender<SendMessage>, Receiver<ReceiveMessage>)> {
-        let (send_send, send_recv) = new_send_channel();
-        let (recv_send, recv_recv) = new_receive_channel();
+    /// new a channel, send unified message to TDN. default capacity is 1024.
+    pub fn new_unified_send_channel(
+    ) -> (Sender<unified::SendMessage>, Receiver<unified::SendMessage>) {
+        mpsc::channel(1024)
+    }
 
-        let config = Config::load(PathBuf::from("./")).await;
+    /// new a channel, receive unified message from TDN. default capacity is 1024.
+    pub fn new_unified_receive_channel() -> (
+        Sender<unified::ReceiveMessage>,
+        Receiver<unified::ReceiveMessage>,
+    ) {
+        mpsc::channel(1024)
+    }
 
-        let (_secret, ids, p2p_config, rpc_config) = config.split();
-        let rpc_send = start_rpc(rpc_config, recv_send.clone()).await?;
-        let peer_id =
-            start_main(ids, p2p_config, recv_send, send_recv, Some(rpc_send), None).await?;
+    /// the running service: peer_id, service Sender<Message>, Receiver<Message>
+    /// and the shutdown handle.
+    pub type Service = (
+        PeerId,
+        Sender<SendMessage>,
+        Receiver<ReceiveMessage>,
+        Shutdown,
+    );
+
+    /// the running service of unified messages.
+    pub type UnifiedService = (
+        PeerId,
+        Sender<unified::SendMessage>,
+        Receiver<unified::ReceiveMessage>,
+        Shutdown,
+    );
 
-        Ok((peer_id, send_send, recv_recv))
+    /// start a service, use config.toml file.
+    /// send a Sender<Message>, and return the peer_id, and service Sender<Message>.
+    pub async fn start() -> Result<Service> {
+        let config = Config::load(PathBuf::from("./")).await;
+        start_with_config(config).await
     }
 
     /// start a service with config.
-    pub async fn start_with_config(
-        config: Config,
-    ) -> Result<(PeerId, Sender<SendMessage>, Receiver<ReceiveMessage>)> {
-        let (send_send, send_recv) = new_send_channel();
-        let (recv_send, recv_recv) = new_receive_channel();
-
-        let (_secret, ids, p2p_config, rpc_config) = config.split();
-        let rpc_send = start_rpc(rpc_config, recv_send.clone()).await?;
-        let peer_id =
-            start_main(ids, p2p_config, recv_send, send_recv, Some(rpc_send), None).await?;
-
-        Ok((peer_id, send_send, recv_recv))
+    pub async fn start_with_config(config: Config) -> Result<Service> {
+        let gid = config.group_ids.first().copied().unwrap_or_default();
+        let service = start_unified_with_config(config, None).await?;
+        Ok(legacy_service(service, gid))
     }
 
     /// start a service with config and PeerKey.
-    pub async fn start_with_config_and_key(
+    pub async fn start_with_config_and_key(config: Config, key: PeerKey) -> Result<Service> {
+        let gid = config.group_ids.first().copied().unwrap_or_default();
+        let service = start_unified_with_config(config, Some(key)).await?;
+        Ok(legacy_service(service, gid))
+    }
+
+    /// start a service of unified messages with config, the mode is in config.
+    /// layer messages are signed by the PeerKey, if no key, use the node's key.
+    pub async fn start_unified_with_config(
         config: Config,
-        key: PeerKey,
-    ) -> Result<(PeerId, Sender<SendMessage>, Receiver<ReceiveMessage>)> {
-        let (send_send, send_recv) = new_send_channel();
-        let (recv_send, recv_recv) = new_receive_channel();
-
-        let (_secret, ids, p2p_config, rpc_config) = config.split();
-        let rpc_send = start_rpc(rpc_config, recv_send.clone()).await?;
-        let peer_id = start_main(
+        key: Option<PeerKey>,
+    ) -> Result<UnifiedService> {
+        let (send_send, send_recv) = new_unified_send_channel();
+        let (recv_send, recv_recv) = new_unified_receive_channel();
+        let shutdown = Shutdown::new();
+
+        let mode = config.mode;
+        let (_secret, ids, p2p_config, rpc_config) = config.split()?;
+        mode.check_groups(&ids)?;
+        let rpc_send = start_unified_rpc(rpc_config, recv_send.clone(), &shutdown).await?;
+        let peer_id = start_unified_main(
+            mode,
             ids,
             p2p_config,
             recv_send,
             send_recv,
             Some(rpc_send),
-            Some(key),
+            key,
+            None,
+            &shutdown,
         )
         .await?;
 
-        Ok((peer_id, send_send, recv_recv))
+        Ok((peer_id, send_send, recv_recv, shutdown))
     }
 
-    /// start a separate rpc service.
+    /// start a separate rpc service, it stops when the shutdown started.
     pub async fn start_rpc(
         config: RpcConfig,
         out_send: Sender<ReceiveMessage>,
+        shutdown: &Shutdown,
     ) -> Result<Sender<RpcSendMessage>> {
-        rpc_start(config, out_send).await
+        start_unified_rpc(config, recv_shim(out_send), shutdown).await
+    }
+
+    /// start a separate rpc service of unified messages.
+    pub async fn start_unified_rpc(
+        config: RpcConfig,
+        out_send: Sender<unified::ReceiveMessage>,
+        shutdown: &Shutdown,
+    ) -> Result<Sender<RpcSendMessage>> {
+        rpc_start(config, out_send, shutdown.signal()).await
     }
 
     /// start a separate p2p service and unified tdn channel.
+    /// the router decides where the received frames go, default is `DefaultRouter`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn start_main(
         group_ids: Vec<GroupId>,
         p2p_config: P2pConfig,
         out_send: Sender<ReceiveMessage>,
-        mut self_recv: Receiver<SendMessage>,
+        self_recv: Receiver<SendMessage>,
         rpc_send: Option<Sender<RpcSendMessage>>,
         key: Option<PeerKey>,
+        router: Option<Arc<dyn Router>>,
+        shutdown: &Shutdown,
     ) -> Result<PeerId> {
-        // start chamomile network & inner rpc.
-        let res1 = if let Some(key) = key {
-            chamomile_start_with_key(p2p_config, key).await
-        } else {
-            chamomile_start(p2p_config).await
+        let gid = group_ids.first().copied().unwrap_or_default();
+        start_unified_main(
+            Mode::default(),
+            group_ids,
+            p2p_config,
+            recv_shim(out_send),
+            send_shim(self_recv, gid),
+            rpc_send,
+            key,
+            router,
+            shutdown,
+        )
+        .await
+    }
+
+    /// start unified tdn channel on a running p2p network,
+    /// the network can be chamomile or other transports (e.g. `simnet`).
+    /// layer messages are signed by the PeerKey of the network, it is required
+    /// when the mode has layers.
+    /// when the shutdown started, stop the network, and wait the rpc down.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start_main_with_network(
+        group_ids: Vec<GroupId>,
+        network: P2pNetwork,
+        out_send: Sender<ReceiveMessage>,
+        self_recv: Receiver<SendMessage>,
+        rpc_send: Option<Sender<RpcSendMessage>>,
+        key: Option<PeerKey>,
+        router: Option<Arc<dyn Router>>,
+        shutdown: &Shutdown,
+    ) -> Result<PeerId> {
+        let gid = group_ids.first().copied().unwrap_or_default();
+        start_unified_main_with_network(
+            Mode::default(),
+            group_ids,
+            network,
+            recv_shim(out_send),
+            send_shim(self_recv, gid),
+            rpc_send,
+            key,
+            router,
+            shutdown,
+        )
+        .await
+    }
+
+    /// start a separate p2p service of unified messages in the mode.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start_unified_main(
+        mode: Mode,
+        group_ids: Vec<GroupId>,
+        p2p_config: P2pConfig,
+        out_send: Sender<unified::ReceiveMessage>,
+        self_recv: Receiver<unified::SendMessage>,
+        rpc_send: Option<Sender<RpcSendMessage>>,
+        key: Option<PeerKey>,
+        router: Option<Arc<dyn Router>>,
+        shutdown: &Shutdown,
+    ) -> Result<PeerId> {
+        mode.check_groups(&group_ids)?;
+
+        // the layer messages are always signed, by the given or node's key.
+        let key = match key {
+            Some(key) => key,
+            None => node_key(&p2p_config.db_dir).await?,
         };
 
-        let (peer_id, p2p_send, mut p2p_recv) = res1?;
+        // start chamomile network & inner rpc.
+        let network = chamomile_start_with_key(p2p_config, key.clone()).await?;
 
         debug!("chamomile & jsonrpc service started");
+        start_unified_main_with_network(
+            mode,
+            group_ids,
+            network,
+            out_send,
+            self_recv,
+            rpc_send,
+            Some(key),
+            router,
+            shutdown,
+        )
+        .await
+    }
+
+    /// load the node's key in the db directory, if not exists, generate it.
+    /// the generated key is a new identity, so it is warned: a node which
+    /// started without key before gets a new PeerId, put its old secret key
+    /// in the file (or start with the key) to keep the PeerId.
+    async fn node_key(db_dir: &Path) -> Result<PeerKey> {
+        let path = db_dir.join(PEER_KEY_FILE_NAME);
+        if let Ok(bytes) = fs::read(&path).await {
+            let sk = secp256k1::SecretKey::from_slice(&bytes)
+                .map_err(|_| new_io_error("peer key file is invalid"))?;
+            return Ok(PeerKey::from_sec_key(PeerSecretKey::new(sk)));
+        }
+
+        let mut rng = ChaChaRng::from_entropy();
+        let mut bytes = [0u8; 32];
+        let sk = loop {
+            rng.fill_bytes(&mut bytes);
+            if let Ok(sk) = secp256k1::SecretKey::from_slice(&bytes) {
+                break sk;
+            }
+        };
+
+        fs::create_dir_all(db_dir).await?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&path).await?;
+        tokio::io::AsyncWriteExt::write_all(&mut file, &bytes).await?;
+        let key = PeerKey::from_sec_key(PeerSecretKey::new(sk));
+        warn!(
+            "Generated the new node key {:?}, PeerId: {}. Put the old secret key in it to keep the old PeerId.",
+            path,
+            key.peer_id().to_hex()
+        );
+        Ok(key)
+    }
+
+    /// start the channel of unified messages on a running p2p network.
+    /// the messages which the mode not supported are dropped: layers of
+    /// single/multiple, other groups and add/del group of single/std.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start_unified_main_with_network(
+        mode: Mode,
+        group_ids: Vec<GroupId>,
+        network: P2pNetwork,
+        out_send: Sender<unified::ReceiveMessage>,
+        mut self_recv: Receiver<unified::SendMessage>,
+        rpc_send: Option<Sender<RpcSendMessage>>,
+        key: Option<PeerKey>,
+        router: Option<Arc<dyn Router>>,
+        shutdown: &Shutdown,
+    ) -> Result<PeerId> {
+        mode.check_groups(&group_ids)?;
+        if mode.has_layers() && key.is_none() {
+            let info = format!("{} mode need the PeerKey to sign layers", mode.to_str());
+            return Err(new_io_error(&info).into());
+        }
+        let (peer_id, p2p_send, mut p2p_recv) = network;
         let my_groups = Arc::new(RwLock::new(group_ids));
         let my_groups_1 = my_groups.clone();
+        let versions = Arc::new(FrameVersions::default());
+        let versions_1 = versions.clone();
+        let nonces = LayerNonces::default();
+        let p2p_send_1 = p2p_send.clone();
+        let router = router.unwrap_or_else(|| Arc::new(DefaultRouter));
 
         // handle chamomile send msg.
         let listen_task = tokio::spawn(async move {
-            // if group's inner message, from_group in our groups.
-            // if layer's message,       from_group not in our groups.
-
             while let Some(message) = p2p_recv.recv().await {
-                match message {
-                    ChamomileReceiveMessage::StableConnect(peer, mut data) => {
-                        if data.len() < GROUP_BYTES_LENGTH * 2 {
-                            continue;
-                        }
-                        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        fgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        tgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        let fgid = GroupId::from_be_bytes(fgid_bytes);
-                        let tgid = GroupId::from_be_bytes(tgid_bytes);
-
-                        let group_lock = my_groups.read().await;
-                        if group_lock.len() == 0 {
-                            continue;
-                        }
-
-                        if fgid == tgid && group_lock.contains(&fgid) {
-                            drop(group_lock);
-                            let _ = group_handle_connect(&fgid, &out_send, peer.into(), data).await;
-                        } else {
-                            drop(group_lock);
-                            // layer handle it.
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ = layer_handle_connect(fgid, tgid, &out_send, peer.into(), data)
-                                .await;
-                        }
+                // the frames have groups, others are handled here.
+                let (fgid, tgid, msg) = match message {
+                    ChamomileReceiveMessage::StableConnect(peer, data) => {
+                        let (fgid, tgid, data) =
+                            match versions.open(&peer.id, &p2p_send_1, data).await {
+                                Some(frame) => frame,
+                                None => continue,
+                            };
+                        versions.hello(&peer.id, &p2p_send_1).await;
+                        (fgid, tgid, RecvType::Connect(peer.into(), data))
                     }
-                    ChamomileReceiveMessage::ResultConnect(peer, mut data) => {
-                        if data.len() < GROUP_BYTES_LENGTH * 2 {
-                            continue;
-                        }
-                        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        fgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        tgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        let fgid = GroupId::from_be_bytes(fgid_bytes);
-                        let tgid = GroupId::from_be_bytes(tgid_bytes);
-
-                        let group_lock = my_groups.read().await;
-                        if group_lock.len() == 0 {
-                            continue;
-                        }
-
-                        if fgid == tgid && group_lock.contains(&fgid) {
-                            drop(group_lock);
-                            let _ =
-                                group_handle_result_connect(&fgid, &out_send, peer.into(), data)
-                                    .await;
-                        } else {
-                            drop(group_lock);
-                            // layer handle it.
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ = layer_handle_result_connect(
-                                fgid,
-                                tgid,
-                                &out_send,
-                                peer.into(),
-                                data,
-                            )
-                            .await;
-                        }
+                    ChamomileReceiveMessage::ResultConnect(peer, data) => {
+                        let (fgid, tgid, data) =
+                            match versions.open(&peer.id, &p2p_send_1, data).await {
+                                Some(frame) => frame,
+                                None => continue,
+                            };
+                        versions.hello(&peer.id, &p2p_send_1).await;
+                        (fgid, tgid, RecvType::ResultConnect(peer.into(), data))
                     }
-                    ChamomileReceiveMessage::StableResult(peer, is_ok, mut data) => {
-                        if data.len() < GROUP_BYTES_LENGTH * 2 {
-                            continue;
-                        }
-                        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        fgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        tgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        let fgid = GroupId::from_be_bytes(fgid_bytes);
-                        let tgid = GroupId::from_be_bytes(tgid_bytes);
-
-                        let group_lock = my_groups.read().await;
-                        if group_lock.len() == 0 {
-                            continue;
-                        }
-
-                        if fgid == tgid && group_lock.contains(&fgid) {
-                            drop(group_lock);
-                            let _ = group_handle_result(&fgid, &out_send, peer.into(), is_ok, data)
-                                .await;
-                        } else {
-                            drop(group_lock);
-                            // layer handle it.
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ = layer_handle_result(
-                                fgid,
-                                tgid,
-                                &out_send,
-                                peer.into(),
-                                is_ok,
-                                data,
-                            )
-                            .await;
-                        }
+                    ChamomileReceiveMessage::StableResult(peer, is_ok, data) => {
+                        let (fgid, tgid, data) =
+                            match versions.open(&peer.id, &p2p_send_1, data).await {
+                                Some(frame) => frame,
+                                None => continue,
+                            };
+                        if is_ok {
+                            versions.hello(&peer.id, &p2p_send_1).await;
+                        }
+                        (fgid, tgid, RecvType::Result(peer.into(), is_ok, data))
                     }
-                    ChamomileReceiveMessage::StableLeave(peer) => {
-                        let group_lock = my_groups.read().await;
-                        for gid in group_lock.iter() {
-                            let _ = group_handle_leave(&gid, &out_send, peer).await;
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ = layer_handle_leave(*gid, &out_send, peer).await;
-                        }
-                        drop(group_lock);
+                    ChamomileReceiveMessage::Data(peer_id, data) => {
+                        let (fgid, tgid, data) =
+                            match versions.open(&peer_id, &p2p_send_1, data).await {
+                                Some(frame) => frame,
+                                None => continue,
+                            };
+                        (fgid, tgid, RecvType::Event(peer_id, data))
                     }
-                    ChamomileReceiveMessage::Data(peer_id, mut data) => {
-                        if data.len() < GROUP_BYTES_LENGTH * 2 {
-                            continue;
-                        }
-                        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        fgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        tgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        let fgid = GroupId::from_be_bytes(fgid_bytes);
-                        let tgid = GroupId::from_be_bytes(tgid_bytes);
-
-                        let group_lock = my_groups.read().await;
-                        if group_lock.len() == 0 {
-                            continue;
-                        }
-
-                        if fgid == tgid && group_lock.contains(&fgid) {
-                            drop(group_lock);
-                            let _ = group_handle_data(&fgid, &out_send, peer_id, data).await;
-                        } else {
-                            drop(group_lock);
-                            // layer handle it.
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ = layer_handle_data(fgid, tgid, &out_send, peer_id, data).await;
-                        }
+                    ChamomileReceiveMessage::Stream(id, stream, data) => {
+                        let (fgid, tgid, data) = match versions.open_stream(data) {
+                            Some(frame) => frame,
+                            None => continue,
+                        };
+                        (fgid, tgid, RecvType::Stream(id, stream, data))
                     }
-                    ChamomileReceiveMessage::Stream(id, stream, mut data) => {
-                        if data.len() < GROUP_BYTES_LENGTH * 2 {
-                            continue;
-                        }
-                        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        fgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        tgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        let fgid = GroupId::from_be_bytes(fgid_bytes);
-                        let tgid = GroupId::from_be_bytes(tgid_bytes);
-
-                        let group_lock = my_groups.read().await;
-                        if group_lock.len() == 0 {
-                            continue;
-                        }
-
-                        if fgid == tgid && group_lock.contains(&fgid) {
-                            drop(group_lock);
-                            let _ = group_handle_stream(&fgid, &out_send, id, stream, data).await;
-                        } else {
-                            drop(group_lock);
-                            // layer handle it.
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ =
-                                layer_handle_stream(fgid, tgid, &out_send, id, stream, data).await;
-                        }
+                    ChamomileReceiveMessage::Delivery(t, tid, is_ok, data) => {
+                        let (fgid, tgid) = match versions.open_delivery(tid, data).await {
+                            Some(gids) => gids,
+                            None => continue,
+                        };
+                        (fgid, tgid, RecvType::Delivery(t.into(), tid, is_ok))
                     }
-                    ChamomileReceiveMessage::Delivery(t, tid, is_ok, mut data) => {
-                        if data.len() < GROUP_BYTES_LENGTH * 2 {
-                            continue;
-                        }
-                        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
-                        fgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        tgid_bytes.copy_from_slice(data.drain(..GROUP_BYTES_LENGTH).as_slice());
-                        let fgid = GroupId::from_be_bytes(fgid_bytes);
-                        let tgid = GroupId::from_be_bytes(tgid_bytes);
-
+                    ChamomileReceiveMessage::StableLeave(peer) => {
+                        versions.leave(&peer.id).await;
+                        nonces.leave(&peer.id).await;
                         let group_lock = my_groups.read().await;
-                        if group_lock.len() == 0 {
-                            continue;
-                        }
-
-                        if fgid == tgid && group_lock.contains(&fgid) {
-                            drop(group_lock);
-                            let _ =
-                                group_handle_delivery(&fgid, &out_send, t.into(), tid, is_ok).await;
-                        } else {
-                            drop(group_lock);
-                            // layer handle it.
-                            #[cfg(any(feature = "std", feature = "full"))]
-                            let _ = layer_handle_delivery(
-                                tgid, // Assuming it is remote sended.
-                                fgid,
-                                &out_send,
-                                t.into(),
-                                tid,
-                                is_ok,
-                            )
-                            .await;
+                        for gid in group_lock.iter() {
+                            let leave = RecvType::Leave(peer.into());
+                            let _ = group_handle_recv(gid, &out_send, leave).await;
+                            if mode.has_layers() {
+                                let leave = RecvType::Leave(peer.into());
+                                let _ =
+                                    layer_handle_recv(*gid, *gid, &nonces, &out_send, leave).await;
+                            }
                         }
+                        drop(group_lock);
+                        continue;
                     }
                     ChamomileReceiveMessage::NetworkLost => {
                         out_send
-                            .send(ReceiveMessage::NetworkLost)
+                            .send(unified::ReceiveMessage::NetworkLost)
                             .await
                             .map_err(|e| error!("Outside channel: {:?}", e));
+                        continue;
                     }
                     ChamomileReceiveMessage::OwnConnect(peer) => {
                         let assist_id = peer.assist;
                         let mut new_peer: Peer = peer.into();
                         new_peer.id = assist_id;
                         out_send
-                            .send(ReceiveMessage::Own(RecvType::Connect(new_peer, vec![])))
+                            .send(unified::ReceiveMessage::Own(RecvType::Connect(
+                                new_peer,
+                                vec![],
+                            )))
                             .await
                             .map_err(|e| error!("Outside channel: {:?}", e));
+                        continue;
                     }
                     ChamomileReceiveMessage::OwnLeave(peer) => {
                         let assist_id = peer.assist;
                         let mut new_peer: Peer = peer.into();
                         new_peer.id = assist_id;
                         out_send
-                            .send(ReceiveMessage::Own(RecvType::Leave(new_peer)))
+                            .send(unified::ReceiveMessage::Own(RecvType::Leave(new_peer)))
                             .await
                             .map_err(|e| error!("Outside channel: {:?}", e));
+                        continue;
                     }
                     ChamomileReceiveMessage::OwnEvent(aid, data) => {
                         out_send
-                            .send(ReceiveMessage::Own(RecvType::Event(aid, data)))
+                            .send(unified::ReceiveMessage::Own(RecvType::Event(aid, data)))
                             .await
                             .map_err(|e| error!("Outside channel: {:?}", e));
+                        continue;
                     }
-                }
+                };
+
+                // the router decides group's, layer's or others.
+                let route = router.route(fgid, tgid, msg, &my_groups.read().await);
+                let _ = route_handle(route, mode, &nonces, &out_send).await;
             }
 
             warn!("Chamomile network is stopped");
         });
 
         // handle outside msg.
+        let mut signal = shutdown.signal();
         tokio::spawn(async move {
-            while let Some(message) = self_recv.recv().await {
+            let mut stopped = false;
+            loop {
+                let message = select! {
+                    v = self_recv.recv() => match v {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = signal.wait(), if !stopped => {
+                        warn!("Start stop chamomile...");
+                        let _ = p2p_send.send(ChamomileSendMessage::NetworkStop).await;
+                        listen_task.abort();
+                        stopped = true;
+                        continue;
+                    }
+                    _ = async {
+                        if let Some(ref rpc_send) = rpc_send {
+                            rpc_send.closed().await;
+                        }
+                    }, if stopped => break,
+                };
+
+                // the network is stopped, only forward rpc until it is down.
+                if stopped
+                    && !matches!(
+                        message,
+                        unified::SendMessage::Rpc(..) | unified::SendMessage::Publish(..)
+                    )
+                {
+                    continue;
+                }
+
                 match message {
-                    SendMessage::Own(msg) => match msg {
+                    unified::SendMessage::Own(msg) => match msg {
                         SendType::Connect(tid, peer, data) => {
                             p2p_send
                                 .send(ChamomileSendMessage::StableConnect(tid, peer.into(), data))
@@ -444,60 +549,57 @@ pub mod prelude {
                             warn!("Own message has no Result");
                         }
                     },
-                    #[cfg(any(feature = "single", feature = "std"))]
-                    SendMessage::Group(msg) => {
-                        let groups_lock = my_groups_1.read().await;
-                        if groups_lock.len() == 0 {
-                            drop(groups_lock);
+                    unified::SendMessage::Group(group_id, msg) => {
+                        if !mode.has_multiple_groups()
+                            && !my_groups_1.read().await.contains(&group_id)
+                        {
+                            warn!("Group {} is not in {} mode", group_id, mode.to_str());
                             continue;
                         }
-                        let default_group_id = groups_lock[0].clone();
-                        drop(groups_lock);
 
-                        group_handle_send(default_group_id, &p2p_send, msg)
+                        group_handle_send(group_id, &versions_1, &p2p_send, msg)
                             .await
                             .map_err(|e| error!("Chamomile channel: {:?}", e))
                             .expect("Chamomile channel closed");
                     }
-                    #[cfg(any(feature = "multiple", feature = "full"))]
-                    SendMessage::Group(group_id, msg) => {
-                        group_handle_send(group_id, &p2p_send, msg)
-                            .await
-                            .map_err(|e| error!("Chamomile channel: {:?}", e))
-                            .expect("Chamomile channel closed");
-                    }
-                    SendMessage::Rpc(uid, param, is_ws) => {
+                    unified::SendMessage::Rpc(uid, param, is_ws) => {
                         if let Some(ref rpc_send) = rpc_send {
-                            rpc_send
-                                .send(RpcSendMessage(uid, param, is_ws))
-                                .await
-                                .map_err(|e| error!("Rpc channel: {:?}", e))
-                                .expect("Rpc channel closed");
+                            // the rpc is down after shutdown, stop forwarding.
+                            if let Err(e) =
+                                rpc_send.send(RpcSendMessage::Rpc(uid, param, is_ws)).await
+                            {
+                                error!("Rpc channel: {:?}", e);
+                                break;
+                            }
                         }
                     }
-                    #[cfg(feature = "std")]
-                    SendMessage::Layer(tgid, msg) => {
-                        let groups_lock = my_groups_1.read().await;
-                        if groups_lock.len() == 0 {
-                            drop(groups_lock);
-                            continue;
+                    unified::SendMessage::Publish(topic, param) => {
+                        if let Some(ref rpc_send) = rpc_send {
+                            // the rpc is down after shutdown, stop forwarding.
+                            if let Err(e) =
+                                rpc_send.send(RpcSendMessage::Publish(topic, param)).await
+                            {
+                                error!("Rpc channel: {:?}", e);
+                                break;
+                            }
                         }
-                        let default_group_id = groups_lock[0].clone();
-                        drop(groups_lock);
-
-                        layer_handle_send(default_group_id, tgid, &p2p_send, msg)
-                            .await
-                            .map_err(|e| error!("Chamomile channel: {:?}", e))
-                            .expect("Chamomile channel closed");
                     }
-                    #[cfg(feature = "full")]
-                    SendMessage::Layer(fgid, tgid, msg) => {
-                        layer_handle_send(fgid, tgid, &p2p_send, msg)
+                    unified::SendMessage::Layer(fgid, tgid, msg) => {
+                        // the key is required when start with layers.
+                        let key = match key.as_ref().filter(|_| mode.has_layers()) {
+                            Some(key) => key,
+                            None => {
+                                warn!("No layers in {} mode", mode.to_str());
+                                continue;
+                            }
+                        };
+
+                        layer_handle_send(fgid, tgid, key, &versions_1, &p2p_send, msg)
                             .await
                             .map_err(|e| error!("Chamomile channel: {:?}", e))
                             .expect("Chamomile channel closed");
                     }
-                    SendMessage::Network(nmsg) => match nmsg {
+                    unified::SendMessage::Network(nmsg) => match nmsg {
                         NetworkType::Broadcast(broadcast, data) => {
                             // broadcast use default_group_id.
                             let mut bytes = vec![];
@@ -544,12 +646,14 @@ pub mod prelude {
                                 .expect("Chamomile channel closed");
                         }
                         NetworkType::NetworkStop => {
-                            warn!("Start stop chamomile...");
-                            let _ = p2p_send.send(ChamomileSendMessage::NetworkStop).await;
-                            listen_task.abort();
-                            break;
+                            // shutdown all, include rpc.
+                            signal.trigger();
+                        }
+                        NetworkType::AddGroup(_) | NetworkType::DelGroup(_)
+                            if !mode.has_multiple_groups() =>
+                        {
+                            warn!("Only one group in {} mode", mode.to_str());
                         }
-                        #[cfg(any(feature = "multiple", feature = "full"))]
                         NetworkType::AddGroup(gid) => {
                             let mut group_lock = my_groups_1.write().await;
                             if !group_lock.contains(&gid) {
@@ -557,7 +661,6 @@ pub mod prelude {
                             }
                             drop(group_lock);
                         }
-                        #[cfg(any(feature = "multiple", feature = "full"))]
                         NetworkType::DelGroup(gid) => {
                             let mut group_lock = my_groups_1.write().await;
                             let mut need_remove: Vec<usize> = vec![];
@@ -578,4 +681,19 @@ pub mod prelude {
 
         Ok(peer_id)
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_node_key() {
+            let path = PathBuf::from("./.test_node_key");
+            let _ = std::fs::remove_dir_all(&path);
+            let key = node_key(&path).await.unwrap();
+            let same = node_key(&path).await.unwrap();
+            assert_eq!(key.peer_id(), same.peer_id());
+            std::fs::remove_dir_all(path).unwrap();
+        }
+    }
 }
@@ -0,0 +1,114 @@
+use tdn_types::{
+    group::GroupId,
+    message::RecvType,
+    primitives::Result,
+    unified::{Mode, ReceiveMessage},
+};
+use tokio::sync::mpsc::Sender;
+
+use crate::group::group_handle_recv;
+use crate::layer::{layer_handle_recv, LayerNonces};
+
+/// where the received frame goes.
+#[derive(Debug)]
+pub enum Route {
+    /// to `ReceiveMessage::Own`.
+    Own(RecvType),
+    /// to `ReceiveMessage::Group`, params: group id, msg.
+    Group(GroupId, RecvType),
+    /// to `ReceiveMessage::Layer`, params: sender's, receiver's, msg.
+    /// the signed data will be verified, no layers in single/multiple mode.
+    Layer(GroupId, GroupId, RecvType),
+    /// rejected, or the router handled it (e.g. sended to custom handlers).
+    Drop,
+}
+
+/// the router of the frames (connect, result, event, stream, delivery)
+/// received from p2p network. it can rewrite or reject the frame.
+/// it is called in the receive loop, so do not block it.
+pub trait Router: Send + Sync {
+    /// route the frame from `fgid` to `tgid`, `groups` is our groups now.
+    fn route(&self, fgid: GroupId, tgid: GroupId, msg: RecvType, groups: &[GroupId]) -> Route;
+}
+
+/// the default router: if from/to is same group of ours, it is group's,
+/// otherwise it is layer's.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultRouter;
+
+impl Router for DefaultRouter {
+    fn route(&self, fgid: GroupId, tgid: GroupId, msg: RecvType, groups: &[GroupId]) -> Route {
+        if groups.is_empty() {
+            return Route::Drop;
+        }
+
+        if fgid == tgid && groups.contains(&fgid) {
+            Route::Group(fgid, msg)
+        } else if let RecvType::Delivery(..) = msg {
+            // the delivery is of our sended frame.
+            Route::Layer(tgid, fgid, msg)
+        } else {
+            Route::Layer(fgid, tgid, msg)
+        }
+    }
+}
+
+/// send the routed message to outside, the mode without layers drops them.
+pub(crate) async fn route_handle(
+    route: Route,
+    mode: Mode,
+    nonces: &LayerNonces,
+    out_send: &Sender<ReceiveMessage>,
+) -> Result<()> {
+    match route {
+        Route::Own(msg) => {
+            let _ = out_send
+                .send(ReceiveMessage::Own(msg))
+                .await
+                .map_err(|e| error!("Outside channel: {:?}", e));
+        }
+        Route::Group(gid, msg) => group_handle_recv(&gid, out_send, msg).await?,
+        Route::Layer(fgid, tgid, msg) => {
+            if mode.has_layers() {
+                layer_handle_recv(fgid, tgid, nonces, out_send, msg).await?
+            } else {
+                debug!("No layers in {} mode, drop the message", mode.to_str());
+            }
+        }
+        Route::Drop => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tdn_types::primitives::{DeliveryType, PeerId};
+
+    #[test]
+    fn test_default_router() {
+        let router = DefaultRouter;
+        let event = || RecvType::Event(PeerId::default(), vec![]);
+
+        assert!(matches!(router.route(1, 1, event(), &[]), Route::Drop));
+        assert!(matches!(
+            router.route(1, 1, event(), &[1]),
+            Route::Group(1, _)
+        ));
+        assert!(matches!(
+            router.route(2, 1, event(), &[1]),
+            Route::Layer(2, 1, _)
+        ));
+        assert!(matches!(
+            router.route(2, 2, event(), &[1]),
+            Route::Layer(2, 2, _)
+        ));
+
+        let delivery = RecvType::Delivery(DeliveryType::Event, 1, true);
+        assert!(matches!(
+            router.route(1, 2, delivery, &[1]),
+            Route::Layer(2, 1, _)
+        ));
+    }
+}
@@ -1,25 +1,44 @@
+mod auth;
 mod channel;
+pub mod client;
 mod http;
+mod limit;
+mod queue;
+mod subscription;
+mod tls;
 mod ws;
 
+pub use auth::RpcAuth;
+pub use limit::{RateLimit, RpcLimits};
+pub use queue::{SlowPolicy, METRICS_METHOD};
+pub use subscription::{SUBSCRIBE_METHOD, SUBSCRIPTION_METHOD, UNSUBSCRIBE_METHOD};
+pub use tls::RpcTlsConfig;
+
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::{
     net::TcpListener,
     select,
     sync::{
-        mpsc::{self, Receiver, Sender},
+        mpsc::{self, error::TrySendError, Receiver, Sender},
         oneshot,
     },
     time::timeout,
 };
 
+use crate::shutdown::ShutdownSignal;
+use auth::{has_scopes, AuthNonces};
+use limit::Limiter;
+use queue::Connections;
+use subscription::{subscription_notification, Subscriptions};
 use tdn_types::{
-    message::{ReceiveMessage, RpcSendMessage},
+    message::RpcSendMessage,
     primitives::{new_io_error, Result},
-    rpc::RpcParam,
+    rpc::{json, RpcError, RpcParam},
+    unified::ReceiveMessage,
 };
 
 pub type ChannelAddr = (Sender<RpcParam>, Receiver<ChannelMessage>);
@@ -29,6 +48,56 @@ pub struct RpcConfig {
     pub ws: Option<SocketAddr>,
     pub channel: Option<ChannelAddr>,
     pub index: Option<PathBuf>,
+    /// the directory of static assets, served by HTTP GET.
+    pub static_dir: Option<PathBuf>,
+    /// max time of reading a HTTP request, the WS handshake, or waiting the
+    /// response of app, default is 30s.
+    pub http_read_timeout: Duration,
+    /// max time of waiting next request in keep-alive connection, default is 60s.
+    pub http_idle_timeout: Duration,
+    /// max HTTP body size, larger request will get 413, default is 1MB.
+    pub http_max_body_size: usize,
+    /// the authentication of HTTP and WS requests, default is none.
+    pub auth: RpcAuth,
+    /// the TLS of HTTP and WS listeners, default is none (plain TCP).
+    pub tls: Option<RpcTlsConfig>,
+    /// the queue size of every WS and channel connection, default is 128.
+    pub queue_size: usize,
+    /// the policy when the connection's queue is full, default is drop message.
+    pub slow_policy: SlowPolicy,
+    /// the connections and requests limits of HTTP and WS, default is unlimited.
+    pub limits: RpcLimits,
+    /// the path of HTTP SSE endpoint (e.g. "/events"), default is closed.
+    pub sse_path: Option<String>,
+    /// the scopes of built-in methods (metrics, subscribe, unsubscribe),
+    /// the request need one of them, default is empty (no scope needed).
+    pub builtin_scopes: HashMap<String, Vec<String>>,
+    /// the allowed origins of HTTP CORS, default is empty: all origins (`*`)
+    /// when no auth, none when auth is enabled.
+    pub cors_origins: Vec<String>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            http: None,
+            ws: None,
+            channel: None,
+            index: None,
+            static_dir: None,
+            http_read_timeout: Duration::from_secs(30),
+            http_idle_timeout: Duration::from_secs(60),
+            http_max_body_size: 1024 * 1024,
+            auth: RpcAuth::None,
+            tls: None,
+            queue_size: 128,
+            slow_policy: SlowPolicy::Drop,
+            limits: RpcLimits::default(),
+            sse_path: None,
+            builtin_scopes: HashMap::new(),
+            cors_origins: vec![],
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -39,8 +108,8 @@ pub enum RpcMessage {
     Response(RpcParam),
 }
 
-fn rpc_channel() -> (Sender<RpcMessage>, Receiver<RpcMessage>) {
-    mpsc::channel(128)
+fn rpc_channel(size: usize) -> (Sender<RpcMessage>, Receiver<RpcMessage>) {
+    mpsc::channel(size)
 }
 
 fn rpc_send_channel() -> (Sender<RpcSendMessage>, Receiver<RpcSendMessage>) {
@@ -95,13 +164,17 @@ impl ChannelRpcSender {
 pub(crate) async fn start(
     config: RpcConfig,
     send: Sender<ReceiveMessage>,
+    signal: ShutdownSignal,
 ) -> Result<Sender<RpcSendMessage>> {
     let (out_send, out_recv) = rpc_send_channel();
 
-    let (self_send, self_recv) = rpc_channel();
+    let (self_send, self_recv) = rpc_channel(128);
 
-    server(self_send, config).await?;
-    listen(send, out_recv, self_recv).await?;
+    let policy = config.slow_policy;
+    let scopes = config.builtin_scopes.clone();
+    let limiter = Arc::new(Limiter::new(config.limits.clone()));
+    server(self_send, config, limiter.clone(), signal.clone()).await?;
+    listen(send, out_recv, self_recv, policy, scopes, limiter, signal).await?;
 
     Ok(out_send)
 }
@@ -115,10 +188,17 @@ async fn listen(
     send: Sender<ReceiveMessage>,
     mut out_recv: Receiver<RpcSendMessage>,
     mut self_recv: Receiver<RpcMessage>,
+    policy: SlowPolicy,
+    scopes: HashMap<String, Vec<String>>,
+    limiter: Arc<Limiter>,
+    signal: ShutdownSignal,
 ) -> Result<()> {
     tokio::spawn(async move {
-        let mut ws_connections: HashMap<u64, Sender<RpcMessage>> = HashMap::new();
+        // the dispatcher is down when all connections closed.
+        let _signal = signal;
+        let mut ws_connections = Connections::new(policy);
         let mut sync_connections: HashMap<u64, oneshot::Sender<RpcMessage>> = HashMap::new();
+        let mut subscriptions = Subscriptions::default();
 
         loop {
             let res = select! {
@@ -127,18 +207,19 @@ async fn listen(
             };
 
             match res {
-                Some(FutureResult::Out(msg)) => {
-                    let RpcSendMessage(id, params, is_ws) = msg;
+                Some(FutureResult::Out(RpcSendMessage::Publish(topic, params))) => {
+                    for (sub, conn) in subscriptions.subscribers(&topic) {
+                        let msg = subscription_notification(*sub, &topic, params.clone());
+                        ws_connections.push(*conn, msg);
+                    }
+                }
+                Some(FutureResult::Out(RpcSendMessage::Rpc(id, params, is_ws))) => {
                     if is_ws {
                         if id == 0 {
                             // default send to all ws.
-                            for s in ws_connections.values() {
-                                let _ = s.send(RpcMessage::Response(params.clone())).await;
-                            }
+                            ws_connections.broadcast(params);
                         } else {
-                            if let Some(s) = ws_connections.get(&id) {
-                                let _ = s.send(RpcMessage::Response(params)).await;
-                            }
+                            ws_connections.push(id, params);
                         }
                     } else {
                         let s = sync_connections.remove(&id);
@@ -150,21 +231,108 @@ async fn listen(
                 Some(FutureResult::Stream(msg)) => {
                     match msg {
                         RpcMessage::Request(id, params, sender) => {
+                            // built-in methods are answered here, check the scopes first.
+                            let method = params["method"].as_str().unwrap_or("");
+                            let required = scopes.get(method).filter(|s| !s.is_empty());
+                            if let Some(required) = required {
+                                if !has_scopes(&params, required) {
+                                    if let Some(rid) = params.get("id") {
+                                        let mut res = RpcError::Unauthorized(method.to_owned())
+                                            .json(rid.clone());
+                                        if let Some(gid) = params.get("gid") {
+                                            res["gid"] = gid.clone();
+                                        }
+                                        match sender {
+                                            Some(sender) => {
+                                                let _ = sender.send(RpcMessage::Response(res));
+                                            }
+                                            None => {
+                                                ws_connections.push(id, res);
+                                            }
+                                        }
+                                    }
+                                    continue;
+                                }
+                            }
+
+                            if params["method"] == METRICS_METHOD {
+                                let metrics = json!({
+                                    "inbound": self_recv.len(),
+                                    "outbound": out_recv.len(),
+                                    "outside": send.max_capacity() - send.capacity(),
+                                    "pending": sync_connections.len(),
+                                    "subscriptions": subscriptions.count(),
+                                    "connections": ws_connections.metrics(),
+                                    "limits": limiter.metrics(),
+                                });
+                                if let Some(res) = builtin_response(&params, metrics) {
+                                    match sender {
+                                        Some(sender) => {
+                                            let _ = sender.send(RpcMessage::Response(res));
+                                        }
+                                        None => {
+                                            ws_connections.push(id, res);
+                                        }
+                                    }
+                                }
+                                continue;
+                            }
+
+                            // topic subscriptions of WS and channel connections.
+                            if sender.is_none() && ws_connections.contains(id) {
+                                if let Some(res) = subscriptions.handle(id, &params) {
+                                    if !res.is_null() {
+                                        ws_connections.push(id, res);
+                                    }
+                                    continue;
+                                }
+                            }
+
+                            // not wait the outside, reply busy when it is full.
+                            let busy = params.get("id").map(|rid| {
+                                let mut res = RpcError::Busy.json(rid.clone());
+                                if let Some(gid) = params.get("gid") {
+                                    res["gid"] = gid.clone();
+                                }
+                                res
+                            });
                             let is_ws = sender.is_none();
-                            if !is_ws {
-                                sync_connections.insert(id, sender.unwrap());
+                            match send.try_send(ReceiveMessage::Rpc(id, params, is_ws)) {
+                                Ok(()) => {
+                                    if let Some(sender) = sender {
+                                        sync_connections.insert(id, sender);
+                                    }
+                                }
+                                Err(TrySendError::Full(_)) => {
+                                    warn!(
+                                        "TDN: outside channel is full, RPC request {} is busy",
+                                        id
+                                    );
+                                    match (sender, busy) {
+                                        (Some(sender), Some(res)) => {
+                                            let _ = sender.send(RpcMessage::Response(res));
+                                        }
+                                        (None, Some(res)) => {
+                                            ws_connections.push(id, res);
+                                        }
+                                        // the notification has no response.
+                                        _ => {}
+                                    }
+                                }
+                                Err(TrySendError::Closed(_)) => {
+                                    error!("TDN: outside channel closed, RPC dispatcher stopped");
+                                    break;
+                                }
                             }
-                            send.send(ReceiveMessage::Rpc(id, params, is_ws))
-                                .await
-                                .expect("Rpc to Outside channel closed");
                         }
                         RpcMessage::Open(id, sender) => {
-                            ws_connections.insert(id, sender);
+                            ws_connections.open(id, sender);
                         }
                         RpcMessage::Close(id) => {
                             // clear this id
-                            ws_connections.remove(&id);
+                            ws_connections.close(id);
                             sync_connections.remove(&id);
+                            subscriptions.close(id);
                         }
                         _ => {} // others not handle
                     }
@@ -177,9 +345,50 @@ async fn listen(
     Ok(())
 }
 
-async fn server(send: Sender<RpcMessage>, config: RpcConfig) -> Result<()> {
+/// the response of built-in method, none if the request is notification.
+fn builtin_response(params: &RpcParam, result: RpcParam) -> Option<RpcParam> {
+    let mut res = json!({
+        "jsonrpc": "2.0",
+        "id": params.get("id")?,
+        "method": params["method"],
+        "result": result,
+    });
+    if let Some(gid) = params.get("gid") {
+        res["gid"] = gid.clone();
+    }
+    Some(res)
+}
+
+async fn server(
+    send: Sender<RpcMessage>,
+    config: RpcConfig,
+    limiter: Arc<Limiter>,
+    signal: ShutdownSignal,
+) -> Result<()> {
+    // HTTPS and WSS
+    let acceptor = match &config.tls {
+        Some(tls) => Some(tls.acceptor().await?),
+        None => None,
+    };
+
+    // the hmac nonces are shared by HTTP and WS.
+    let nonces = Arc::new(AuthNonces::default());
+
     // HTTP blind
     if let Some(http) = config.http {
+        let options = http::HttpOptions {
+            read_timeout: config.http_read_timeout,
+            idle_timeout: config.http_idle_timeout,
+            max_body_size: config.http_max_body_size,
+            static_dir: config.static_dir.clone(),
+            auth: config.auth.clone(),
+            nonces: nonces.clone(),
+            limiter: limiter.clone(),
+            sse_path: config.sse_path.clone(),
+            queue_size: config.queue_size,
+            sse_sessions: Default::default(),
+            cors_origins: config.cors_origins.clone(),
+        };
         tokio::spawn(http::http_listen(
             config.index.clone(),
             send.clone(),
@@ -187,6 +396,9 @@ async fn server(send: Sender<RpcMessage>, config: RpcConfig) -> Result<()> {
                 error!("RPC HTTP listen {:?}", e);
                 std::io::Error::new(std::io::ErrorKind::Other, "TCP Listen")
             })?,
+            options,
+            acceptor.clone(),
+            signal.clone(),
         ));
     }
 
@@ -198,12 +410,25 @@ async fn server(send: Sender<RpcMessage>, config: RpcConfig) -> Result<()> {
                 error!("RPC WS listen {:?}", e);
                 std::io::Error::new(std::io::ErrorKind::Other, "TCP Listen")
             })?,
+            config.auth.clone(),
+            nonces,
+            acceptor,
+            config.http_read_timeout,
+            config.queue_size,
+            limiter,
+            signal.clone(),
         ));
     }
 
     // Channel
     if let Some((out_send, my_recv)) = config.channel {
-        tokio::spawn(channel::channel_listen(send, out_send, my_recv));
+        tokio::spawn(channel::channel_listen(
+            send,
+            out_send,
+            my_recv,
+            config.queue_size,
+            signal,
+        ));
     }
 
     Ok(())
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tdn_types::rpc::{json, RpcParam};
+
+/// max seconds between the signed timestamp and now.
+const MAX_TIMESTAMP_SKEW: u64 = 300;
+
+/// max length of the client nonce.
+const MAX_NONCE_LENGTH: usize = 64;
+
+/// max seen nonces in the skew window, the new ones are rejected when full.
+const MAX_NONCES: usize = 100_000;
+
+/// all scopes, can call all methods.
+pub(crate) const ALL_SCOPES: &str = "*";
+
+/// Authentication of the HTTP and WS rpc requests.
+#[derive(Clone, Debug, Default)]
+pub enum RpcAuth {
+    /// no authentication, all requests have all scopes.
+    #[default]
+    None,
+    /// `Authorization: Bearer <token>` (WS can also use `?token=<token>`),
+    /// every token has its scopes.
+    Bearer(HashMap<String, Vec<String>>),
+    /// `X-Timestamp: <unix seconds>`, `X-Nonce: <nonce>` and `X-Signature: <hex>`,
+    /// the signature is blake3 keyed hash of (timestamp, nonce, path, body),
+    /// see `RpcAuth::sign`. the nonce is used once in the skew window.
+    /// WS (no body) use `?timestamp=<unix seconds>&nonce=<nonce>&signature=<hex>`.
+    Hmac([u8; 32], Vec<String>),
+}
+
+impl RpcAuth {
+    /// the bearer token derived from the config secret.
+    pub fn token(secret: &[u8; 32]) -> String {
+        blake3::Hash::from(blake3::derive_key("tdn rpc bearer token", secret))
+            .to_hex()
+            .to_string()
+    }
+
+    /// the hmac key derived from the config secret.
+    pub fn hmac_key(secret: &[u8; 32]) -> [u8; 32] {
+        blake3::derive_key("tdn rpc hmac key", secret)
+    }
+
+    /// bearer authentication, the token is derived from the secret, has all scopes.
+    pub fn bearer(secret: &[u8; 32]) -> Self {
+        let mut tokens = HashMap::new();
+        tokens.insert(Self::token(secret), vec![ALL_SCOPES.to_owned()]);
+        RpcAuth::Bearer(tokens)
+    }
+
+    /// hmac authentication, the key is derived from the secret, has all scopes.
+    pub fn hmac(secret: &[u8; 32]) -> Self {
+        RpcAuth::Hmac(Self::hmac_key(secret), vec![ALL_SCOPES.to_owned()])
+    }
+
+    /// sign the request with hmac key, return the hex signature. the nonce is
+    /// 1-64 chars of `[A-Za-z0-9_-]`, and the path has no query.
+    pub fn sign(key: &[u8; 32], timestamp: u64, nonce: &str, path: &str, body: &[u8]) -> String {
+        blake3::keyed_hash(key, &message(timestamp, nonce, path, body))
+            .to_hex()
+            .to_string()
+    }
+
+    /// check the request, return the scopes of it, none if unauthorized.
+    pub(crate) fn check(&self, nonces: &AuthNonces, req: AuthRequest) -> Option<Vec<String>> {
+        match self {
+            RpcAuth::None => Some(vec![ALL_SCOPES.to_owned()]),
+            RpcAuth::Bearer(tokens) => {
+                // compare the hashes, constant time.
+                let token = blake3::hash(req.token?.as_bytes());
+                tokens
+                    .iter()
+                    .find(|(t, _)| blake3::hash(t.as_bytes()) == token)
+                    .map(|(_, scopes)| scopes.clone())
+            }
+            RpcAuth::Hmac(key, scopes) => {
+                let timestamp = req.timestamp?.trim().parse::<u64>().ok()?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                if now.abs_diff(timestamp) > MAX_TIMESTAMP_SKEW {
+                    return None;
+                }
+
+                let nonce = req.nonce?.trim();
+                if !is_nonce(nonce) {
+                    return None;
+                }
+                let signature = blake3::Hash::from_hex(req.signature?.trim()).ok()?;
+                let msg = message(timestamp, nonce, req.path, req.body);
+                if blake3::keyed_hash(key, &msg) == signature && nonces.insert(nonce, now) {
+                    Some(scopes.clone())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// the credentials from headers or ws url query, and the signed content.
+#[derive(Default)]
+pub(crate) struct AuthRequest<'a> {
+    pub token: Option<&'a str>,
+    pub timestamp: Option<&'a str>,
+    pub nonce: Option<&'a str>,
+    pub signature: Option<&'a str>,
+    /// the request path without query.
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+/// the seen nonces of hmac requests, the replayed request is rejected.
+/// the nonces out of the skew window are cleared when full.
+#[derive(Debug, Default)]
+pub(crate) struct AuthNonces(Mutex<HashMap<String, u64>>);
+
+impl AuthNonces {
+    /// record the nonce, false if it is seen or full.
+    fn insert(&self, nonce: &str, now: u64) -> bool {
+        let mut nonces = self.0.lock().unwrap();
+        if nonces.contains_key(nonce) {
+            return false;
+        }
+        if nonces.len() >= MAX_NONCES {
+            nonces.retain(|_, seen| now.abs_diff(*seen) <= MAX_TIMESTAMP_SKEW * 2);
+            if nonces.len() >= MAX_NONCES {
+                return false;
+            }
+        }
+        nonces.insert(nonce.to_owned(), now);
+        true
+    }
+}
+
+fn is_nonce(nonce: &str) -> bool {
+    !nonce.is_empty()
+        && nonce.len() <= MAX_NONCE_LENGTH
+        && nonce
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// the signed message, the parts are split by newline.
+fn message(timestamp: u64, nonce: &str, path: &str, body: &[u8]) -> Vec<u8> {
+    let mut msg = format!("{}\n{}\n{}\n", timestamp, nonce, path).into_bytes();
+    msg.extend(body);
+    msg
+}
+
+/// get the value of the key in url query.
+pub(crate) fn query_value<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query.split('&').find_map(|kv| {
+        let mut kv = kv.splitn(2, '=');
+        if kv.next()? == key {
+            kv.next()
+        } else {
+            None
+        }
+    })
+}
+
+/// inject the connection's session to the request (and every request in
+/// batch), it will overwrite the session from client.
+pub(crate) fn inject_session(param: &mut RpcParam, id: u64, scopes: &[String]) {
+    match param {
+        RpcParam::Array(params) => {
+            for p in params.iter_mut() {
+                inject_session(p, id, scopes);
+            }
+        }
+        RpcParam::Object(map) => {
+            map.insert("session".to_owned(), json!({ "id": id, "scopes": scopes }));
+        }
+        _ => {}
+    }
+}
+
+/// check the request's session has one of the scopes.
+/// no session means the request is from channel, not from HTTP or WS.
+pub(crate) fn has_scopes(param: &RpcParam, scopes: &[String]) -> bool {
+    match param.get("session") {
+        Some(session) => session["scopes"]
+            .as_array()
+            .map(|ss| {
+                ss.iter()
+                    .filter_map(|s| s.as_str())
+                    .any(|s| s == ALL_SCOPES || scopes.iter().any(|scope| scope == s))
+            })
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_auth() {
+        let nonces = AuthNonces::default();
+        let secret = [1u8; 32];
+        let auth = RpcAuth::bearer(&secret);
+        let token = RpcAuth::token(&secret);
+        let req = |token| AuthRequest {
+            token,
+            ..Default::default()
+        };
+        assert_eq!(
+            auth.check(&nonces, req(Some(&token))),
+            Some(vec!["*".to_owned()])
+        );
+        assert!(auth.check(&nonces, req(Some("bad"))).is_none());
+        assert!(auth.check(&nonces, req(None)).is_none());
+
+        let auth = RpcAuth::hmac(&secret);
+        let key = RpcAuth::hmac_key(&secret);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let ts = now.to_string();
+        let sign = RpcAuth::sign(&key, now, "n1", "/", b"body");
+        let req = |nonce, path, body| AuthRequest {
+            timestamp: Some(&ts),
+            nonce: Some(nonce),
+            signature: Some(&sign),
+            path,
+            body,
+            ..Default::default()
+        };
+        assert!(auth.check(&nonces, req("n1", "/", b"other")).is_none());
+        assert!(auth.check(&nonces, req("n1", "/admin", b"body")).is_none());
+        assert!(auth.check(&nonces, req("n2", "/", b"body")).is_none());
+        assert!(auth.check(&nonces, req("n1", "/", b"body")).is_some());
+        // replayed.
+        assert!(auth.check(&nonces, req("n1", "/", b"body")).is_none());
+
+        let sign = RpcAuth::sign(&key, now, "n:1", "/", b"body");
+        let req = AuthRequest {
+            timestamp: Some(&ts),
+            nonce: Some("n:1"),
+            signature: Some(&sign),
+            path: "/",
+            body: b"body",
+            ..Default::default()
+        };
+        assert!(auth.check(&nonces, req).is_none());
+
+        let old = (now - 1000).to_string();
+        let sign = RpcAuth::sign(&key, now - 1000, "n3", "/", b"body");
+        let req = AuthRequest {
+            timestamp: Some(&old),
+            nonce: Some("n3"),
+            signature: Some(&sign),
+            path: "/",
+            body: b"body",
+            ..Default::default()
+        };
+        assert!(auth.check(&nonces, req).is_none());
+
+        assert_eq!(query_value("a=1&token=abc", "token"), Some("abc"));
+        assert_eq!(query_value("a=1", "token"), None);
+    }
+
+    #[test]
+    fn test_has_scopes() {
+        let scopes = vec!["admin".to_owned()];
+        let mut param = json!({"method": "rpc.metrics"});
+        assert!(has_scopes(&param, &scopes));
+        inject_session(&mut param, 1, &["read".to_owned()]);
+        assert!(!has_scopes(&param, &scopes));
+        inject_session(&mut param, 1, &["admin".to_owned()]);
+        assert!(has_scopes(&param, &scopes));
+        inject_session(&mut param, 1, &[ALL_SCOPES.to_owned()]);
+        assert!(has_scopes(&param, &scopes));
+    }
+}
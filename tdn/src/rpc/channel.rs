@@ -2,33 +2,42 @@ use rand_chacha::{
     rand_core::{RngCore, SeedableRng},
     ChaChaRng,
 };
-use tdn_types::{primitives::Result, rpc::RpcParam};
+use tdn_types::{
+    primitives::Result,
+    rpc::{is_notification, RpcParam},
+};
 use tokio::{
     select,
     sync::mpsc::{Receiver, Sender},
 };
 
+use crate::shutdown::ShutdownSignal;
+
 use super::{rpc_channel, ChannelMessage, RpcMessage};
 
 enum FutureResult {
     Out(RpcMessage),
     Stream(ChannelMessage),
+    Shutdown,
 }
 
 pub(super) async fn channel_listen(
     send: Sender<RpcMessage>,
     out_send: Sender<RpcParam>,
     mut my_recv: Receiver<ChannelMessage>,
+    queue_size: usize,
+    mut signal: ShutdownSignal,
 ) -> Result<()> {
     let mut rng = ChaChaRng::from_entropy();
     let id: u64 = rng.next_u64();
-    let (s_send, mut s_recv) = rpc_channel();
+    let (s_send, mut s_recv) = rpc_channel(queue_size);
     send.send(RpcMessage::Open(id, s_send)).await?;
 
     loop {
         let res = select! {
             v = async { s_recv.recv().await.map(FutureResult::Out) } => v,
             v = async { my_recv.recv().await.map(FutureResult::Stream) } => v,
+            _ = signal.wait() => Some(FutureResult::Shutdown),
         };
 
         match res {
@@ -41,6 +50,12 @@ pub(super) async fn channel_listen(
             }
             Some(FutureResult::Stream(msg)) => match msg {
                 ChannelMessage::Sync(msg, tx) => {
+                    if is_notification(&msg) {
+                        // notifications no response.
+                        send.send(RpcMessage::Request(id, msg, None)).await?;
+                        let _ = tx.send(RpcMessage::Response(RpcParam::Null));
+                        continue;
+                    }
                     let id: u64 = rng.next_u64();
                     send.send(RpcMessage::Request(id, msg, Some(tx))).await?;
                 }
@@ -48,6 +63,13 @@ pub(super) async fn channel_listen(
                     send.send(RpcMessage::Request(id, msg, None)).await?;
                 }
             },
+            Some(FutureResult::Shutdown) => {
+                // send the queued responses, then close.
+                while let Ok(RpcMessage::Response(param)) = s_recv.try_recv() {
+                    let _ = out_send.send(param).await;
+                }
+                break;
+            }
             None => break,
         }
     }
@@ -0,0 +1,811 @@
+//! JSON-RPC client of the TDN node, over HTTP, WebSocket and channel.
+//!
+//! ``` ignore
+//! let client = RpcClient::ws("ws://127.0.0.1:7366", Default::default()).await?;
+//! let result = client.request("echo", vec![json!("hello")]).await?;
+//! let mut pushes = client.subscribe()?;
+//! while let Some(param) = pushes.recv().await {
+//!     println!("{}", param);
+//! }
+//! ```
+use futures_util::{SinkExt, StreamExt};
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaChaRng,
+};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    select,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot,
+    },
+    time::{sleep, timeout},
+};
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig},
+    TlsConnector,
+};
+use tokio_tungstenite::{
+    client_async,
+    tungstenite::{
+        client::IntoClientRequest,
+        http::{HeaderValue, Uri},
+        protocol::Message as WsMessage,
+    },
+    WebSocketStream,
+};
+
+use tdn_types::{
+    primitives::{new_io_error, Result},
+    rpc::{is_notification, json, parse_response, rpc_request, RpcParam},
+};
+
+use super::{ChannelRpcSender, RpcAuth, SUBSCRIBE_METHOD, SUBSCRIPTION_METHOD, UNSUBSCRIBE_METHOD};
+
+/// the options of rpc client.
+#[derive(Clone, Debug)]
+pub struct RpcClientOptions {
+    /// max time of every request, default is 30s.
+    pub timeout: Duration,
+    /// the bearer token, see `RpcAuth::Bearer`.
+    pub token: Option<String>,
+    /// the hmac key, see `RpcAuth::Hmac`.
+    pub hmac_key: Option<[u8; 32]>,
+    /// the TLS config, need when use https or wss.
+    pub tls: Option<Arc<ClientConfig>>,
+    /// max waiting time between WS reconnections, default is 30s.
+    pub max_reconnect_delay: Duration,
+}
+
+impl Default for RpcClientOptions {
+    fn default() -> Self {
+        RpcClientOptions {
+            timeout: Duration::from_secs(30),
+            token: None,
+            hmac_key: None,
+            tls: None,
+            max_reconnect_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type WsStream = WebSocketStream<Box<dyn Stream>>;
+
+/// the request and the waiter of its response (none if notification).
+type WsCommand = (RpcParam, Option<(RpcParam, oneshot::Sender<RpcParam>)>);
+
+/// the id prefix of the subscribe requests sent again after reconnection.
+const RESUBSCRIBE_ID: &str = "tdn.resubscribe:";
+
+type Subscribers = Arc<Mutex<Vec<Sender<RpcParam>>>>;
+
+enum Transport {
+    Http(Uri),
+    Ws(Sender<WsCommand>),
+    Channel(ChannelRpcSender),
+}
+
+/// JSON-RPC client, the requests and responses are correlated by id.
+pub struct RpcClient {
+    transport: Transport,
+    options: RpcClientOptions,
+    next_id: AtomicU64,
+    subscribers: Subscribers,
+}
+
+impl RpcClient {
+    /// HTTP client, url likes `http://127.0.0.1:7365` or `https://...`.
+    pub fn http(url: &str, options: RpcClientOptions) -> Result<Self> {
+        let uri: Uri = url.parse()?;
+        Ok(Self::new(Transport::Http(uri), options))
+    }
+
+    /// WebSocket client, url likes `ws://127.0.0.1:7366` or `wss://...`.
+    /// it will reconnect when the connection lost.
+    pub async fn ws(url: &str, options: RpcClientOptions) -> Result<Self> {
+        let stream = ws_connect(url, &options).await?;
+        let (send, recv) = mpsc::channel(128);
+        let client = Self::new(Transport::Ws(send), options);
+        tokio::spawn(ws_loop(
+            url.to_owned(),
+            client.options.clone(),
+            stream,
+            recv,
+            client.subscribers.clone(),
+        ));
+        Ok(client)
+    }
+
+    /// in-process channel client, use the channels from `channel_rpc_channel`.
+    pub fn channel(
+        sender: ChannelRpcSender,
+        mut recv: Receiver<RpcParam>,
+        options: RpcClientOptions,
+    ) -> Self {
+        let client = Self::new(Transport::Channel(sender), options);
+        let subscribers = client.subscribers.clone();
+        tokio::spawn(async move {
+            while let Some(param) = recv.recv().await {
+                publish(&subscribers, param).await;
+            }
+        });
+        client
+    }
+
+    fn new(transport: Transport, options: RpcClientOptions) -> Self {
+        Self {
+            transport,
+            options,
+            next_id: AtomicU64::new(1),
+            subscribers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// request the method, return the result.
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+    pub async fn request(&self, method: &str, params: Vec<RpcParam>) -> Result<RpcParam> {
+        let res = self
+            .call(rpc_request(self.next_id(), method, params))
+            .await?;
+        parse_result(res)
+    }
+
+    /// request the method of the group, return the result.
+    #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+    pub async fn request(
+        &self,
+        gid: tdn_types::group::GroupId,
+        method: &str,
+        params: Vec<RpcParam>,
+    ) -> Result<RpcParam> {
+        let res = self
+            .call(rpc_request(self.next_id(), method, params, gid))
+            .await?;
+        parse_result(res)
+    }
+
+    /// send the notification, no response.
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+    pub async fn notify(&self, method: &str, params: Vec<RpcParam>) -> Result<()> {
+        let mut param = rpc_request(0, method, params);
+        let _ = param.as_object_mut().map(|p| p.remove("id"));
+        self.call(param).await.map(|_| ())
+    }
+
+    /// send the notification to the group, no response.
+    #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+    pub async fn notify(
+        &self,
+        gid: tdn_types::group::GroupId,
+        method: &str,
+        params: Vec<RpcParam>,
+    ) -> Result<()> {
+        let mut param = rpc_request(0, method, params, gid);
+        let _ = param.as_object_mut().map(|p| p.remove("id"));
+        self.call(param).await.map(|_| ())
+    }
+
+    /// send the raw request (or batch), return the raw response.
+    /// notification will return null. WS responses are correlated by the id.
+    pub async fn call(&self, param: RpcParam) -> Result<RpcParam> {
+        let notification = is_notification(&param);
+        match &self.transport {
+            Transport::Http(uri) => timeout(
+                self.options.timeout,
+                http_post(uri, &self.options, param.to_string()),
+            )
+            .await
+            .map_err(|_| new_io_error("Timeout"))?,
+            Transport::Ws(sender) => {
+                if notification {
+                    sender
+                        .send((param, None))
+                        .await
+                        .map_err(|_| new_io_error("WS client closed"))?;
+                    return Ok(RpcParam::Null);
+                }
+
+                let id = param_id(&param).ok_or(new_io_error("WS request need id"))?;
+                let (tx, rx) = oneshot::channel();
+                sender
+                    .send((param, Some((id, tx))))
+                    .await
+                    .map_err(|_| new_io_error("WS client closed"))?;
+                match timeout(self.options.timeout, rx).await {
+                    Ok(Ok(res)) => Ok(res),
+                    Ok(Err(_)) => Err(new_io_error("WS connection lost").into()),
+                    Err(_) => Err(new_io_error("Timeout").into()),
+                }
+            }
+            Transport::Channel(sender) => {
+                sender
+                    .sync_send(param, self.options.timeout.as_millis() as u64)
+                    .await
+            }
+        }
+    }
+
+    /// the stream of messages pushed by the node (not the responses),
+    /// only WS and channel support it.
+    pub fn subscribe(&self) -> Result<Receiver<RpcParam>> {
+        if let Transport::Http(_) = self.transport {
+            return Err(new_io_error("HTTP not support subscription").into());
+        }
+        let (send, recv) = mpsc::channel(128);
+        self.subscribers
+            .lock()
+            .map_err(|_| new_io_error("RPC client poisoned"))?
+            .push(send);
+        Ok(recv)
+    }
+}
+
+/// the result of the response, error if the response has error.
+fn parse_result(res: RpcParam) -> Result<RpcParam> {
+    parse_response(res)
+        .map_err(|e| new_io_error(&format!("RPC error {}: {}", e["code"], e["message"])).into())
+}
+
+/// the (first) id of request, response or batch, number or string.
+fn param_id(param: &RpcParam) -> Option<RpcParam> {
+    let id = |p: &RpcParam| p.get("id").filter(|id| !id.is_null()).cloned();
+    match param {
+        RpcParam::Array(params) => params.iter().find_map(id),
+        _ => id(param),
+    }
+}
+
+async fn publish(subscribers: &Mutex<Vec<Sender<RpcParam>>>, param: RpcParam) {
+    let subs = match subscribers.lock() {
+        Ok(mut subs) => {
+            subs.retain(|s| !s.is_closed());
+            subs.clone()
+        }
+        Err(_) => return,
+    };
+    for s in subs {
+        let _ = s.send(param.clone()).await;
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// the request path, empty is the root.
+fn uri_path(uri: &Uri) -> &str {
+    match uri.path() {
+        "" => "/",
+        path => path,
+    }
+}
+
+/// the random nonce of hmac auth.
+fn nonce() -> String {
+    let mut rng = ChaChaRng::from_entropy();
+    format!("{:016x}{:016x}", rng.next_u64(), rng.next_u64())
+}
+
+/// connect the TCP (or TLS when https & wss) stream.
+async fn connect(uri: &Uri, options: &RpcClientOptions) -> Result<Box<dyn Stream>> {
+    let host = uri
+        .host()
+        .ok_or(new_io_error("RPC url missing host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let secure = matches!(uri.scheme_str(), Some("https") | Some("wss"));
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+    let stream = TcpStream::connect((host, port)).await?;
+
+    if secure {
+        let config = options
+            .tls
+            .clone()
+            .ok_or(new_io_error("RPC client missing TLS config"))?;
+        let name = ServerName::try_from(host.to_owned())?;
+        let stream = TlsConnector::from(config).connect(name, stream).await?;
+        Ok(Box::new(stream))
+    } else {
+        Ok(Box::new(stream))
+    }
+}
+
+async fn http_post(uri: &Uri, options: &RpcClientOptions, body: String) -> Result<RpcParam> {
+    let mut stream = connect(uri, options).await?;
+
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        uri.path_and_query().map(|p| p.as_str()).unwrap_or("/"),
+        uri.authority().map(|a| a.as_str()).unwrap_or(""),
+        body.len()
+    );
+    if let Some(token) = &options.token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    if let Some(key) = &options.hmac_key {
+        let (timestamp, nonce) = (now(), nonce());
+        let signature = RpcAuth::sign(key, timestamp, &nonce, uri_path(uri), body.as_bytes());
+        head.push_str(&format!(
+            "X-Timestamp: {}\r\nX-Nonce: {}\r\nX-Signature: {}\r\n",
+            timestamp, nonce, signature
+        ));
+    }
+    head.push_str("\r\n");
+    head.push_str(&body);
+    stream.write_all(head.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut buf = vec![];
+    let mut tmp = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut tmp).await?;
+        buf.extend(&tmp[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut res = httparse::Response::new(&mut headers);
+        let status = res
+            .parse(&buf)
+            .map_err(|_| new_io_error("HTTP response invalid"))?;
+        if let httparse::Status::Complete(amt) = status {
+            let code = res.code.unwrap_or(0);
+            if code == 204 {
+                return Ok(RpcParam::Null);
+            }
+            let length = res
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case("content-length"))
+                .and_then(|h| {
+                    std::str::from_utf8(h.value)
+                        .ok()?
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                });
+            let end = match length {
+                Some(length) if buf.len() >= amt + length => Some(amt + length),
+                None if n == 0 => Some(buf.len()),
+                _ => None,
+            };
+            if let Some(end) = end {
+                let body = String::from_utf8_lossy(&buf[amt..end]);
+                return body.parse::<RpcParam>().map_err(|_| {
+                    new_io_error(&format!("HTTP response {} is invalid", code)).into()
+                });
+            }
+        }
+
+        if n == 0 {
+            return Err(new_io_error("HTTP connection closed").into());
+        }
+    }
+}
+
+async fn ws_connect(url: &str, options: &RpcClientOptions) -> Result<WsStream> {
+    // ws hmac is in the url query.
+    let url = if let Some(key) = &options.hmac_key {
+        let (timestamp, nonce) = (now(), nonce());
+        let uri: Uri = url.parse()?;
+        let path = uri_path(&uri);
+        // the signed path must be in the request, `ws://host?..` has none.
+        let query = uri.query().map(|q| format!("{}&", q)).unwrap_or_default();
+        format!(
+            "{}://{}{}?{}timestamp={}&nonce={}&signature={}",
+            uri.scheme_str().unwrap_or("ws"),
+            uri.authority().map(|a| a.as_str()).unwrap_or(""),
+            path,
+            query,
+            timestamp,
+            nonce,
+            RpcAuth::sign(key, timestamp, &nonce, path, b"")
+        )
+    } else {
+        url.to_owned()
+    };
+
+    let uri: Uri = url.parse()?;
+    let mut request = uri.clone().into_client_request()?;
+    if let Some(token) = &options.token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+        request.headers_mut().insert("Authorization", value);
+    }
+    let handshake = async {
+        let stream = connect(&uri, options).await?;
+        let (ws, _) = client_async(request, stream).await?;
+        Ok(ws)
+    };
+    timeout(options.timeout, handshake)
+        .await
+        .map_err(|_| new_io_error("WS connect timeout"))?
+}
+
+async fn ws_loop(
+    url: String,
+    options: RpcClientOptions,
+    mut stream: WsStream,
+    mut recv: Receiver<WsCommand>,
+    subscribers: Subscribers,
+) {
+    // the waiters by the id (json string of it).
+    let mut pending: HashMap<String, oneshot::Sender<RpcParam>> = HashMap::new();
+    // the subscribe requests waiting the response, by the id.
+    let mut subscribing: HashMap<String, RpcParam> = HashMap::new();
+    // the active subscriptions, client's id => (subscribe request, node's id).
+    // the node's id changes after reconnection, the client's id not.
+    let mut subscriptions: HashMap<u64, (RpcParam, u64)> = HashMap::new();
+
+    loop {
+        loop {
+            select! {
+                cmd = recv.recv() => match cmd {
+                    Some((mut param, waiter)) => {
+                        match param["method"].as_str() {
+                            Some(SUBSCRIBE_METHOD) => {
+                                if let Some((id, _)) = &waiter {
+                                    subscribing.insert(id.to_string(), param.clone());
+                                }
+                            }
+                            Some(UNSUBSCRIBE_METHOD) => {
+                                let sub = param["params"][0].as_u64();
+                                let removed = sub.and_then(|s| subscriptions.remove(&s));
+                                if let Some((_, node_id)) = removed {
+                                    param["params"][0] = node_id.into();
+                                }
+                            }
+                            _ => {}
+                        }
+                        if let Some((id, tx)) = waiter {
+                            // clear the timeout requests.
+                            pending.retain(|_, tx| !tx.is_closed());
+                            pending.insert(id.to_string(), tx);
+                        }
+                        if stream.send(WsMessage::from(param.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => {
+                        // client dropped.
+                        let _ = stream.close(None).await;
+                        return;
+                    }
+                },
+                msg = stream.next() => match msg {
+                    Some(Ok(WsMessage::Text(text))) => {
+                        if let Ok(mut param) = text.parse::<RpcParam>() {
+                            // the response of subscribe sent again.
+                            let resubscribe = param["id"]
+                                .as_str()
+                                .and_then(|id| id.strip_prefix(RESUBSCRIBE_ID))
+                                .and_then(|sub| sub.parse::<u64>().ok());
+                            if let Some(sub) = resubscribe {
+                                match (subscriptions.get_mut(&sub), param["result"].as_u64()) {
+                                    (Some(s), Some(node_id)) => s.1 = node_id,
+                                    _ => debug!("DEBUG: RPC client resubscribe: {}", param),
+                                }
+                                continue;
+                            }
+
+                            // the pushes of the subscription use the client's id.
+                            if param["method"] == SUBSCRIPTION_METHOD {
+                                let node_id = param["params"]["subscription"].as_u64();
+                                let sub = subscriptions
+                                    .iter()
+                                    .find(|(_, (_, n))| Some(*n) == node_id)
+                                    .map(|(sub, _)| *sub);
+                                if let Some(sub) = sub {
+                                    param["params"]["subscription"] = sub.into();
+                                }
+                            }
+
+                            let id = param_id(&param).map(|id| id.to_string());
+                            let request = id.as_ref().and_then(|id| subscribing.remove(id));
+                            let sub = param["result"].as_u64();
+                            if let (Some(request), Some(sub)) = (request, sub) {
+                                subscriptions.insert(sub, (request, sub));
+                            }
+                            match id.and_then(|id| pending.remove(&id)) {
+                                Some(tx) => {
+                                    let _ = tx.send(param);
+                                }
+                                None => publish(&subscribers, param).await,
+                            }
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    _ => break,
+                },
+            }
+        }
+
+        // connection lost, the waiting requests will fail.
+        pending.clear();
+        subscribing.clear();
+        let mut delay = Duration::from_millis(500);
+        stream = loop {
+            sleep(delay).await;
+            if recv.is_closed() {
+                return;
+            }
+            match ws_connect(&url, &options).await {
+                Ok(stream) => break stream,
+                Err(e) => {
+                    debug!("DEBUG: RPC client reconnect {} failure: {}", url, e);
+                    delay = (delay * 2).min(options.max_reconnect_delay);
+                }
+            }
+        };
+        debug!("DEBUG: RPC client reconnected: {}", url);
+
+        // subscribe the topics again, the node will give new ids.
+        for (sub, (request, _)) in subscriptions.iter() {
+            let mut request = request.clone();
+            request["id"] = json!(format!("{}{}", RESUBSCRIBE_ID, sub));
+            let _ = stream.send(WsMessage::from(request.to_string())).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{channel_rpc_channel, start, RpcConfig};
+    use crate::shutdown::Shutdown;
+    use std::net::SocketAddr;
+
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_rpc_client() {
+        use tdn_types::{
+            message::RpcSendMessage,
+            primitives::HandleResult,
+            rpc::{json, RpcHandler},
+            unified::ReceiveMessage,
+        };
+
+        let (http, ws) = (free_addr(), free_addr());
+        let (out_send, out_recv, inner_send, inner_recv) = channel_rpc_channel();
+        let config = RpcConfig {
+            http: Some(http),
+            ws: Some(ws),
+            channel: Some((out_send, inner_recv)),
+            ..Default::default()
+        };
+        let (send, mut recv) = mpsc::channel(128);
+        let shutdown = Shutdown::new();
+        let rpc_send = start(config, send, shutdown.signal()).await.unwrap();
+        tokio::spawn(async move {
+            let mut handler = RpcHandler::new(());
+            handler.add_method("echo", |params: Vec<RpcParam>, _| async move {
+                Ok(HandleResult::rpc(json!(params)))
+            });
+            while let Some(ReceiveMessage::Rpc(uid, params, is_ws)) = recv.recv().await {
+                if params["method"] == json!("notice") {
+                    // push to all WS and channel.
+                    let msg = RpcSendMessage::Rpc(0, json!({"method": "notice"}), true);
+                    let _ = rpc_send.send(msg).await;
+                    continue;
+                }
+                if params["method"] == json!("news") {
+                    // push to the subscribers of topic.
+                    let msg = RpcSendMessage::Publish("news".to_owned(), json!("hi"));
+                    let _ = rpc_send.send(msg).await;
+                    continue;
+                }
+                for rpc in handler.handle(params).await.unwrap().rpcs {
+                    let _ = rpc_send.send(RpcSendMessage::Rpc(uid, rpc, is_ws)).await;
+                }
+            }
+        });
+
+        let options = RpcClientOptions {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let client = RpcClient::http(&format!("http://{}", http), options.clone()).unwrap();
+        let res = client.request("echo", vec![json!("http")]).await.unwrap();
+        assert_eq!(res, json!(["http"]));
+        assert!(client.request("none", vec![]).await.is_err());
+        assert!(client.subscribe().is_err());
+        let metrics = client.request("rpc.metrics", vec![]).await.unwrap();
+        assert_eq!(metrics["connections"]["connections"], json!(1));
+
+        let ws_client = RpcClient::ws(&format!("ws://{}", ws), options.clone())
+            .await
+            .unwrap();
+        let res = ws_client.request("echo", vec![json!("ws")]).await.unwrap();
+        assert_eq!(res, json!(["ws"]));
+
+        let channel_client = RpcClient::channel(inner_send, out_recv, options);
+        let res = channel_client
+            .request("echo", vec![json!(1)])
+            .await
+            .unwrap();
+        assert_eq!(res, json!([1]));
+
+        let mut ws_pushes = ws_client.subscribe().unwrap();
+        let mut channel_pushes = channel_client.subscribe().unwrap();
+        ws_client.notify("notice", vec![]).await.unwrap();
+        let wait = Duration::from_secs(5);
+        let msg = timeout(wait, ws_pushes.recv()).await.unwrap().unwrap();
+        assert_eq!(msg["method"], json!("notice"));
+        let msg = timeout(wait, channel_pushes.recv()).await.unwrap().unwrap();
+        assert_eq!(msg["method"], json!("notice"));
+
+        // only the subscribed connection receives the topic.
+        let sub = ws_client
+            .request("subscribe", vec![json!("news")])
+            .await
+            .unwrap();
+        channel_client.notify("news", vec![]).await.unwrap();
+        let msg = timeout(wait, ws_pushes.recv()).await.unwrap().unwrap();
+        assert_eq!(msg["method"], json!("subscription"));
+        assert_eq!(msg["params"]["subscription"], sub);
+        assert_eq!(msg["params"]["result"], json!("hi"));
+        assert!(channel_pushes.try_recv().is_err());
+
+        let res = ws_client.request("unsubscribe", vec![sub]).await.unwrap();
+        assert_eq!(res, json!(true));
+
+        // stop accepting, close the connections, and wait all down.
+        shutdown.shutdown().await.unwrap();
+        assert!(tokio::net::TcpStream::connect(http).await.is_err());
+        assert!(tokio::net::TcpStream::connect(ws).await.is_err());
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_ws_reconnect() {
+        use tdn_types::message::RpcSendMessage;
+
+        let ws = free_addr();
+        let config = || RpcConfig {
+            ws: Some(ws),
+            ..Default::default()
+        };
+        let (send, _recv) = mpsc::channel(128);
+        let shutdown = Shutdown::new();
+        let _rpc_send = start(config(), send, shutdown.signal()).await.unwrap();
+
+        let options = RpcClientOptions {
+            timeout: Duration::from_secs(5),
+            max_reconnect_delay: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let client = RpcClient::ws(&format!("ws://{}", ws), options)
+            .await
+            .unwrap();
+        let mut pushes = client.subscribe().unwrap();
+        let other = client.request("subscribe", vec![json!("other")]).await;
+        let res = client.request("unsubscribe", vec![other.unwrap()]).await;
+        assert_eq!(res.unwrap(), json!(true));
+        // the string id.
+        let req = json!({"jsonrpc": "2.0", "id": "a", "method": "subscribe", "params": ["news"]});
+        let res = client.call(req).await.unwrap();
+        assert_eq!(res["id"], json!("a"));
+        let sub = res["result"].clone();
+
+        // restart the node, the client reconnects and subscribes again,
+        // the pushes use the subscription id before reconnection.
+        shutdown.shutdown().await.unwrap();
+        let (send, _recv) = mpsc::channel(128);
+        let shutdown = Shutdown::new();
+        let rpc_send = start(config(), send, shutdown.signal()).await.unwrap();
+        let msg = timeout(Duration::from_secs(10), async {
+            loop {
+                let msg = RpcSendMessage::Publish("news".to_owned(), json!("hi"));
+                let _ = rpc_send.send(msg).await;
+                if let Ok(Some(msg)) = timeout(Duration::from_millis(200), pushes.recv()).await {
+                    break msg;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(msg["params"]["subscription"], sub);
+        assert_eq!(msg["params"]["result"], json!("hi"));
+
+        shutdown.shutdown().await.unwrap();
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_rpc_busy() {
+        let http = free_addr();
+        let config = RpcConfig {
+            http: Some(http),
+            ..Default::default()
+        };
+        // the outside is full after one message, and never received.
+        let (send, _recv) = mpsc::channel(1);
+        let shutdown = Shutdown::new();
+        let _rpc_send = start(config, send, shutdown.signal()).await.unwrap();
+
+        let options = RpcClientOptions {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let client = RpcClient::http(&format!("http://{}", http), options).unwrap();
+        client.notify("fill", vec![]).await.unwrap();
+        let err = client.request("echo", vec![json!(1)]).await.unwrap_err();
+        assert!(err.to_string().contains("-32003"));
+
+        shutdown.shutdown().await.unwrap();
+    }
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_rpc_hmac() {
+        use tdn_types::{message::RpcSendMessage, unified::ReceiveMessage};
+
+        let (http, ws) = (free_addr(), free_addr());
+        let secret = [1u8; 32];
+        let config = RpcConfig {
+            http: Some(http),
+            ws: Some(ws),
+            auth: RpcAuth::hmac(&secret),
+            ..Default::default()
+        };
+        let (send, mut recv) = mpsc::channel(128);
+        let shutdown = Shutdown::new();
+        let rpc_send = start(config, send, shutdown.signal()).await.unwrap();
+        tokio::spawn(async move {
+            while let Some(ReceiveMessage::Rpc(uid, params, is_ws)) = recv.recv().await {
+                let res = json!({"jsonrpc": "2.0", "id": params["id"], "result": 1});
+                let _ = rpc_send.send(RpcSendMessage::Rpc(uid, res, is_ws)).await;
+            }
+        });
+
+        // the path and nonce are signed by the client.
+        let options = RpcClientOptions {
+            timeout: Duration::from_secs(5),
+            hmac_key: Some(RpcAuth::hmac_key(&secret)),
+            ..Default::default()
+        };
+        let url = format!("http://{}/rpc", http);
+        let client = RpcClient::http(&url, options.clone()).unwrap();
+        for _ in 0..2 {
+            assert_eq!(client.request("echo", vec![]).await.unwrap(), json!(1));
+        }
+        let ws_client = RpcClient::ws(&format!("ws://{}", ws), options.clone())
+            .await
+            .unwrap();
+        assert_eq!(ws_client.request("echo", vec![]).await.unwrap(), json!(1));
+
+        let options = RpcClientOptions {
+            hmac_key: Some(RpcAuth::hmac_key(&[2u8; 32])),
+            ..options
+        };
+        let client = RpcClient::http(&url, options.clone()).unwrap();
+        assert!(client.request("echo", vec![]).await.is_err());
+        assert!(RpcClient::ws(&format!("ws://{}", ws), options)
+            .await
+            .is_err());
+
+        shutdown.shutdown().await.unwrap();
+    }
+}
@@ -2,154 +2,1706 @@ use rand_chacha::{
     rand_core::{RngCore, SeedableRng},
     ChaChaRng,
 };
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::{
     fs,
-    io::{AsyncReadExt, AsyncWriteExt, Result},
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Result},
+    net::TcpListener,
+    select,
     sync::{mpsc::Sender, oneshot, RwLock},
+    time::{interval_at, timeout, timeout_at, Instant},
 };
-//use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
 
-use tdn_types::rpc::parse_jsonrpc;
+use tdn_types::rpc::{is_notification, json, parse_jsonrpc, RpcError, RpcParam};
 
-use super::RpcMessage;
+use crate::shutdown::ShutdownSignal;
+
+use super::auth::{inject_session, query_value, AuthNonces, AuthRequest, RpcAuth};
+use super::limit::{reject, ConnectionGuard, Limiter};
+use super::{rpc_channel, RpcMessage};
+
+/// max size of request line and headers.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// max number of request headers.
+const MAX_HEADERS: usize = 32;
+
+const JSON_TYPE: &str = "application/json;charset=UTF-8";
+
+/// the seconds of waiting when the request is limited.
+const RETRY_AFTER: &str = "1";
+
+/// the headers allowed in CORS requests.
+const ALLOW_HEADERS: &str = "Authorization, Content-Type, X-Timestamp, X-Signature, X-SSE-Session";
+
+/// the header of POST request, the response will be pushed to the SSE session.
+const SSE_SESSION_HEADER: &str = "x-sse-session";
+
+/// the interval of SSE keep-alive comment.
+const SSE_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// http connection options, from RpcConfig.
+#[derive(Clone, Debug)]
+pub(crate) struct HttpOptions {
+    pub read_timeout: Duration,
+    pub idle_timeout: Duration,
+    pub max_body_size: usize,
+    /// the directory of static assets, served by GET.
+    pub static_dir: Option<PathBuf>,
+    /// the authentication of POST requests, GET is always public.
+    pub auth: RpcAuth,
+    /// the connections and requests limits, shared with WS.
+    /// the seen nonces of hmac auth.
+    pub nonces: Arc<AuthNonces>,
+    pub limiter: Arc<Limiter>,
+    /// the path of SSE endpoint, none is closed.
+    pub sse_path: Option<String>,
+    /// the queue size of every SSE connection.
+    pub queue_size: usize,
+    /// the opened SSE sessions.
+    pub sse_sessions: Arc<Mutex<HashSet<u64>>>,
+    /// the allowed origins of CORS, empty is all origins when no auth.
+    pub cors_origins: Vec<String>,
+}
 
 pub(crate) async fn http_listen(
     index: Option<PathBuf>,
     send: Sender<RpcMessage>,
     listener: TcpListener,
+    options: HttpOptions,
+    acceptor: Option<TlsAcceptor>,
+    mut signal: ShutdownSignal,
 ) -> Result<()> {
     let homepage = if let Some(path) = index {
-        fs::read_to_string(path)
-            .await
-            .unwrap_or("Error Homepage.".to_owned())
+        Some(
+            fs::read_to_string(path)
+                .await
+                .unwrap_or("Error Homepage.".to_owned()),
+        )
     } else {
-        "No Homepage.".to_owned()
+        None
     };
     let homelink = Arc::new(RwLock::new(homepage));
+    let options = Arc::new(options);
 
-    while let Ok((stream, addr)) = listener.accept().await {
-        tokio::spawn(http_connection(
-            homelink.clone(),
-            send.clone(),
-            stream,
-            addr,
-        ));
+    loop {
+        // stop accepting when shutdown.
+        let (stream, addr) = select! {
+            v = listener.accept() => match v {
+                Ok(v) => v,
+                Err(_) => break,
+            },
+            _ = signal.wait() => break,
+        };
+        // over the max connections, reject before reading anything.
+        let guard = match options.limiter.connect() {
+            Some(guard) => guard,
+            None => {
+                debug!("DEBUG: HTTP connections reach the max: {}", addr);
+                if acceptor.is_none() {
+                    let res =
+                        limited_response("connections".to_owned(), RpcParam::Null, false, None);
+                    reject(stream, &res);
+                }
+                continue;
+            }
+        };
+        let homelink = homelink.clone();
+        let send = send.clone();
+        let options = options.clone();
+        let signal = signal.clone();
+        if let Some(acceptor) = acceptor.clone() {
+            tokio::spawn(async move {
+                match timeout(options.read_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        http_connection(homelink, send, stream, addr, options, guard, signal).await
+                    }
+                    _ => {
+                        debug!("DEBUG: HTTPS handshake failure: {}", addr);
+                        Ok(())
+                    }
+                }
+            });
+        } else {
+            tokio::spawn(http_connection(
+                homelink, send, stream, addr, options, guard, signal,
+            ));
+        }
     }
 
     Ok(())
 }
 
-enum HTTP {
-    Ok(usize),
-    NeedMore(usize, usize),
+enum HttpError {
+    /// connection closed or idle timeout, no need response.
+    Closed,
+    Timeout,
+    TooLarge,
+    Invalid(&'static str),
 }
 
-fn parse_req<'a>(src: &[u8]) -> std::result::Result<HTTP, &'a str> {
-    let mut req_parsed_headers = [httparse::EMPTY_HEADER; 16];
-    let mut req = httparse::Request::new(&mut req_parsed_headers);
-    let status = req.parse(&src).map_err(|_| "HTTP parse error")?;
+impl HttpError {
+    fn status(&self) -> &'static str {
+        match self {
+            HttpError::Closed => "",
+            HttpError::Timeout => "408 Request Timeout",
+            HttpError::TooLarge => "413 Payload Too Large",
+            HttpError::Invalid(_) => "400 Bad Request",
+        }
+    }
+}
 
-    let content_length_headers: Vec<httparse::Header> = req
-        .headers
-        .iter()
-        .filter(|header| header.name.to_ascii_lowercase() == "content-length")
-        .cloned()
-        .collect();
+/// the mime type of static file, by the file extension.
+fn mime_type(path: &Path) -> String {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let mime = match ext.as_str() {
+        "html" | "htm" => mime::TEXT_HTML_UTF_8,
+        "css" => mime::TEXT_CSS_UTF_8,
+        "js" | "mjs" => mime::APPLICATION_JAVASCRIPT_UTF_8,
+        "json" | "map" => mime::APPLICATION_JSON,
+        "txt" => mime::TEXT_PLAIN_UTF_8,
+        "csv" => mime::TEXT_CSV_UTF_8,
+        "xml" => mime::TEXT_XML,
+        "png" => mime::IMAGE_PNG,
+        "jpg" | "jpeg" => mime::IMAGE_JPEG,
+        "gif" => mime::IMAGE_GIF,
+        "bmp" => mime::IMAGE_BMP,
+        "svg" => mime::IMAGE_SVG,
+        "woff" => mime::FONT_WOFF,
+        "woff2" => mime::FONT_WOFF2,
+        "pdf" => mime::APPLICATION_PDF,
+        "wasm" => return "application/wasm".to_owned(),
+        "ico" => return "image/x-icon".to_owned(),
+        _ => mime::APPLICATION_OCTET_STREAM,
+    };
+    mime.to_string()
+}
 
-    if content_length_headers.len() != 1 {
-        return Err("HTTP header is invalid");
+/// the file path in static directory, none if the path is outside it.
+fn static_path(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let mut file = dir.to_path_buf();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => file.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
     }
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    Some(file)
+}
 
-    let length_bytes = content_length_headers.first().unwrap().value;
-    let mut length_string = String::new();
+/// the homepage or static file content, and its mime type.
+async fn static_file(
+    homelink: &RwLock<Option<String>>,
+    dir: &Option<PathBuf>,
+    path: &str,
+) -> Option<(Vec<u8>, String)> {
+    if path == "/" || path.starts_with("/?") {
+        if let Some(homepage) = homelink.read().await.as_ref() {
+            return Some((
+                homepage.as_bytes().to_vec(),
+                mime::TEXT_HTML_UTF_8.to_string(),
+            ));
+        }
+        if dir.is_none() {
+            return Some((b"No Homepage.".to_vec(), mime::TEXT_HTML_UTF_8.to_string()));
+        }
+    }
+
+    let file = static_path(dir.as_ref()?, path)?;
+    let bytes = fs::read(&file).await.ok()?;
+    Some((bytes, mime_type(&file)))
+}
 
-    for b in length_bytes {
-        length_string.push(*b as char);
+/// GET or HEAD the homepage and static files, support ETag and If-None-Match.
+async fn static_response(
+    homelink: &RwLock<Option<String>>,
+    options: &HttpOptions,
+    request: &Request,
+) -> Vec<u8> {
+    let cors = allow_origin(options, request);
+    let (body, content_type) = match static_file(homelink, &options.static_dir, &request.path).await
+    {
+        Some(file) => file,
+        None => {
+            return response(
+                "404 Not Found",
+                &[("Content-Type", mime::TEXT_PLAIN_UTF_8.as_ref())],
+                b"Not Found",
+                request.keep_alive,
+                cors,
+            )
+        }
+    };
+
+    let etag = format!("\"{}\"", &blake3::hash(&body).to_hex()[..32]);
+    let is_match = request
+        .headers
+        .get("if-none-match")
+        .map(|tags| {
+            tags.split(',')
+                .any(|t| t.trim() == "*" || t.trim().trim_start_matches("W/") == etag)
+        })
+        .unwrap_or(false);
+
+    let headers = [
+        ("Content-Type", content_type.as_str()),
+        ("ETag", etag.as_str()),
+        ("Cache-Control", "no-cache"),
+    ];
+    if is_match {
+        response_head(
+            "304 Not Modified",
+            &headers[1..],
+            None,
+            request.keep_alive,
+            cors,
+        )
+    } else if request.method == "HEAD" {
+        response_head(
+            "200 OK",
+            &headers,
+            Some(body.len()),
+            request.keep_alive,
+            cors,
+        )
+    } else {
+        response("200 OK", &headers, &body, request.keep_alive, cors)
     }
+}
+
+enum Body {
+    Empty,
+    Length(usize),
+    Chunked,
+}
 
-    let length = length_string
-        .parse::<usize>()
-        .map_err(|_| "HTTP length is invalid")?;
+struct Request {
+    method: String,
+    path: String,
+    keep_alive: bool,
+    /// the headers, name is lowercase.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
 
-    let amt = match status {
+impl Request {
+    /// the path without query.
+    fn path_only(&self) -> &str {
+        self.path.split(['?', '#']).next().unwrap_or("")
+    }
+}
+
+/// parse request line and headers, return None if need more bytes.
+fn parse_head(src: &[u8]) -> std::result::Result<Option<(usize, Request, Body)>, HttpError> {
+    let mut req_parsed_headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut req_parsed_headers);
+    let amt = match req
+        .parse(src)
+        .map_err(|_| HttpError::Invalid("HTTP parse error"))?
+    {
         httparse::Status::Complete(amt) => amt,
-        httparse::Status::Partial => return Err("HTTP parse error"),
+        httparse::Status::Partial => return Ok(None),
+    };
+
+    // HTTP/1.1 default is keep-alive, HTTP/1.0 default is close.
+    let mut keep_alive = req.version == Some(1);
+    let mut headers = HashMap::new();
+    let mut body = Body::Empty;
+    for header in req.headers.iter() {
+        let raw_value = String::from_utf8_lossy(header.value);
+        let value = raw_value.to_ascii_lowercase();
+        let name = header.name.to_ascii_lowercase();
+        match name.as_str() {
+            "content-length" => {
+                if let Body::Length(_) = body {
+                    return Err(HttpError::Invalid("HTTP header is invalid"));
+                }
+                let length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| HttpError::Invalid("HTTP length is invalid"))?;
+                if let Body::Empty = body {
+                    body = Body::Length(length);
+                }
+            }
+            "transfer-encoding" if value.contains("chunked") => {
+                body = Body::Chunked;
+            }
+            "connection" => {
+                if value.contains("close") {
+                    keep_alive = false;
+                } else if value.contains("keep-alive") {
+                    keep_alive = true;
+                }
+            }
+            _ => {}
+        }
+        headers.insert(name, raw_value.to_string());
+    }
+
+    let request = Request {
+        method: req.method.unwrap_or("").to_owned(),
+        path: req.path.unwrap_or("/").to_owned(),
+        keep_alive,
+        headers,
+        body: vec![],
+    };
+
+    Ok(Some((amt, request, body)))
+}
+
+/// decode the chunked body, return the body and the used length,
+/// return None if need more bytes.
+fn parse_chunked(
+    src: &[u8],
+    max: usize,
+) -> std::result::Result<Option<(Vec<u8>, usize)>, HttpError> {
+    let mut body = vec![];
+    let mut pos = 0;
+
+    loop {
+        let line_end = match find_crlf(&src[pos..]) {
+            Some(i) => pos + i,
+            None => return Ok(None),
+        };
+        let line = String::from_utf8_lossy(&src[pos..line_end]);
+        let size_str = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_str, 16)
+            .map_err(|_| HttpError::Invalid("HTTP chunk size is invalid"))?;
+        pos = line_end + 2;
+
+        if size == 0 {
+            // skip the trailers, end with a empty line.
+            loop {
+                let line_end = match find_crlf(&src[pos..]) {
+                    Some(i) => pos + i,
+                    None => return Ok(None),
+                };
+                let is_empty = line_end == pos;
+                pos = line_end + 2;
+                if is_empty {
+                    return Ok(Some((body, pos)));
+                }
+            }
+        }
+
+        if body.len() + size > max {
+            return Err(HttpError::TooLarge);
+        }
+        if src.len() < pos + size + 2 {
+            return Ok(None);
+        }
+        if &src[pos + size..pos + size + 2] != b"\r\n" {
+            return Err(HttpError::Invalid("HTTP chunk is invalid"));
+        }
+        body.extend(&src[pos..pos + size]);
+        pos += size + 2;
+    }
+}
+
+fn find_crlf(src: &[u8]) -> Option<usize> {
+    src.windows(2).position(|w| w == b"\r\n")
+}
+
+/// read more bytes to the buffer before the deadline.
+async fn read_more<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    deadline: Instant,
+) -> std::result::Result<(), HttpError> {
+    let mut tmp = [0u8; 4096];
+    match timeout_at(deadline, stream.read(&mut tmp)).await {
+        Ok(Ok(0)) | Ok(Err(_)) => Err(HttpError::Closed),
+        Ok(Ok(n)) => {
+            buf.extend(&tmp[..n]);
+            Ok(())
+        }
+        Err(_) => Err(HttpError::Timeout),
+    }
+}
+
+/// read a full request from the buffer and stream, the left bytes
+/// (pipelined requests) will keep in the buffer.
+async fn read_request<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    options: &HttpOptions,
+) -> std::result::Result<Request, HttpError> {
+    let deadline = Instant::now() + options.read_timeout;
+
+    let (amt, mut request, body) = loop {
+        if let Some(head) = parse_head(buf)? {
+            break head;
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(HttpError::Invalid("HTTP header is too large"));
+        }
+        read_more(stream, buf, deadline).await?;
     };
 
-    if src[amt..].len() >= length {
-        return Ok(HTTP::Ok(amt));
+    match body {
+        Body::Empty => {
+            buf.drain(..amt);
+        }
+        Body::Length(len) => {
+            if len > options.max_body_size {
+                return Err(HttpError::TooLarge);
+            }
+            while buf.len() < amt + len {
+                read_more(stream, buf, deadline).await?;
+            }
+            request.body = buf[amt..amt + len].to_vec();
+            buf.drain(..amt + len);
+        }
+        Body::Chunked => loop {
+            if let Some((body, used)) = parse_chunked(&buf[amt..], options.max_body_size)? {
+                request.body = body;
+                buf.drain(..amt + used);
+                break;
+            }
+            read_more(stream, buf, deadline).await?;
+        },
+    }
+
+    Ok(request)
+}
+
+/// the allowed origin of the request's CORS, none if not allowed.
+/// `*` only when no auth, otherwise the origin must be in the allowed list.
+fn allow_origin<'a>(options: &HttpOptions, request: &'a Request) -> Option<&'a str> {
+    let origin = request.headers.get("origin").map(|o| o.trim());
+    if let Some(origin) = origin.filter(|o| options.cors_origins.iter().any(|c| c == o)) {
+        return Some(origin);
+    }
+
+    let is_any = options.cors_origins.is_empty() || options.cors_origins.iter().any(|c| c == "*");
+    if is_any && matches!(options.auth, RpcAuth::None) {
+        Some("*")
+    } else {
+        None
+    }
+}
+
+/// the response status line and headers, no content-length if length is none.
+fn response_head(
+    status: &str,
+    headers: &[(&str, &str)],
+    length: Option<usize>,
+    keep_alive: bool,
+    cors: Option<&str>,
+) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {}\r\n", status);
+    match cors {
+        Some("*") => {
+            head.push_str("Access-Control-Allow-Origin:*\r\n");
+            head.push_str("Cross-Origin-Resource-Policy:cross-origin\r\n");
+        }
+        Some(origin) => {
+            head.push_str(&format!("Access-Control-Allow-Origin:{}\r\n", origin));
+            head.push_str("Vary:Origin\r\nCross-Origin-Resource-Policy:cross-origin\r\n");
+        }
+        None => {}
+    }
+    for (name, value) in headers {
+        head.push_str(&format!("{}:{}\r\n", name, value));
     }
+    if let Some(length) = length {
+        head.push_str(&format!("Content-Length:{}\r\n", length));
+    }
+    head.push_str(if keep_alive {
+        "Connection:keep-alive\r\n\r\n"
+    } else {
+        "Connection:close\r\n\r\n"
+    });
+    head.into_bytes()
+}
+
+fn response(
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    keep_alive: bool,
+    cors: Option<&str>,
+) -> Vec<u8> {
+    let mut bytes = response_head(status, headers, Some(body.len()), keep_alive, cors);
+    bytes.extend(body);
+    bytes
+}
+
+/// the response of exceeding the connections or rate limit.
+fn limited_response(
+    limited: String,
+    id: RpcParam,
+    keep_alive: bool,
+    cors: Option<&str>,
+) -> Vec<u8> {
+    let body = RpcError::LimitExceeded(limited).json(id).to_string();
+    response(
+        "429 Too Many Requests",
+        &[("Content-Type", JSON_TYPE), ("Retry-After", RETRY_AFTER)],
+        body.as_bytes(),
+        keep_alive,
+        cors,
+    )
+}
+
+/// the response when the app not response the request in time.
+fn timeout_response(id: RpcParam, keep_alive: bool, cors: Option<&str>) -> Vec<u8> {
+    let body = RpcError::Custom("Gateway Timeout".to_owned()).json(id);
+    response(
+        "504 Gateway Timeout",
+        &[("Content-Type", JSON_TYPE)],
+        body.to_string().as_bytes(),
+        keep_alive,
+        cors,
+    )
+}
 
-    Ok(HTTP::NeedMore(amt, length))
+/// the response when the rpc dispatcher is stopped (e.g. shutdown), then close.
+fn unavailable_response(cors: Option<&str>) -> Vec<u8> {
+    let body = RpcError::Custom("Service Unavailable".to_owned()).json(RpcParam::Null);
+    response(
+        "503 Service Unavailable",
+        &[("Content-Type", JSON_TYPE)],
+        body.to_string().as_bytes(),
+        false,
+        cors,
+    )
 }
 
-async fn http_connection(
-    _homelink: Arc<RwLock<String>>,
+/// the SSE event, data is the one line JSON.
+fn sse_event(event: &str, data: &str) -> Vec<u8> {
+    format!("event: {}\ndata: {}\n\n", event, data).into_bytes()
+}
+
+enum SseResult {
+    Out(Option<RpcMessage>),
+    Ping,
+    Closed,
+    Shutdown,
+}
+
+/// the SSE stream of server pushes, the first event is `open` with the session id,
+/// then `message` events, until the client closed or shutdown.
+async fn sse_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    send: &Sender<RpcMessage>,
+    stream: &mut S,
+    options: &HttpOptions,
+    request: &Request,
+    signal: &mut ShutdownSignal,
+) -> Result<()> {
+    let cors = allow_origin(options, request);
+    // EventSource cannot set headers, so support the query too.
+    let query = request.path.split_once('?').map(|(_, q)| q).unwrap_or("");
+    let header = |name: &str| request.headers.get(name).map(|v| v.trim());
+    let token = header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| query_value(query, "token"));
+    // SSE request has no body.
+    let scopes = options.auth.check(
+        &options.nonces,
+        AuthRequest {
+            token,
+            timestamp: header("x-timestamp").or_else(|| query_value(query, "timestamp")),
+            nonce: header("x-nonce").or_else(|| query_value(query, "nonce")),
+            signature: header("x-signature").or_else(|| query_value(query, "signature")),
+            path: request.path_only(),
+            body: b"",
+        },
+    );
+    if scopes.is_none() {
+        let body = RpcError::Unauthorized(String::new()).json(RpcParam::Null);
+        let res = response(
+            "401 Unauthorized",
+            &[("Content-Type", JSON_TYPE)],
+            body.to_string().as_bytes(),
+            false,
+            cors,
+        );
+        return stream.write_all(&res).await;
+    }
+
+    let id = ChaChaRng::from_entropy().next_u64();
+    let (s_send, mut s_recv) = rpc_channel(options.queue_size);
+    if send.send(RpcMessage::Open(id, s_send)).await.is_err() {
+        return Ok(());
+    }
+    options.sse_sessions.lock().unwrap().insert(id);
+
+    // the session id is string, JSON number in JS is not u64.
+    let mut bytes = response_head(
+        "200 OK",
+        &[
+            ("Content-Type", "text/event-stream"),
+            ("Cache-Control", "no-cache"),
+        ],
+        None,
+        false,
+        cors,
+    );
+    bytes.extend(sse_event(
+        "open",
+        &json!({ "session": id.to_string() }).to_string(),
+    ));
+
+    let mut ping = interval_at(Instant::now() + SSE_PING_INTERVAL, SSE_PING_INTERVAL);
+    let mut tmp = [0u8; 64];
+    loop {
+        if stream.write_all(&bytes).await.is_err() {
+            break;
+        }
+        let _ = stream.flush().await;
+
+        let res = select! {
+            v = s_recv.recv() => SseResult::Out(v),
+            _ = ping.tick() => SseResult::Ping,
+            _ = stream.read(&mut tmp) => SseResult::Closed,
+            _ = signal.wait() => SseResult::Shutdown,
+        };
+
+        bytes = match res {
+            SseResult::Out(Some(RpcMessage::Response(param))) => {
+                sse_event("message", &param.to_string())
+            }
+            SseResult::Out(Some(_)) => vec![],
+            SseResult::Ping => b": ping\n\n".to_vec(),
+            SseResult::Shutdown => {
+                // send the queued messages, then close.
+                let mut bytes = vec![];
+                while let Ok(RpcMessage::Response(param)) = s_recv.try_recv() {
+                    bytes.extend(sse_event("message", &param.to_string()));
+                }
+                let _ = stream.write_all(&bytes).await;
+                break;
+            }
+            // the client closed, or the queue dropped by dispatcher.
+            SseResult::Out(None) | SseResult::Closed => break,
+        };
+    }
+
+    options.sse_sessions.lock().unwrap().remove(&id);
+    let _ = send.send(RpcMessage::Close(id)).await;
+    Ok(())
+}
+
+async fn http_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    homelink: Arc<RwLock<Option<String>>>,
     send: Sender<RpcMessage>,
-    mut stream: TcpStream,
+    mut stream: S,
     addr: SocketAddr,
+    options: Arc<HttpOptions>,
+    _guard: ConnectionGuard,
+    mut signal: ShutdownSignal,
 ) -> Result<()> {
     debug!("DEBUG: HTTP connection established: {}", addr);
     let mut rng = ChaChaRng::from_entropy();
-    let id: u64 = rng.next_u64();
-    let (s_send, s_recv) = oneshot::channel();
-
+    let session: u64 = rng.next_u64();
     let mut buf = vec![];
 
-    // TODO add timeout
-    let mut tmp_buf = vec![0u8; 1024];
-    let n = stream.read(&mut tmp_buf).await?;
-    let body = match parse_req(&tmp_buf[..n]) {
-        Ok(HTTP::NeedMore(amt, len)) => {
-            buf.extend(&tmp_buf[amt..n]);
-            loop {
-                let mut tmp = vec![0u8; 1024];
-                let n = stream.read(&mut tmp).await?;
-                buf.extend(&tmp[..n]);
-                if buf.len() >= len {
+    loop {
+        // keep-alive, wait the next request, or close when shutdown.
+        if buf.is_empty() {
+            let deadline = Instant::now() + options.idle_timeout;
+            let closed = select! {
+                v = read_more(&mut stream, &mut buf, deadline) => v.is_err(),
+                _ = signal.wait() => true,
+            };
+            if closed {
+                break;
+            }
+        }
+
+        let mut request = match read_request(&mut stream, &mut buf, &options).await {
+            Ok(request) => request,
+            Err(HttpError::Closed) => break,
+            Err(e) => {
+                if let HttpError::Invalid(info) = e {
+                    info!("TDN: HTTP JSONRPC parse error: {}", info);
+                }
+                let _ = stream
+                    .write_all(&response(
+                        e.status(),
+                        &[("Content-Type", JSON_TYPE)],
+                        b"",
+                        false,
+                        None,
+                    ))
+                    .await;
+                break;
+            }
+        };
+
+        // finish the in-flight request, then close.
+        if signal.is_shutdown() {
+            request.keep_alive = false;
+        }
+        let cors = allow_origin(&options, &request);
+
+        // the static requests only take the IP's token.
+        let is_static = matches!(request.method.as_str(), "GET" | "HEAD" | "OPTIONS");
+        if is_static {
+            if let Err(limited) = options.limiter.check(addr.ip(), &RpcParam::Null) {
+                let res = limited_response(limited, RpcParam::Null, request.keep_alive, cors);
+                stream.write_all(&res).await?;
+                let _ = stream.flush().await;
+                if !request.keep_alive {
                     break;
                 }
+                continue;
             }
-            &buf[..]
         }
-        Ok(HTTP::Ok(amt)) => &tmp_buf[amt..n],
-        Err(e) => {
-            info!("TDN: HTTP JSONRPC parse error: {}", e);
-            return Ok(());
+
+        let path = request.path.split(['?', '#']).next();
+        if request.method == "GET"
+            && options.sse_path.is_some()
+            && path == options.sse_path.as_deref()
+        {
+            sse_connection(&send, &mut stream, &options, &request, &mut signal).await?;
+            break;
         }
-    };
 
-    let msg = String::from_utf8_lossy(body);
-    let res = "HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin:*\r\nCross-Origin-Resource-Policy:cross-origin\r\nContent-Type:application/json;charset=UTF-8\r\n\r\n";
+        if request.method == "GET" || request.method == "HEAD" {
+            let res = static_response(&homelink, &options, &request).await;
+            stream.write_all(&res).await?;
+            let _ = stream.flush().await;
+            if !request.keep_alive {
+                break;
+            }
+            continue;
+        }
 
-    match parse_jsonrpc((*msg).to_string()) {
-        Ok(rpc_param) => {
-            send.send(RpcMessage::Request(id, rpc_param, Some(s_send)))
-                .await
-                .expect("Http to Rpc channel closed");
+        if request.method == "OPTIONS" {
+            // CORS preflight.
+            let res = response_head(
+                "204 No Content",
+                &[
+                    ("Access-Control-Allow-Methods", "GET, HEAD, POST, OPTIONS"),
+                    ("Access-Control-Allow-Headers", ALLOW_HEADERS),
+                ],
+                None,
+                request.keep_alive,
+                cors,
+            );
+            stream.write_all(&res).await?;
+            let _ = stream.flush().await;
+            if !request.keep_alive {
+                break;
+            }
+            continue;
         }
-        Err((err, id)) => {
-            stream
-                .write(format!("{}{}", res, err.json(id).to_string()).as_bytes())
-                .await?;
+
+        let token = request
+            .headers
+            .get("authorization")
+            .and_then(|v| v.trim().strip_prefix("Bearer "))
+            .map(|v| v.trim());
+        let header = |name: &str| request.headers.get(name).map(|v| v.as_str());
+        let scopes = options.auth.check(
+            &options.nonces,
+            AuthRequest {
+                token,
+                timestamp: header("x-timestamp"),
+                nonce: header("x-nonce"),
+                signature: header("x-signature"),
+                path: request.path_only(),
+                body: &request.body,
+            },
+        );
+
+        let id: u64 = rng.next_u64();
+        let (s_send, s_recv) = oneshot::channel();
+        let msg = String::from_utf8_lossy(&request.body);
+        let parsed = parse_jsonrpc((*msg).to_string());
+
+        let limit_param = parsed.as_ref().unwrap_or(&RpcParam::Null);
+        if let Err(limited) = options.limiter.check(addr.ip(), limit_param) {
+            let id = limit_param.get("id").cloned().unwrap_or_default();
+            let res = limited_response(limited, id, request.keep_alive, cors);
+            stream.write_all(&res).await?;
             let _ = stream.flush().await;
-            stream.shutdown().await?;
+            if !request.keep_alive {
+                break;
+            }
+            continue;
+        }
+
+        let scopes = match scopes {
+            Some(scopes) => scopes,
+            None => {
+                let method = match &parsed {
+                    Ok(param) => param["method"].as_str().unwrap_or("").to_owned(),
+                    Err(_) => String::new(),
+                };
+                let body = RpcError::Unauthorized(method).json(RpcParam::Null);
+                stream
+                    .write_all(&response(
+                        "401 Unauthorized",
+                        &[("Content-Type", JSON_TYPE)],
+                        body.to_string().as_bytes(),
+                        request.keep_alive,
+                        cors,
+                    ))
+                    .await?;
+                let _ = stream.flush().await;
+                if !request.keep_alive {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // the request of SSE session, the response will be pushed to the session.
+        if let Some(sse) = request.headers.get(SSE_SESSION_HEADER) {
+            let sid = sse
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|sid| options.sse_sessions.lock().unwrap().contains(sid));
+            let res = match (sid, parsed) {
+                (Some(sid), Ok(mut rpc_param)) => {
+                    inject_session(&mut rpc_param, sid, &scopes);
+                    if send
+                        .send(RpcMessage::Request(sid, rpc_param, None))
+                        .await
+                        .is_err()
+                    {
+                        let _ = stream.write_all(&unavailable_response(cors)).await;
+                        break;
+                    }
+                    response("202 Accepted", &[], b"", request.keep_alive, cors)
+                }
+                (Some(_), Err((err, id))) => response(
+                    "200 OK",
+                    &[("Content-Type", JSON_TYPE)],
+                    err.json(id).to_string().as_bytes(),
+                    request.keep_alive,
+                    cors,
+                ),
+                (None, _) => {
+                    let err = RpcError::Custom("SSE session not found".to_owned());
+                    response(
+                        "404 Not Found",
+                        &[("Content-Type", JSON_TYPE)],
+                        err.json(RpcParam::Null).to_string().as_bytes(),
+                        request.keep_alive,
+                        cors,
+                    )
+                }
+            };
+            stream.write_all(&res).await?;
+            let _ = stream.flush().await;
+            if !request.keep_alive {
+                break;
+            }
+            continue;
         }
-    }
 
-    if let Ok(msg) = s_recv.await {
-        let param = match msg {
-            RpcMessage::Response(param) => param,
-            _ => Default::default(),
+        let body = match parsed.map(|mut rpc_param| {
+            inject_session(&mut rpc_param, session, &scopes);
+            rpc_param
+        }) {
+            Ok(rpc_param) if is_notification(&rpc_param) => {
+                // notifications no response, no need wait.
+                if send
+                    .send(RpcMessage::Request(id, rpc_param, None))
+                    .await
+                    .is_err()
+                {
+                    let _ = stream.write_all(&unavailable_response(cors)).await;
+                    break;
+                }
+                stream
+                    .write_all(&response_head(
+                        "204 No Content",
+                        &[],
+                        None,
+                        request.keep_alive,
+                        cors,
+                    ))
+                    .await?;
+                let _ = stream.flush().await;
+                if !request.keep_alive {
+                    break;
+                }
+                continue;
+            }
+            Ok(rpc_param) => {
+                let rpc_id = rpc_param.get("id").cloned().unwrap_or_default();
+                if send
+                    .send(RpcMessage::Request(id, rpc_param, Some(s_send)))
+                    .await
+                    .is_err()
+                {
+                    let _ = stream.write_all(&unavailable_response(cors)).await;
+                    break;
+                }
+                // the app maybe not response, release the waiting of dispatcher.
+                match timeout(options.read_timeout, s_recv).await {
+                    Ok(Ok(RpcMessage::Response(param))) => param.to_string(),
+                    Ok(Ok(_)) => RpcParam::default().to_string(),
+                    Ok(Err(_)) => break,
+                    Err(_) => {
+                        debug!("DEBUG: HTTP response timeout: {}", addr);
+                        let _ = send.send(RpcMessage::Close(id)).await;
+                        let res = timeout_response(rpc_id, request.keep_alive, cors);
+                        stream.write_all(&res).await?;
+                        let _ = stream.flush().await;
+                        if !request.keep_alive {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            }
+            Err((err, id)) => err.json(id).to_string(),
         };
-        let _ = stream.write(format!("{}{}", res, param).as_bytes()).await?;
+
+        stream
+            .write_all(&response(
+                "200 OK",
+                &[("Content-Type", JSON_TYPE)],
+                body.as_bytes(),
+                request.keep_alive,
+                cors,
+            ))
+            .await?;
         let _ = stream.flush().await;
-        stream.shutdown().await?;
+
+        if !request.keep_alive {
+            break;
+        }
     }
 
+    let _ = stream.shutdown().await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::Shutdown;
+    use tokio::{net::TcpStream, sync::mpsc};
+
+    #[test]
+    fn test_parse_chunked() {
+        let src = b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\n\r\nnext";
+        let (body, used) = parse_chunked(src, 1024).ok().unwrap().unwrap();
+        assert_eq!(body, b"Wikipedia ".to_vec());
+        assert_eq!(&src[used..], b"next");
+
+        assert!(parse_chunked(b"4\r\nWi", 1024).ok().unwrap().is_none());
+        assert!(matches!(
+            parse_chunked(b"a\r\n0123456789\r\n", 8),
+            Err(HttpError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_allow_origin() {
+        let mut options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 128,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        let mut request = Request {
+            method: "POST".to_owned(),
+            path: "/".to_owned(),
+            keep_alive: true,
+            headers: HashMap::new(),
+            body: vec![],
+        };
+        request
+            .headers
+            .insert("origin".to_owned(), "https://a.com".to_owned());
+        assert_eq!(allow_origin(&options, &request), Some("*"));
+
+        options.auth = RpcAuth::bearer(&[1u8; 32]);
+        assert_eq!(allow_origin(&options, &request), None);
+        options.cors_origins = vec!["*".to_owned()];
+        assert_eq!(allow_origin(&options, &request), None);
+        options.cors_origins = vec!["https://a.com".to_owned()];
+        assert_eq!(allow_origin(&options, &request), Some("https://a.com"));
+        request
+            .headers
+            .insert("origin".to_owned(), "https://b.com".to_owned());
+        assert_eq!(allow_origin(&options, &request), None);
+    }
+
+    /// read a response, return the status, body and etag.
+    async fn read_response<S: AsyncRead + Unpin>(
+        stream: &mut S,
+    ) -> (String, String, Option<String>) {
+        let mut buf = vec![];
+        loop {
+            let mut tmp = [0u8; 1024];
+            let n = stream.read(&mut tmp).await.unwrap();
+            buf.extend(&tmp[..n]);
+            let mut headers = [httparse::EMPTY_HEADER; 16];
+            let mut res = httparse::Response::new(&mut headers);
+            if let Ok(httparse::Status::Complete(amt)) = res.parse(&buf) {
+                let header = |name: &str| {
+                    res.headers
+                        .iter()
+                        .find(|h| h.name.eq_ignore_ascii_case(name))
+                        .map(|h| String::from_utf8_lossy(h.value).to_string())
+                };
+                let len = header("content-length")
+                    .map(|v| v.parse::<usize>().unwrap())
+                    .unwrap_or(0);
+                if buf.len() >= amt + len || n == 0 {
+                    let status = format!("{}", res.code.unwrap());
+                    let body = String::from_utf8_lossy(&buf[amt..]).to_string();
+                    return (status, body, header("etag"));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_keep_alive() {
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 128,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+        tokio::spawn(async move {
+            while let Some(RpcMessage::Request(_, params, Some(tx))) = recv.recv().await {
+                let _ = tx.send(RpcMessage::Response(params["method"].clone()));
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"echo"}"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!((status.as_str(), res.as_str()), ("200", "\"echo\""));
+
+        // same connection, chunked body.
+        let req = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding:chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!((status.as_str(), res.as_str()), ("200", "\"echo\""));
+
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length:1024\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "413".to_owned());
+    }
+
+    #[tokio::test]
+    async fn test_http_response_timeout() {
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_millis(200),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 128,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"echo"}"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+
+        // the app not response, the waiting is closed.
+        let (id, _tx) = match recv.recv().await {
+            Some(RpcMessage::Request(id, _, Some(tx))) => (id, tx),
+            _ => panic!("need request"),
+        };
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "504");
+        assert_eq!(res.parse::<RpcParam>().unwrap()["id"], 1);
+        match recv.recv().await {
+            Some(RpcMessage::Close(close_id)) => assert_eq!(close_id, id),
+            _ => panic!("need close"),
+        }
+
+        // the connection is kept alive.
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert!(matches!(
+            recv.recv().await,
+            Some(RpcMessage::Request(_, _, Some(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_http_static() {
+        let dir = PathBuf::from("./.test_http_static");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join("app.js"), "let a = 1;").unwrap();
+
+        let (send, _recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 128,
+            static_dir: Some(dir.clone()),
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let (status, body, _) = read_response(&mut stream).await;
+        assert_eq!((status.as_str(), body.as_str()), ("200", "<html></html>"));
+
+        stream
+            .write_all(b"GET /app.js HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let (status, body, etag) = read_response(&mut stream).await;
+        assert_eq!((status.as_str(), body.as_str()), ("200", "let a = 1;"));
+        assert_eq!(
+            mime_type(&dir.join("app.js")),
+            "application/javascript; charset=utf-8"
+        );
+
+        let req = format!(
+            "GET /app.js HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n",
+            etag.unwrap()
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "304");
+
+        stream
+            .write_all(b"GET /../Cargo.toml HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "404");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_http_batch() {
+        use tdn_types::{
+            primitives::HandleResult,
+            rpc::{json, RpcHandler, RpcParam},
+        };
+
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 1024,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+        tokio::spawn(async move {
+            let mut handler = RpcHandler::new(());
+            handler.add_method("echo", |params: Vec<RpcParam>, _| async move {
+                Ok(HandleResult::rpc(json!(params)))
+            });
+            while let Some(RpcMessage::Request(_, params, tx)) = recv.recv().await {
+                let mut res = handler.handle(params).await.unwrap();
+                if let Some(tx) = tx {
+                    let _ = tx.send(RpcMessage::Response(res.rpcs.remove(0)));
+                }
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = r#"[{"jsonrpc":"2.0","id":"a","method":"echo","params":[1]},{"jsonrpc":"2.0","method":"echo"},{"jsonrpc":"2.0","id":2,"method":"none"}]"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "200");
+        let res = res.parse::<RpcParam>().unwrap();
+        let res = res.as_array().unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0]["id"], json!("a"));
+        assert_eq!(res[0]["result"], json!([1]));
+        assert_eq!(res[1]["id"], json!(2));
+        assert_eq!(res[1]["error"]["code"], json!(-32601));
+
+        let body = r#"{"jsonrpc":"2.0","method":"echo"}"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "204");
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_http_auth() {
+        use tdn_types::{
+            primitives::HandleResult,
+            rpc::{json, RpcHandler},
+        };
+
+        let mut tokens = HashMap::new();
+        tokens.insert("admin".to_owned(), vec!["*".to_owned()]);
+        tokens.insert("reader".to_owned(), vec!["read".to_owned()]);
+
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 1024,
+            static_dir: None,
+            auth: RpcAuth::Bearer(tokens),
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+        tokio::spawn(async move {
+            let mut handler = RpcHandler::new(());
+            handler.add_method_with_scopes("write", &["write"], |_, _| async move {
+                Ok(HandleResult::rpc(json!("ok")))
+            });
+            while let Some(RpcMessage::Request(_, params, tx)) = recv.recv().await {
+                let mut res = handler.handle(params).await.unwrap();
+                if let Some(tx) = tx {
+                    let _ = tx.send(RpcMessage::Response(res.rpcs.remove(0)));
+                }
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"write"}"#;
+        let request = |auth: &str| {
+            format!(
+                "POST / HTTP/1.1\r\n{}Content-Length:{}\r\n\r\n{}",
+                auth,
+                body.len(),
+                body
+            )
+        };
+
+        stream
+            .write_all(b"OPTIONS / HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "204");
+
+        stream.write_all(request("").as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "401");
+        assert_eq!(
+            res.parse::<RpcParam>().unwrap()["error"]["code"],
+            json!(-32001)
+        );
+
+        let auth = "Authorization: Bearer reader\r\n";
+        stream.write_all(request(auth).as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "200");
+        assert_eq!(
+            res.parse::<RpcParam>().unwrap()["error"]["code"],
+            json!(-32001)
+        );
+
+        let auth = "Authorization: Bearer admin\r\n";
+        stream.write_all(request(auth).as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "200");
+        assert_eq!(res.parse::<RpcParam>().unwrap()["result"], json!("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_http_sse() {
+        use tdn_types::rpc::json;
+
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 1024,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: Some("/events".to_owned()),
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+        tokio::spawn(async move {
+            let mut sessions = HashMap::new();
+            while let Some(msg) = recv.recv().await {
+                match msg {
+                    RpcMessage::Open(id, sender) => {
+                        sessions.insert(id, sender);
+                    }
+                    RpcMessage::Request(id, params, None) => {
+                        let res = json!({"jsonrpc": "2.0", "id": params["id"], "result": id});
+                        let _ = sessions[&id].send(RpcMessage::Response(res)).await;
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        // read the SSE stream until the end of head or next event.
+        async fn next_event(stream: &mut TcpStream, buf: &mut Vec<u8>, end: &[u8]) -> String {
+            loop {
+                if let Some(i) = buf.windows(end.len()).position(|w| w == end) {
+                    let event = String::from_utf8_lossy(&buf[..i]).to_string();
+                    buf.drain(..i + end.len());
+                    return event;
+                }
+                let mut tmp = [0u8; 1024];
+                let n = stream.read(&mut tmp).await.unwrap();
+                assert!(n > 0);
+                buf.extend(&tmp[..n]);
+            }
+        }
+
+        let mut events = TcpStream::connect(addr).await.unwrap();
+        events
+            .write_all(b"GET /events HTTP/1.1\r\nAccept: text/event-stream\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = vec![];
+        let head = next_event(&mut events, &mut buf, b"\r\n\r\n").await;
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains("text/event-stream"));
+        let open = next_event(&mut events, &mut buf, b"\n\n").await;
+        let data = open.strip_prefix("event: open\ndata: ").unwrap();
+        let session = data.parse::<RpcParam>().unwrap()["session"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"echo"}"#;
+        let request = |session: &str| {
+            format!(
+                "POST / HTTP/1.1\r\nX-SSE-Session:{}\r\nContent-Length:{}\r\n\r\n{}",
+                session,
+                body.len(),
+                body
+            )
+        };
+        stream
+            .write_all(request(&session).as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "202");
+        let message = next_event(&mut events, &mut buf, b"\n\n").await;
+        let data = message.strip_prefix("event: message\ndata: ").unwrap();
+        let res = data.parse::<RpcParam>().unwrap();
+        assert_eq!(res["id"], json!(1));
+        assert_eq!(res["result"].to_string(), session);
+
+        stream.write_all(request("1").as_bytes()).await.unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "404");
+    }
+
+    #[tokio::test]
+    async fn test_http_limits() {
+        use super::super::limit::{RateLimit, RpcLimits};
+        use tdn_types::rpc::json;
+
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = RpcLimits::default()
+            .max_connections(1)
+            .method_rate("send", RateLimit::new(1, 1));
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 1024,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Arc::new(Limiter::new(limits)),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            None,
+            Shutdown::new().signal(),
+        ));
+        tokio::spawn(async move {
+            while let Some(RpcMessage::Request(_, params, tx)) = recv.recv().await {
+                let res = json!({"jsonrpc": "2.0", "id": params["id"], "result": 1});
+                let _ = tx.unwrap().send(RpcMessage::Response(res));
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"send"}"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(read_response(&mut stream).await.0, "200");
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "429");
+        let res = res.parse::<RpcParam>().unwrap();
+        assert_eq!(res["id"], json!(1));
+        assert_eq!(res["error"]["code"], json!(-32005));
+
+        // the second connection is over the max connections, rejected
+        // before the request.
+        let mut stream2 = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_response(&mut stream2).await.0, "429");
+        let mut tmp = [0u8; 16];
+        assert_eq!(stream2.read(&mut tmp).await.unwrap(), 0);
+
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut stream3 = TcpStream::connect(addr).await.unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":2,"method":"echo"}"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream3.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(read_response(&mut stream3).await.0, "200");
+    }
+
+    #[tokio::test]
+    async fn test_https() {
+        use super::super::RpcTlsConfig;
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+        use tokio_rustls::{
+            rustls::{
+                crypto::ring::default_provider,
+                pki_types::{PrivateKeyDer, ServerName},
+                ClientConfig, RootCertStore,
+            },
+            TlsConnector,
+        };
+
+        // self-signed CA, and the server & client certificates signed by it.
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = KeyPair::generate().unwrap();
+        let server = CertificateParams::new(vec!["localhost".to_owned()])
+            .unwrap()
+            .signed_by(&server_key, &ca, &ca_key)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client = CertificateParams::new(vec!["client".to_owned()])
+            .unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        let path = PathBuf::from("./.test_https");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("ca.pem"), ca.pem()).unwrap();
+        std::fs::write(path.join("cert.pem"), server.pem()).unwrap();
+        std::fs::write(path.join("key.pem"), server_key.serialize_pem()).unwrap();
+        let tls = RpcTlsConfig::new(path.join("cert.pem"), path.join("key.pem"))
+            .with_client_ca(path.join("ca.pem"));
+        let acceptor = tls.acceptor().await.unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let (send, mut recv) = mpsc::channel(128);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = HttpOptions {
+            read_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(5),
+            max_body_size: 128,
+            static_dir: None,
+            auth: RpcAuth::None,
+            nonces: Default::default(),
+            limiter: Default::default(),
+            sse_path: None,
+            queue_size: 128,
+            sse_sessions: Default::default(),
+            cors_origins: vec![],
+        };
+        tokio::spawn(http_listen(
+            None,
+            send,
+            listener,
+            options,
+            Some(acceptor),
+            Shutdown::new().signal(),
+        ));
+        tokio::spawn(async move {
+            while let Some(RpcMessage::Request(_, params, Some(tx))) = recv.recv().await {
+                let _ = tx.send(RpcMessage::Response(params["method"].clone()));
+            }
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let domain = ServerName::try_from("localhost").unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"echo"}"#;
+        let req = format!(
+            "POST / HTTP/1.1\r\nContent-Length:{}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        // mTLS: client without certificate will be rejected.
+        let connector = TlsConnector::from(Arc::new(builder.clone().with_no_client_auth()));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        if let Ok(mut stream) = connector.connect(domain.clone(), tcp).await {
+            let _ = stream.write_all(req.as_bytes()).await;
+            let mut tmp = [0u8; 1024];
+            assert!(!matches!(stream.read(&mut tmp).await, Ok(n) if n > 0));
+        }
+
+        let key = PrivateKeyDer::Pkcs8(client_key.serialize_der().into());
+        let config = builder
+            .with_client_auth_cert(vec![client.der().clone()], key)
+            .unwrap();
+        let connector = TlsConnector::from(Arc::new(config));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect(domain, tcp).await.unwrap();
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let (status, res, _) = read_response(&mut stream).await;
+        assert_eq!(status, "200");
+        assert_eq!(res, "\"echo\"");
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Instant;
+
+use tdn_types::rpc::{json, RpcParam};
+
+/// the max number of buckets, the full ones will be cleared when reached.
+const MAX_BUCKETS: usize = 10_000;
+
+/// the token bucket rate, at most `burst` requests at once, and refill
+/// `per_second` tokens every second.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: u32,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_second: u32) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+/// the limits of HTTP and WS endpoints, default is unlimited.
+#[derive(Clone, Debug, Default)]
+pub struct RpcLimits {
+    /// max concurrent HTTP and WS connections.
+    pub max_connections: Option<usize>,
+    /// the requests rate of every IP.
+    pub ip_rate: Option<RateLimit>,
+    /// the method's requests rate of every IP.
+    pub method_rates: HashMap<String, RateLimit>,
+}
+
+impl RpcLimits {
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    pub fn ip_rate(mut self, rate: RateLimit) -> Self {
+        self.ip_rate = Some(rate);
+        self
+    }
+
+    pub fn method_rate(mut self, method: impl ToString, rate: RateLimit) -> Self {
+        self.method_rates.insert(method.to_string(), rate);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: &RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: rate.burst as f64,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, rate: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second as f64).min(rate.burst as f64);
+        self.last = now;
+    }
+
+    fn take(&mut self, rate: &RateLimit, now: Instant) -> bool {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// the limiter shared by HTTP and WS listeners.
+#[derive(Debug, Default)]
+pub(crate) struct Limiter {
+    limits: RpcLimits,
+    connections: Arc<AtomicUsize>,
+    rejected: AtomicU64,
+    limited: AtomicU64,
+    /// (IP, none or the method) => bucket.
+    buckets: Mutex<HashMap<(IpAddr, Option<String>), Bucket>>,
+}
+
+/// the connection counted by limiter, released when dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Limiter {
+    pub fn new(limits: RpcLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// count the new connection, none if reach the max connections.
+    pub fn connect(&self) -> Option<ConnectionGuard> {
+        let max = self.limits.max_connections.unwrap_or(usize::MAX);
+        let res = self
+            .connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            });
+        match res {
+            Ok(_) => Some(ConnectionGuard(self.connections.clone())),
+            Err(_) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// take a token of the IP, and of every method in the request (or batch),
+    /// return the limited IP or method if exceeded.
+    pub fn check(&self, ip: IpAddr, params: &RpcParam) -> Result<(), String> {
+        let mut methods: Vec<&str> = match params {
+            RpcParam::Array(params) => params.iter().filter_map(|p| p["method"].as_str()).collect(),
+            _ => params["method"].as_str().into_iter().collect(),
+        };
+        methods.retain(|m| self.limits.method_rates.contains_key(*m));
+        if self.limits.ip_rate.is_none() && methods.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_BUCKETS {
+            self.clear(&mut buckets, now);
+        }
+
+        if let Some(rate) = &self.limits.ip_rate {
+            let bucket = buckets
+                .entry((ip, None))
+                .or_insert_with(|| Bucket::new(rate, now));
+            if !bucket.take(rate, now) {
+                self.limited.fetch_add(1, Ordering::Relaxed);
+                return Err(ip.to_string());
+            }
+        }
+
+        for method in methods {
+            let rate = &self.limits.method_rates[method];
+            let bucket = buckets
+                .entry((ip, Some(method.to_owned())))
+                .or_insert_with(|| Bucket::new(rate, now));
+            if !bucket.take(rate, now) {
+                self.limited.fetch_add(1, Ordering::Relaxed);
+                return Err(method.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// remove the full buckets, they are same as the new ones.
+    fn clear(&self, buckets: &mut HashMap<(IpAddr, Option<String>), Bucket>, now: Instant) {
+        buckets.retain(|(_, method), bucket| {
+            let rate = match method {
+                Some(method) => self.limits.method_rates.get(method),
+                None => self.limits.ip_rate.as_ref(),
+            };
+            match rate {
+                Some(rate) => {
+                    bucket.refill(rate, now);
+                    bucket.tokens < rate.burst as f64
+                }
+                None => false,
+            }
+        });
+    }
+
+    /// the metrics of connections and limited requests.
+    pub fn metrics(&self) -> RpcParam {
+        json!({
+            "connections": self.connections.load(Ordering::SeqCst),
+            "max_connections": self.limits.max_connections,
+            "rejected": self.rejected.load(Ordering::Relaxed),
+            "limited": self.limited.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// reject the connection over the max connections. the response is written
+/// to the empty socket buffer without waiting, then the connection is closed.
+pub(crate) fn reject(stream: tokio::net::TcpStream, response: &[u8]) {
+    if let Ok(mut stream) = stream.into_std() {
+        let _ = std::io::Write::write(&mut stream, response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter() {
+        let limits = RpcLimits::default()
+            .max_connections(1)
+            .ip_rate(RateLimit::new(3, 1))
+            .method_rate("send", RateLimit::new(1, 1));
+        let limiter = Limiter::new(limits);
+
+        let guard = limiter.connect();
+        assert!(guard.is_some());
+        assert!(limiter.connect().is_none());
+        drop(guard);
+        assert!(limiter.connect().is_some());
+
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "127.0.0.2".parse().unwrap();
+        let send = json!({"method": "send"});
+        let echo = json!({"method": "echo"});
+        assert!(limiter.check(ip, &send).is_ok());
+        assert_eq!(limiter.check(ip, &send), Err("send".to_owned()));
+        assert!(limiter.check(ip, &echo).is_ok());
+        assert_eq!(limiter.check(ip, &echo), Err("127.0.0.1".to_owned()));
+        assert!(limiter.check(other, &json!([echo, send])).is_ok());
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics["connections"], json!(0));
+        assert_eq!(metrics["rejected"], json!(1));
+        assert_eq!(metrics["limited"], json!(2));
+    }
+}
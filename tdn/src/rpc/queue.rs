@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use tdn_types::rpc::{json, RpcParam};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+use super::RpcMessage;
+
+/// the built-in method of dispatcher metrics (queue depth, dropped...).
+pub const METRICS_METHOD: &str = "rpc.metrics";
+
+/// the policy of the connection which queue is full (the client is too slow).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SlowPolicy {
+    /// drop the message, keep the connection.
+    #[default]
+    Drop,
+    /// disconnect the connection.
+    Disconnect,
+}
+
+/// the WS and channel connections, every one has a bounded queue,
+/// the dispatcher never waits for them.
+pub(crate) struct Connections {
+    senders: HashMap<u64, Sender<RpcMessage>>,
+    policy: SlowPolicy,
+    dropped: u64,
+    disconnected: u64,
+}
+
+impl Connections {
+    pub fn new(policy: SlowPolicy) -> Self {
+        Self {
+            senders: HashMap::new(),
+            policy,
+            dropped: 0,
+            disconnected: 0,
+        }
+    }
+
+    pub fn open(&mut self, id: u64, sender: Sender<RpcMessage>) {
+        self.senders.insert(id, sender);
+    }
+
+    pub fn close(&mut self, id: u64) {
+        self.senders.remove(&id);
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.senders.contains_key(&id)
+    }
+
+    /// push the message to the connection's queue, return false if dropped.
+    pub fn push(&mut self, id: u64, param: RpcParam) -> bool {
+        let sender = match self.senders.get(&id) {
+            Some(sender) => sender,
+            None => return false,
+        };
+
+        match sender.try_send(RpcMessage::Response(param)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                if self.policy == SlowPolicy::Disconnect {
+                    // drop the sender, the connection will close.
+                    warn!("TDN: RPC connection {} is too slow, disconnect it", id);
+                    self.senders.remove(&id);
+                    self.disconnected += 1;
+                }
+                false
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.senders.remove(&id);
+                false
+            }
+        }
+    }
+
+    /// push the message to all connections.
+    pub fn broadcast(&mut self, param: RpcParam) {
+        let ids: Vec<u64> = self.senders.keys().copied().collect();
+        for id in ids {
+            self.push(id, param.clone());
+        }
+    }
+
+    /// the metrics of the connections' queues.
+    pub fn metrics(&self) -> RpcParam {
+        let mut queues: Vec<RpcParam> = self
+            .senders
+            .iter()
+            .map(|(id, s)| {
+                json!({
+                    "id": id,
+                    "depth": s.max_capacity() - s.capacity(),
+                    "capacity": s.max_capacity(),
+                })
+            })
+            .collect();
+        queues.sort_by_key(|q| std::cmp::Reverse(q["depth"].as_u64()));
+
+        json!({
+            "connections": self.senders.len(),
+            "dropped": self.dropped,
+            "disconnected": self.disconnected,
+            "queues": queues,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_connections() {
+        let mut conns = Connections::new(SlowPolicy::Drop);
+        let (s1, mut r1) = mpsc::channel(1);
+        let (s2, _r2) = mpsc::channel(2);
+        conns.open(1, s1);
+        conns.open(2, s2);
+
+        conns.broadcast(json!(1));
+        assert!(!conns.push(1, json!(2)));
+        assert!(conns.push(2, json!(2)));
+        assert!(conns.contains(1));
+
+        let metrics = conns.metrics();
+        assert_eq!(metrics["connections"], json!(2));
+        assert_eq!(metrics["dropped"], json!(1));
+        assert_eq!(metrics["queues"][0]["id"], json!(2));
+        assert_eq!(metrics["queues"][0]["depth"], json!(2));
+        assert!(r1.try_recv().is_ok());
+
+        let mut conns = Connections::new(SlowPolicy::Disconnect);
+        let (s1, _r1) = mpsc::channel(1);
+        conns.open(1, s1);
+        assert!(conns.push(1, json!(1)));
+        assert!(!conns.push(1, json!(2)));
+        assert!(!conns.contains(1));
+        assert_eq!(conns.metrics()["disconnected"], json!(1));
+    }
+}
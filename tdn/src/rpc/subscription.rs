@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use tdn_types::rpc::{json, RpcParam};
+
+/// the method of subscribing topic, params: `[topic]`, result: subscription id.
+pub const SUBSCRIBE_METHOD: &str = "subscribe";
+
+/// the method of unsubscribing, params: `[subscription id]`, result: bool.
+pub const UNSUBSCRIBE_METHOD: &str = "unsubscribe";
+
+/// the method of the pushed notification, params:
+/// `{"subscription": id, "topic": topic, "result": params}`.
+pub const SUBSCRIPTION_METHOD: &str = "subscription";
+
+/// the topic subscriptions of WS (and channel) connections.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    next_id: u64,
+    /// topic => (subscription id, connection id).
+    topics: HashMap<String, Vec<(u64, u64)>>,
+    /// subscription id => (connection id, topic).
+    subs: HashMap<u64, (u64, String)>,
+}
+
+impl Subscriptions {
+    /// subscribe the topic, return the subscription id.
+    pub fn subscribe(&mut self, conn: u64, topic: String) -> u64 {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.topics
+            .entry(topic.clone())
+            .or_default()
+            .push((id, conn));
+        self.subs.insert(id, (conn, topic));
+        id
+    }
+
+    /// unsubscribe, only the connection's own subscription.
+    pub fn unsubscribe(&mut self, conn: u64, id: u64) -> bool {
+        match self.subs.get(&id) {
+            Some((c, _)) if *c == conn => {}
+            _ => return false,
+        }
+        if let Some((_, topic)) = self.subs.remove(&id) {
+            self.remove_topic(&topic, |(sub, _)| *sub == id);
+        }
+        true
+    }
+
+    /// clear all subscriptions of the closed connection.
+    pub fn close(&mut self, conn: u64) {
+        let ids: Vec<u64> = self
+            .subs
+            .iter()
+            .filter(|(_, (c, _))| *c == conn)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            if let Some((_, topic)) = self.subs.remove(&id) {
+                self.remove_topic(&topic, |(_, c)| *c == conn);
+            }
+        }
+    }
+
+    fn remove_topic(&mut self, topic: &str, f: impl Fn(&(u64, u64)) -> bool) {
+        if let Some(subs) = self.topics.get_mut(topic) {
+            subs.retain(|s| !f(s));
+            if subs.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+
+    /// the number of all subscriptions.
+    pub fn count(&self) -> usize {
+        self.subs.len()
+    }
+
+    /// the (subscription id, connection id) of the topic.
+    pub fn subscribers(&self, topic: &str) -> &[(u64, u64)] {
+        self.topics.get(topic).map(|s| s.as_slice()).unwrap_or(&[])
+    }
+
+    /// handle the subscribe or unsubscribe request of the connection,
+    /// return none if it is not, or the response (null if notification).
+    pub fn handle(&mut self, conn: u64, params: &RpcParam) -> Option<RpcParam> {
+        let method = params["method"].as_str()?;
+        let result = match method {
+            SUBSCRIBE_METHOD => match params["params"][0].as_str() {
+                Some(topic) => Ok(json!(self.subscribe(conn, topic.to_owned()))),
+                None => Err("Invalid Request: subscribe need the topic"),
+            },
+            UNSUBSCRIBE_METHOD => match params["params"][0].as_u64() {
+                Some(id) => Ok(json!(self.unsubscribe(conn, id))),
+                None => Err("Invalid Request: unsubscribe need the subscription id"),
+            },
+            _ => return None,
+        };
+
+        let id = match params.get("id") {
+            Some(id) => id.clone(),
+            None => return Some(RpcParam::Null),
+        };
+        let mut res = match result {
+            Ok(result) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "result": result,
+            }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32600,
+                    "message": message
+                }
+            }),
+        };
+        if let Some(gid) = params.get("gid") {
+            res["gid"] = gid.clone();
+        }
+        Some(res)
+    }
+}
+
+/// the pushed notification of the subscription.
+pub(crate) fn subscription_notification(id: u64, topic: &str, params: RpcParam) -> RpcParam {
+    json!({
+        "jsonrpc": "2.0",
+        "method": SUBSCRIPTION_METHOD,
+        "params": {
+            "subscription": id,
+            "topic": topic,
+            "result": params,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriptions() {
+        let mut subs = Subscriptions::default();
+        let req = json!({"jsonrpc": "2.0", "id": 1, "method": "subscribe", "params": ["a"]});
+        let s1 = subs.handle(1, &req).unwrap()["result"].as_u64().unwrap();
+        let s2 = subs.subscribe(2, "a".to_owned());
+        let s3 = subs.subscribe(2, "b".to_owned());
+        assert_eq!(subs.subscribers("a"), &[(s1, 1), (s2, 2)]);
+
+        let req = json!({"jsonrpc": "2.0", "id": 2, "method": "unsubscribe", "params": [s2]});
+        assert_eq!(subs.handle(1, &req).unwrap()["result"], json!(false));
+        assert_eq!(subs.handle(2, &req).unwrap()["result"], json!(true));
+        assert_eq!(subs.subscribers("a"), &[(s1, 1)]);
+
+        subs.close(2);
+        assert!(subs.subscribers("b").is_empty());
+        assert!(!subs.unsubscribe(2, s3));
+
+        let req = json!({"jsonrpc": "2.0", "method": "subscribe"});
+        assert_eq!(subs.handle(1, &req), Some(RpcParam::Null));
+        let req = json!({"jsonrpc": "2.0", "id": 3, "method": "echo"});
+        assert!(subs.handle(1, &req).is_none());
+    }
+}
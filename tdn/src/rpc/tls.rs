@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use tdn_types::primitives::{new_io_error, Result};
+
+/// TLS of the HTTP and WS rpc listeners (HTTPS and WSS).
+#[derive(Clone, Debug)]
+pub struct RpcTlsConfig {
+    /// the PEM certificate chain file.
+    pub cert: PathBuf,
+    /// the PEM private key file.
+    pub key: PathBuf,
+    /// the PEM CA certificates file, if set, clients must have a
+    /// certificate signed by them (mTLS).
+    pub client_ca: Option<PathBuf>,
+}
+
+impl RpcTlsConfig {
+    pub fn new(cert: PathBuf, key: PathBuf) -> Self {
+        Self {
+            cert,
+            key,
+            client_ca: None,
+        }
+    }
+
+    /// need client certificate signed by the CA.
+    pub fn with_client_ca(mut self, client_ca: PathBuf) -> Self {
+        self.client_ca = Some(client_ca);
+        self
+    }
+
+    /// load the certificates and key, build the acceptor.
+    pub(crate) async fn acceptor(&self) -> Result<TlsAcceptor> {
+        let certs = load_certs(&self.cert).await?;
+        let key = load_key(&self.key).await?;
+
+        let provider = Arc::new(default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if let Some(path) = &self.client_ca {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path).await? {
+                roots.add(cert)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let config = builder.with_single_cert(certs, key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+async fn load_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>> {
+    let bytes = fs::read(path).await?;
+    let certs =
+        rustls_pemfile::certs(&mut bytes.as_slice()).collect::<std::io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(new_io_error("TLS certificate not found").into());
+    }
+    Ok(certs)
+}
+
+async fn load_key(path: &PathBuf) -> Result<PrivateKeyDer<'static>> {
+    let bytes = fs::read(path).await?;
+    rustls_pemfile::private_key(&mut bytes.as_slice())?
+        .ok_or(new_io_error("TLS private key not found").into())
+}
@@ -4,22 +4,117 @@ use rand_chacha::{
 };
 use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::{
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
     select,
     sync::mpsc::Sender,
+    time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::StatusCode,
+        protocol::{frame::coding::CloseCode, CloseFrame, Message as WsMessage},
+    },
 };
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message as WsMessage};
 
-use tdn_types::{primitives::Result, rpc::parse_jsonrpc};
+use tdn_types::{
+    primitives::Result,
+    rpc::{parse_jsonrpc, RpcError, RpcParam},
+};
 
 use futures_util::{SinkExt, StreamExt};
 
+use crate::shutdown::ShutdownSignal;
+
+use super::auth::{inject_session, query_value, AuthNonces, AuthRequest, RpcAuth};
+use super::limit::{reject, ConnectionGuard, Limiter};
 use super::{rpc_channel, RpcMessage};
 
-pub(crate) async fn ws_listen(send: Sender<RpcMessage>, listener: TcpListener) -> Result<()> {
-    while let Ok((stream, addr)) = listener.accept().await {
-        tokio::spawn(ws_connection(send.clone(), stream, addr));
+/// the response of the connection over the max connections.
+const TOO_MANY_RESPONSE: &[u8] =
+    b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn ws_listen(
+    send: Sender<RpcMessage>,
+    listener: TcpListener,
+    auth: RpcAuth,
+    nonces: Arc<AuthNonces>,
+    acceptor: Option<TlsAcceptor>,
+    read_timeout: Duration,
+    queue_size: usize,
+    limiter: Arc<Limiter>,
+    mut signal: ShutdownSignal,
+) -> Result<()> {
+    loop {
+        // stop accepting when shutdown.
+        let (stream, addr) = select! {
+            v = listener.accept() => match v {
+                Ok(v) => v,
+                Err(_) => break,
+            },
+            _ = signal.wait() => break,
+        };
+        // over the max connections, reject before the handshake.
+        let guard = match limiter.connect() {
+            Some(guard) => guard,
+            None => {
+                debug!("DEBUG: WS connections reach the max: {}", addr);
+                if acceptor.is_none() {
+                    reject(stream, TOO_MANY_RESPONSE);
+                }
+                continue;
+            }
+        };
+        let send = send.clone();
+        let auth = auth.clone();
+        let nonces = nonces.clone();
+        let limiter = limiter.clone();
+        let signal = signal.clone();
+        if let Some(acceptor) = acceptor.clone() {
+            tokio::spawn(async move {
+                match timeout(read_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        ws_connection(
+                            send,
+                            stream,
+                            addr,
+                            auth,
+                            nonces,
+                            read_timeout,
+                            queue_size,
+                            limiter,
+                            guard,
+                            signal,
+                        )
+                        .await
+                    }
+                    _ => {
+                        debug!("DEBUG: WSS handshake failure: {}", addr);
+                        Ok(())
+                    }
+                }
+            });
+        } else {
+            tokio::spawn(ws_connection(
+                send,
+                stream,
+                addr,
+                auth,
+                nonces,
+                read_timeout,
+                queue_size,
+                limiter,
+                guard,
+                signal,
+            ));
+        }
     }
 
     Ok(())
@@ -28,21 +123,64 @@ pub(crate) async fn ws_listen(send: Sender<RpcMessage>, listener: TcpListener) -
 enum FutureResult {
     Out(RpcMessage),
     Stream(WsMessage),
+    Shutdown,
 }
 
-async fn ws_connection(
+// the handshake error response is defined by tungstenite.
+#[allow(clippy::result_large_err, clippy::too_many_arguments)]
+async fn ws_connection<S: AsyncRead + AsyncWrite + Unpin>(
     send: Sender<RpcMessage>,
-    raw_stream: TcpStream,
+    raw_stream: S,
     addr: SocketAddr,
+    auth: RpcAuth,
+    nonces: Arc<AuthNonces>,
+    read_timeout: Duration,
+    queue_size: usize,
+    limiter: Arc<Limiter>,
+    _guard: ConnectionGuard,
+    mut signal: ShutdownSignal,
 ) -> Result<()> {
-    let ws_stream = accept_async(raw_stream)
+    let mut scopes = vec![];
+    let callback = |req: &Request, res: Response| {
+        let query = req.uri().query().unwrap_or("");
+        let token = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().strip_prefix("Bearer "))
+            .or_else(|| query_value(query, "token"));
+        // ws handshake has no body.
+        match auth.check(
+            &nonces,
+            AuthRequest {
+                token,
+                timestamp: query_value(query, "timestamp"),
+                nonce: query_value(query, "nonce"),
+                signature: query_value(query, "signature"),
+                path: req.uri().path(),
+                body: b"",
+            },
+        ) {
+            Some(s) => {
+                scopes = s;
+                Ok(res)
+            }
+            None => {
+                let mut err = ErrorResponse::new(Some("Unauthorized".to_owned()));
+                *err.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(err)
+            }
+        }
+    };
+    let ws_stream = timeout(read_timeout, accept_hdr_async(raw_stream, callback))
         .await
+        .map_err(|_e| Error::new(ErrorKind::TimedOut, "Accept WebSocket Timeout!"))?
         .map_err(|_e| Error::new(ErrorKind::Other, "Accept WebSocket Failure!"))?;
     debug!("DEBUG: WebSocket connection established: {}", addr);
 
     let mut rng = ChaChaRng::from_entropy();
     let id: u64 = rng.next_u64();
-    let (s_send, mut s_recv) = rpc_channel();
+    let (s_send, mut s_recv) = rpc_channel(queue_size);
     send.send(RpcMessage::Open(id, s_send)).await?;
 
     let (mut writer, mut reader) = ws_stream.split();
@@ -57,6 +195,7 @@ async fn ws_connection(
                     .map(|msg| msg.map(|msg| FutureResult::Stream(msg)).ok())
                     .flatten()
             } => v,
+            _ = signal.wait() => Some(FutureResult::Shutdown),
         };
 
         match res {
@@ -76,8 +215,18 @@ async fn ws_connection(
                     continue;
                 }
 
-                match parse_jsonrpc(msg.to_owned()) {
-                    Ok(rpc_param) => {
+                let parsed = parse_jsonrpc(msg.to_owned());
+                let limit_param = parsed.as_ref().unwrap_or(&RpcParam::Null);
+                if let Err(limited) = limiter.check(addr.ip(), limit_param) {
+                    let id = limit_param.get("id").cloned().unwrap_or_default();
+                    let s = WsMessage::from(RpcError::LimitExceeded(limited).json(id).to_string());
+                    let _ = writer.send(s).await;
+                    continue;
+                }
+
+                match parsed {
+                    Ok(mut rpc_param) => {
+                        inject_session(&mut rpc_param, id, &scopes);
                         send.send(RpcMessage::Request(id, rpc_param, None)).await?;
                     }
                     Err((err, id)) => {
@@ -86,6 +235,18 @@ async fn ws_connection(
                     }
                 }
             }
+            Some(FutureResult::Shutdown) => {
+                // send the queued responses, then close.
+                while let Ok(RpcMessage::Response(param)) = s_recv.try_recv() {
+                    let _ = writer.feed(WsMessage::from(param.to_string())).await;
+                }
+                let frame = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "Server shutdown".into(),
+                };
+                let _ = writer.send(WsMessage::Close(Some(frame))).await;
+                break;
+            }
             None => break,
         }
     }
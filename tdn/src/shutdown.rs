@@ -0,0 +1,119 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, watch},
+    time::timeout,
+};
+
+use tdn_types::primitives::{new_io_error, Result};
+
+/// the shutdown handle of TDN service (p2p network, dispatcher and RPC).
+pub struct Shutdown {
+    signal: Arc<watch::Sender<bool>>,
+    done_send: mpsc::Sender<()>,
+    done_recv: mpsc::Receiver<()>,
+    drain_timeout: Duration,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (signal, _) = watch::channel(false);
+        let (done_send, done_recv) = mpsc::channel(1);
+        Shutdown {
+            signal: Arc::new(signal),
+            done_send,
+            done_recv,
+            drain_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// max time of draining the in-flight requests, default is 10s.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// the signal for the tasks, shutdown will wait until all of them dropped.
+    pub(crate) fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            signal: self.signal.clone(),
+            recv: self.signal.subscribe(),
+            _done: self.done_send.clone(),
+        }
+    }
+
+    /// stop accepting, drain in-flight requests, and wait everything down.
+    /// return error if not down before the drain timeout.
+    pub async fn shutdown(self) -> Result<()> {
+        self.signal.send_replace(true);
+        let drain_timeout = self.drain_timeout;
+        timeout(drain_timeout, self.wait())
+            .await
+            .map_err(|_| new_io_error("Shutdown timeout").into())
+    }
+
+    /// wait everything down, e.g. after `NetworkType::NetworkStop`.
+    pub async fn wait(self) {
+        let Shutdown {
+            done_send,
+            mut done_recv,
+            ..
+        } = self;
+        drop(done_send);
+        let _ = done_recv.recv().await;
+    }
+}
+
+/// the shutdown signal of a task, the task is down when it dropped.
+#[derive(Clone)]
+pub(crate) struct ShutdownSignal {
+    signal: Arc<watch::Sender<bool>>,
+    recv: watch::Receiver<bool>,
+    _done: mpsc::Sender<()>,
+}
+
+impl ShutdownSignal {
+    /// start the shutdown of all tasks.
+    pub fn trigger(&self) {
+        self.signal.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.recv.borrow()
+    }
+
+    /// wait until the shutdown started.
+    pub async fn wait(&mut self) {
+        let _ = self.recv.wait_for(|s| *s).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let shutdown = Shutdown::new().with_drain_timeout(Duration::from_millis(500));
+        let mut signal = shutdown.signal();
+        tokio::spawn(async move {
+            signal.wait().await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        });
+        let start = tokio::time::Instant::now();
+        assert!(shutdown.shutdown().await.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let shutdown = Shutdown::new().with_drain_timeout(Duration::from_millis(50));
+        let signal = shutdown.signal();
+        signal.trigger();
+        assert!(signal.is_shutdown());
+        assert!(shutdown.shutdown().await.is_err());
+    }
+}
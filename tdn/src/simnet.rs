@@ -0,0 +1,611 @@
+//! In-process simulated p2p network.
+//!
+//! `SimNet` stands in for chamomile under `start_main_with_network`, so many
+//! TDN nodes can live in one process and exchange messages without sockets.
+//! Latency, packet loss and partitions are configurable, and loss uses a
+//! seeded rng, so multi-node tests of Group/Layer routing are deterministic.
+//!
+//! Supported messages: `StableConnect`, `StableResult`, `StableDisconnect`,
+//! `Data`, `Broadcast` and their `Delivery` feedback. `Stream` is forwarded
+//! when the node has exactly one stable connected peer. DHT control
+//! (`Connect`, `DisConnect`, `NetworkState`, `NetworkReboot`) is ignored.
+
+use chamomile::prelude::{
+    DeliveryType as P2pDeliveryType, Peer as P2pPeer, ReceiveMessage as P2pReceiveMessage,
+    SendMessage as P2pSendMessage,
+};
+use futures_util::future::join_all;
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaChaRng,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tdn_types::{
+    frame::FRAME_HEADER_LENGTH,
+    primitives::{Peer, PeerId},
+};
+use tokio::sync::{
+    mpsc::{self, Sender},
+    Mutex,
+};
+
+use crate::P2pNetwork;
+
+/// Same length of delivery data which TDN config to chamomile.
+const DELIVERY_LENGTH: usize = FRAME_HEADER_LENGTH;
+
+struct Node {
+    sender: Sender<P2pReceiveMessage>,
+    stables: HashSet<PeerId>,
+}
+
+struct Inner {
+    nodes: HashMap<PeerId, Node>,
+    partitions: HashSet<(PeerId, PeerId)>,
+    latency: Duration,
+    loss: f64,
+    rng: ChaChaRng,
+}
+
+impl Inner {
+    fn is_reachable(&mut self, from: &PeerId, to: &PeerId) -> bool {
+        if !self.nodes.contains_key(to) || self.partitions.contains(&(*from, *to)) {
+            return false;
+        }
+
+        if self.loss > 0.0 {
+            let roll = self.rng.next_u64() as f64 / u64::MAX as f64;
+            if roll < self.loss {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// The in-process network shared by all simulated nodes.
+#[derive(Clone)]
+pub struct SimNet {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for SimNet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimNet {
+    /// new a network without latency and loss.
+    pub fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    /// new a network, the seed is used to decide which packet will be lost.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                nodes: HashMap::new(),
+                partitions: HashSet::new(),
+                latency: Duration::from_millis(0),
+                loss: 0.0,
+                rng: ChaChaRng::seed_from_u64(seed),
+            })),
+        }
+    }
+
+    /// set the latency of every message.
+    pub async fn set_latency(&self, latency: Duration) {
+        self.inner.lock().await.latency = latency;
+    }
+
+    /// set the probability (0.0 ~ 1.0) of message loss.
+    pub async fn set_loss(&self, loss: f64) {
+        self.inner.lock().await.loss = loss.clamp(0.0, 1.0);
+    }
+
+    /// split the network, peers in `a` cannot reach peers in `b`, and reverse.
+    pub async fn partition(&self, a: &[PeerId], b: &[PeerId]) {
+        let mut inner = self.inner.lock().await;
+        for x in a {
+            for y in b {
+                inner.partitions.insert((*x, *y));
+                inner.partitions.insert((*y, *x));
+            }
+        }
+    }
+
+    /// remove all partitions.
+    pub async fn heal(&self) {
+        self.inner.lock().await.partitions.clear();
+    }
+
+    /// add a node to the network, return the p2p channels as the network
+    /// of `start_main_with_network`.
+    pub async fn join(&self, peer_id: PeerId) -> P2pNetwork {
+        let (p2p_send, mut self_recv) = mpsc::channel(1024);
+        let (self_send, p2p_recv) = mpsc::channel(1024);
+
+        self.inner.lock().await.nodes.insert(
+            peer_id,
+            Node {
+                sender: self_send,
+                stables: HashSet::new(),
+            },
+        );
+
+        let net = self.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = self_recv.recv().await {
+                if let P2pSendMessage::NetworkStop = msg {
+                    break;
+                }
+                // with latency, the messages are in flight together.
+                if net.inner.lock().await.latency.is_zero() {
+                    net.handle(peer_id, msg).await;
+                } else {
+                    let net = net.clone();
+                    tokio::spawn(async move { net.handle(peer_id, msg).await });
+                }
+            }
+
+            net.leave(peer_id).await;
+        });
+
+        (peer_id, p2p_send, p2p_recv)
+    }
+
+    async fn leave(&self, peer_id: PeerId) {
+        let mut senders = vec![];
+        let mut inner = self.inner.lock().await;
+        if let Some(node) = inner.nodes.remove(&peer_id) {
+            for pid in node.stables {
+                if let Some(n) = inner.nodes.get_mut(&pid) {
+                    n.stables.remove(&peer_id);
+                    senders.push(n.sender.clone());
+                }
+            }
+        }
+        drop(inner);
+
+        let me: P2pPeer = Peer::peer(peer_id).into();
+        for sender in senders {
+            let _ = sender.send(P2pReceiveMessage::StableLeave(me)).await;
+        }
+    }
+
+    async fn handle(&self, from: PeerId, msg: P2pSendMessage) {
+        let me: P2pPeer = Peer::peer(from).into();
+
+        match msg {
+            P2pSendMessage::StableConnect(tid, peer, data) => {
+                let delivery = delivery_data(&data);
+                let is_ok = self
+                    .deliver(from, peer.id, P2pReceiveMessage::StableConnect(me, data))
+                    .await;
+                self.feedback(from, P2pDeliveryType::StableConnect, tid, is_ok, delivery)
+                    .await;
+            }
+            P2pSendMessage::StableResult(tid, peer, is_ok, _is_force, data) => {
+                let delivery = delivery_data(&data);
+                let is_sended = self
+                    .deliver(
+                        from,
+                        peer.id,
+                        P2pReceiveMessage::StableResult(me, is_ok, data),
+                    )
+                    .await;
+                if is_ok && is_sended {
+                    let mut inner = self.inner.lock().await;
+                    if let Some(node) = inner.nodes.get_mut(&from) {
+                        node.stables.insert(peer.id);
+                    }
+                    if let Some(node) = inner.nodes.get_mut(&peer.id) {
+                        node.stables.insert(from);
+                    }
+                }
+                self.feedback(
+                    from,
+                    P2pDeliveryType::StableResult,
+                    tid,
+                    is_sended,
+                    delivery,
+                )
+                .await;
+            }
+            P2pSendMessage::StableDisconnect(peer_id) => {
+                let mut inner = self.inner.lock().await;
+                if let Some(node) = inner.nodes.get_mut(&from) {
+                    node.stables.remove(&peer_id);
+                }
+                let sender = inner.nodes.get_mut(&peer_id).and_then(|node| {
+                    if node.stables.remove(&from) {
+                        Some(node.sender.clone())
+                    } else {
+                        None
+                    }
+                });
+                drop(inner);
+
+                if let Some(sender) = sender {
+                    let _ = sender.send(P2pReceiveMessage::StableLeave(me)).await;
+                }
+            }
+            P2pSendMessage::Data(tid, peer_id, data) => {
+                let delivery = delivery_data(&data);
+                let is_ok = self
+                    .deliver(from, peer_id, P2pReceiveMessage::Data(from, data))
+                    .await;
+                if tid != 0 {
+                    self.feedback(from, P2pDeliveryType::Data, tid, is_ok, delivery)
+                        .await;
+                }
+            }
+            P2pSendMessage::Broadcast(_, data) => {
+                let peers: Vec<PeerId> = self
+                    .inner
+                    .lock()
+                    .await
+                    .nodes
+                    .keys()
+                    .filter(|p| **p != from)
+                    .cloned()
+                    .collect();
+                join_all(peers.into_iter().map(|peer_id| {
+                    self.deliver(from, peer_id, P2pReceiveMessage::Data(from, data.clone()))
+                }))
+                .await;
+            }
+            P2pSendMessage::Stream(id, stream, data) => {
+                let stables: Vec<PeerId> = self
+                    .inner
+                    .lock()
+                    .await
+                    .nodes
+                    .get(&from)
+                    .map(|n| n.stables.iter().cloned().collect())
+                    .unwrap_or_default();
+                if stables.len() == 1 {
+                    self.deliver(
+                        from,
+                        stables[0],
+                        P2pReceiveMessage::Stream(id, stream, data),
+                    )
+                    .await;
+                } else {
+                    warn!("SimNet stream needs only one stable connection");
+                }
+            }
+            _ => {} // DHT & network control is not simulated.
+        }
+    }
+
+    /// send the message to the peer after the latency, return if it is sended.
+    async fn deliver(&self, from: PeerId, to: PeerId, msg: P2pReceiveMessage) -> bool {
+        let mut inner = self.inner.lock().await;
+        if !inner.is_reachable(&from, &to) {
+            return false;
+        }
+
+        let latency = inner.latency;
+        let sender = inner.nodes.get(&to).map(|n| n.sender.clone()).unwrap();
+        drop(inner);
+
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        sender.send(msg).await.is_ok()
+    }
+
+    async fn feedback(&self, to: PeerId, t: P2pDeliveryType, tid: u64, is_ok: bool, data: Vec<u8>) {
+        let sender = self
+            .inner
+            .lock()
+            .await
+            .nodes
+            .get(&to)
+            .map(|n| n.sender.clone());
+        if let Some(sender) = sender {
+            let _ = sender
+                .send(P2pReceiveMessage::Delivery(t, tid, is_ok, data))
+                .await;
+        }
+    }
+}
+
+fn delivery_data(data: &[u8]) -> Vec<u8> {
+    data[..std::cmp::min(data.len(), DELIVERY_LENGTH)].to_vec()
+}
+
+#[cfg(all(test, tdn_mode = "std"))]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use tdn_types::primitives::{secp256k1, PeerSecretKey};
+
+    async fn node(
+        net: &SimNet,
+        seed: u8,
+    ) -> (PeerId, Sender<SendMessage>, mpsc::Receiver<ReceiveMessage>) {
+        node_with_router(net, seed, None).await
+    }
+
+    async fn node_with_router(
+        net: &SimNet,
+        seed: u8,
+        router: Option<Arc<dyn Router>>,
+    ) -> (PeerId, Sender<SendMessage>, mpsc::Receiver<ReceiveMessage>) {
+        let sk = secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap();
+        let key = PeerKey::from_sec_key(PeerSecretKey::new(sk));
+        let (send_send, send_recv) = new_send_channel();
+        let (recv_send, recv_recv) = new_receive_channel();
+        let network = net.join(key.peer_id()).await;
+        let shutdown = Shutdown::new();
+        let peer_id = start_main_with_network(
+            vec![1],
+            network,
+            recv_send,
+            send_recv,
+            None,
+            Some(key),
+            router,
+            &shutdown,
+        )
+        .await
+        .unwrap();
+        (peer_id, send_send, recv_recv)
+    }
+
+    #[tokio::test]
+    async fn test_simnet_group_and_layer() {
+        let net = SimNet::new();
+        let (a_id, a_send, _a_recv) = node(&net, 1).await;
+        let (b_id, _b_send, mut b_recv) = node(&net, 2).await;
+
+        a_send
+            .send(SendMessage::Group(SendType::Event(0, b_id, vec![1, 2, 3])))
+            .await
+            .unwrap();
+        match b_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Event(peer_id, data)) => {
+                assert_eq!(peer_id, a_id);
+                assert_eq!(data, vec![1, 2, 3]);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        a_send
+            .send(SendMessage::Layer(2, SendType::Event(0, b_id, vec![4])))
+            .await
+            .unwrap();
+        match b_recv.recv().await.unwrap() {
+            ReceiveMessage::Layer(fgid, tgid, RecvType::Event(peer_id, data)) => {
+                assert_eq!((fgid, tgid), (1, 2));
+                assert_eq!(peer_id, a_id);
+                assert_eq!(data, vec![4]);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simnet_frame_hello() {
+        let net = SimNet::new();
+        let (a_id, a_send, mut a_recv) = node(&net, 1).await;
+        let (b_id, b_send, mut b_recv) = node(&net, 2).await;
+
+        // the hello is handled inside, only group messages outside.
+        a_send
+            .send(SendMessage::Group(SendType::Connect(
+                0,
+                Peer::peer(b_id),
+                vec![1],
+            )))
+            .await
+            .unwrap();
+        match b_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Connect(peer, data)) => {
+                assert_eq!((peer.id, data), (a_id, vec![1]));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        b_send
+            .send(SendMessage::Group(SendType::Result(
+                0,
+                Peer::peer(a_id),
+                true,
+                false,
+                vec![2],
+            )))
+            .await
+            .unwrap();
+        for recv in [&mut a_recv, &mut b_recv] {
+            match recv.recv().await.unwrap() {
+                ReceiveMessage::Group(RecvType::Delivery(_, tid, is_sended)) => {
+                    assert!(tid == 0 && is_sended);
+                }
+                msg => panic!("unexpected message: {:?}", msg),
+            }
+        }
+        match a_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Result(peer, is_ok, data)) => {
+                assert_eq!((peer.id, is_ok, data), (b_id, true, vec![2]));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        a_send
+            .send(SendMessage::Group(SendType::Event(1, b_id, vec![3])))
+            .await
+            .unwrap();
+        match b_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Event(peer_id, data)) => {
+                assert_eq!((peer_id, data), (a_id, vec![3]));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+        match a_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Delivery(_, tid, is_sended)) => {
+                assert!(tid == 1 && is_sended);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    /// drop the empty events, others are own's.
+    struct OwnRouter;
+
+    impl Router for OwnRouter {
+        fn route(&self, _: GroupId, _: GroupId, msg: RecvType, _: &[GroupId]) -> Route {
+            match msg {
+                RecvType::Event(_, ref data) if data.is_empty() => Route::Drop,
+                msg => Route::Own(msg),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simnet_router() {
+        let net = SimNet::new();
+        let (a_id, a_send, _a_recv) = node(&net, 1).await;
+        let (b_id, _b_send, mut b_recv) =
+            node_with_router(&net, 2, Some(Arc::new(OwnRouter))).await;
+
+        for data in [vec![], vec![1]] {
+            a_send
+                .send(SendMessage::Group(SendType::Event(0, b_id, data)))
+                .await
+                .unwrap();
+        }
+        match b_recv.recv().await.unwrap() {
+            ReceiveMessage::Own(RecvType::Event(peer_id, data)) => {
+                assert_eq!((peer_id, data), (a_id, vec![1]));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    async fn unified_node(
+        net: &SimNet,
+        seed: u8,
+        mode: Mode,
+        groups: Vec<GroupId>,
+    ) -> (
+        PeerId,
+        Sender<unified::SendMessage>,
+        mpsc::Receiver<unified::ReceiveMessage>,
+    ) {
+        let sk = secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap();
+        let key = PeerKey::from_sec_key(PeerSecretKey::new(sk));
+        let (send_send, send_recv) = new_unified_send_channel();
+        let (recv_send, recv_recv) = new_unified_receive_channel();
+        let network = net.join(key.peer_id()).await;
+        let shutdown = Shutdown::new();
+        let peer_id = start_unified_main_with_network(
+            mode,
+            groups,
+            network,
+            recv_send,
+            send_recv,
+            None,
+            Some(key),
+            None,
+            &shutdown,
+        )
+        .await
+        .unwrap();
+        (peer_id, send_send, recv_recv)
+    }
+
+    #[tokio::test]
+    async fn test_simnet_unified_mode() {
+        let net = SimNet::new();
+        let (a_id, a_send, _a_recv) = unified_node(&net, 1, Mode::Full, vec![1, 2]).await;
+        let (b_id, _b_send, mut b_recv) = unified_node(&net, 2, Mode::Single, vec![1]).await;
+
+        // single mode has no layers, so the layer message is dropped.
+        a_send
+            .send(unified::SendMessage::Layer(
+                2,
+                1,
+                SendType::Event(0, b_id, vec![1]),
+            ))
+            .await
+            .unwrap();
+        a_send
+            .send(unified::SendMessage::Group(
+                1,
+                SendType::Event(0, b_id, vec![2]),
+            ))
+            .await
+            .unwrap();
+        match b_recv.recv().await.unwrap() {
+            unified::ReceiveMessage::Group(1, RecvType::Event(peer_id, data)) => {
+                assert_eq!((peer_id, data), (a_id, vec![2]));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        let network = net.join(PeerId::default()).await;
+        let (recv_send, _recv_recv) = new_unified_receive_channel();
+        let (_send_send, send_recv) = new_unified_send_channel();
+        let res = start_unified_main_with_network(
+            Mode::Std,
+            vec![1, 2],
+            network,
+            recv_send,
+            send_recv,
+            None,
+            None,
+            None,
+            &Shutdown::new(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_simnet_partition() {
+        let net = SimNet::new();
+        let (a_id, a_send, mut a_recv) = node(&net, 1).await;
+        let (b_id, _b_send, _b_recv) = node(&net, 2).await;
+
+        net.partition(&[a_id], &[b_id]).await;
+        a_send
+            .send(SendMessage::Group(SendType::Event(1, b_id, vec![1])))
+            .await
+            .unwrap();
+        match a_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Delivery(_, tid, is_sended)) => {
+                assert_eq!(tid, 1);
+                assert!(!is_sended);
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simnet_latency() {
+        let net = SimNet::new();
+        net.set_latency(Duration::from_millis(50)).await;
+        let (_a_id, a_send, mut a_recv) = node(&net, 1).await;
+        let (b_id, _b_send, _b_recv) = node(&net, 2).await;
+
+        let start = std::time::Instant::now();
+        a_send
+            .send(SendMessage::Group(SendType::Event(1, b_id, vec![1])))
+            .await
+            .unwrap();
+        match a_recv.recv().await.unwrap() {
+            ReceiveMessage::Group(RecvType::Delivery(_, tid, is_sended)) => {
+                assert_eq!(tid, 1);
+                assert!(is_sended);
+                assert!(start.elapsed() >= Duration::from_millis(50));
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+}
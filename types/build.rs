@@ -0,0 +1,22 @@
+//! the mode features can be combined (e.g. a workspace has a single-group
+//! tool and a full node), the legacy message shapes are the union of them:
+//! multiple groups (`multiple`, `full`) and layers (`std`, `full`).
+fn main() {
+    let groups = has_feature("MULTIPLE") || has_feature("FULL");
+    let layers = has_feature("STD") || has_feature("FULL");
+    let mode = match (groups, layers) {
+        (false, false) => "single",
+        (false, true) => "std",
+        (true, false) => "multiple",
+        (true, true) => "full",
+    };
+
+    println!(
+        "cargo:rustc-check-cfg=cfg(tdn_mode, values(\"single\", \"std\", \"multiple\", \"full\"))"
+    );
+    println!("cargo:rustc-cfg=tdn_mode=\"{}\"", mode);
+}
+
+fn has_feature(name: &str) -> bool {
+    std::env::var_os(format!("CARGO_FEATURE_{}", name)).is_some()
+}
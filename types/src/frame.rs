@@ -0,0 +1,230 @@
+use crate::group::{GroupId, GROUP_BYTES_LENGTH};
+use crate::primitives::Result;
+
+/// the first byte of the versioned frame.
+pub const FRAME_MAGIC: u8 = 0xFD;
+
+/// the newest frame version, 0 is the legacy layout (fgid + tgid + data).
+pub const FRAME_VERSION: u8 = 1;
+
+/// the legacy frame header: fgid + tgid.
+pub const LEGACY_HEADER_LENGTH: usize = GROUP_BYTES_LENGTH * 2;
+
+/// the frame header: magic + version + flags + type + fgid + tgid.
+pub const FRAME_HEADER_LENGTH: usize = 4 + GROUP_BYTES_LENGTH * 2;
+
+/// the hello frame has no gids: magic + version + flags + type.
+/// legacy receivers drop it, because it is shorter than the legacy header.
+pub const HELLO_LENGTH: usize = 4;
+
+/// the data is compressed.
+pub const FLAG_COMPRESSED: u8 = 0b0000_0001;
+/// the data is encrypted.
+pub const FLAG_ENCRYPTED: u8 = 0b0000_0010;
+/// the data is a fragment of a large message.
+pub const FLAG_FRAGMENTED: u8 = 0b0000_0100;
+
+/// the type of frame.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FrameType {
+    /// the group or layer message.
+    Data,
+    /// the version negotiation, sended after stable connection.
+    Hello,
+}
+
+impl FrameType {
+    fn to_byte(self) -> u8 {
+        match self {
+            FrameType::Data => 0,
+            FrameType::Hello => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(FrameType::Data),
+            1 => Some(FrameType::Hello),
+            _ => None,
+        }
+    }
+}
+
+/// the header of group and layer frames between peers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// the frame version, 0 is the legacy layout.
+    pub version: u8,
+    pub flags: u8,
+    pub ftype: FrameType,
+    pub fgid: GroupId,
+    pub tgid: GroupId,
+}
+
+impl Frame {
+    /// the data frame, version is 0 (legacy) or `FRAME_VERSION`.
+    pub fn new(version: u8, fgid: GroupId, tgid: GroupId) -> Self {
+        Frame {
+            version,
+            flags: 0,
+            ftype: FrameType::Data,
+            fgid,
+            tgid,
+        }
+    }
+
+    /// the hello frame of version negotiation.
+    pub fn hello() -> Self {
+        Frame {
+            version: FRAME_VERSION,
+            flags: 0,
+            ftype: FrameType::Hello,
+            fgid: 0,
+            tgid: 0,
+        }
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == 0
+    }
+
+    /// serialize the header and data to bytes.
+    pub fn to_bytes(&self, data: Vec<u8>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FRAME_HEADER_LENGTH + data.len());
+        if !self.is_legacy() {
+            bytes.extend([FRAME_MAGIC, self.version, self.flags, self.ftype.to_byte()]);
+        }
+        if self.ftype == FrameType::Data {
+            bytes.extend(&self.fgid.to_be_bytes());
+            bytes.extend(&self.tgid.to_be_bytes());
+        }
+        bytes.extend(data);
+        bytes
+    }
+
+    /// check the bytes is a hello of any version. it is unambiguous, because
+    /// the hello is shorter than the legacy header.
+    pub fn is_hello(bytes: &[u8]) -> bool {
+        bytes.len() == HELLO_LENGTH
+            && bytes[0] == FRAME_MAGIC
+            && bytes[1] > 0
+            && bytes[3] == FrameType::Hello.to_byte()
+    }
+
+    /// deserialize bytes to header and data by the negotiated version of the
+    /// sender, 0 is the legacy layout. the hello is read in any version.
+    /// the layout is not guessed from the magic byte: a legacy fgid can start
+    /// with the magic and a known version and type (about 2^-23 of group ids),
+    /// and then every frame of that group would be misread.
+    pub fn from_bytes(version: u8, mut bytes: Vec<u8>) -> Result<(Frame, Vec<u8>)> {
+        if Self::is_hello(&bytes) {
+            return Ok((
+                Frame {
+                    version: bytes[1],
+                    flags: bytes[2],
+                    ..Frame::hello()
+                },
+                vec![],
+            ));
+        }
+
+        let (version, flags, ftype, start) = if version == 0 {
+            (0, 0, FrameType::Data, 0)
+        } else {
+            if bytes.len() < HELLO_LENGTH || bytes[0] != FRAME_MAGIC || bytes[1] == 0 {
+                return Err(anyhow::anyhow!("frame is not versioned"));
+            }
+            let ftype = FrameType::from_byte(bytes[3])
+                .ok_or_else(|| anyhow::anyhow!("frame type is unknown"))?;
+            (bytes[1], bytes[2], ftype, HELLO_LENGTH)
+        };
+
+        if ftype == FrameType::Hello {
+            let data = bytes.split_off(start);
+            return Ok((
+                Frame {
+                    version,
+                    flags,
+                    ..Frame::hello()
+                },
+                data,
+            ));
+        }
+
+        if bytes.len() < start + LEGACY_HEADER_LENGTH {
+            return Err(anyhow::anyhow!("frame is too short"));
+        }
+        let mut fgid_bytes = [0u8; GROUP_BYTES_LENGTH];
+        let mut tgid_bytes = [0u8; GROUP_BYTES_LENGTH];
+        fgid_bytes.copy_from_slice(&bytes[start..start + GROUP_BYTES_LENGTH]);
+        tgid_bytes
+            .copy_from_slice(&bytes[start + GROUP_BYTES_LENGTH..start + LEGACY_HEADER_LENGTH]);
+        let data = bytes.split_off(start + LEGACY_HEADER_LENGTH);
+
+        Ok((
+            Frame {
+                version,
+                flags,
+                ftype,
+                fgid: GroupId::from_be_bytes(fgid_bytes),
+                tgid: GroupId::from_be_bytes(tgid_bytes),
+            },
+            data,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        let frame = Frame::new(FRAME_VERSION, 1, 2);
+        let bytes = frame.to_bytes(vec![3]);
+        assert_eq!(bytes.len(), FRAME_HEADER_LENGTH + 1);
+        assert_eq!(
+            Frame::from_bytes(FRAME_VERSION, bytes.clone()).unwrap(),
+            (frame, vec![3])
+        );
+        // the versioned frame is not read as legacy, and reverse.
+        assert!(Frame::from_bytes(0, bytes).unwrap().0.is_legacy());
+
+        // legacy layout.
+        let frame = Frame::new(0, 1, 2);
+        let mut bytes = 1u64.to_be_bytes().to_vec();
+        bytes.extend(&2u64.to_be_bytes());
+        bytes.push(3);
+        assert_eq!(frame.to_bytes(vec![3]), bytes);
+        assert_eq!(
+            Frame::from_bytes(0, bytes.clone()).unwrap(),
+            (frame, vec![3])
+        );
+        assert!(Frame::from_bytes(FRAME_VERSION, bytes).is_err());
+
+        // legacy fgid starts with the magic, version and type.
+        let fgid = GroupId::from_be_bytes([FRAME_MAGIC, FRAME_VERSION, 0, 0, 1, 2, 3, 4]);
+        let frame = Frame::new(0, fgid, 2);
+        let bytes = frame.to_bytes(vec![3]);
+        assert_eq!(Frame::from_bytes(0, bytes).unwrap(), (frame, vec![3]));
+
+        let bytes = Frame::hello().to_bytes(vec![]);
+        assert_eq!(bytes.len(), HELLO_LENGTH);
+        assert!(bytes.len() < LEGACY_HEADER_LENGTH);
+        for version in [0, FRAME_VERSION] {
+            let (frame, _) = Frame::from_bytes(version, bytes.clone()).unwrap();
+            assert_eq!(frame.ftype, FrameType::Hello);
+        }
+        let (frame, _) = Frame::from_bytes(0, vec![FRAME_MAGIC, 2, 0, 1]).unwrap();
+        assert_eq!((frame.version, frame.ftype), (2, FrameType::Hello));
+
+        // unknown type, and too short.
+        assert!(Frame::from_bytes(FRAME_VERSION, vec![FRAME_MAGIC, 1, 0, 9]).is_err());
+        assert!(Frame::from_bytes(0, vec![FRAME_MAGIC, 9, 0, 0]).is_err());
+        let mut frame = Frame::new(FRAME_VERSION, 1, 2);
+        frame.flags = FLAG_COMPRESSED | FLAG_FRAGMENTED;
+        let (new_frame, _) = Frame::from_bytes(FRAME_VERSION, frame.to_bytes(vec![])).unwrap();
+        assert_eq!(new_frame.flags & FLAG_ENCRYPTED, 0);
+        assert_eq!(new_frame.flags, frame.flags);
+    }
+}
@@ -1,5 +1,153 @@
-pub fn generate() {}
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn sign() {}
+use crate::group::GroupId;
+use crate::message::RecvType;
+use crate::primitives::{HandleResult, PeerId, PeerKey, PeerPublicKey, PeerSignature, Result};
 
-pub fn verify() {}
+/// nonce counter, every envelope in this process has different nonce.
+static NONCE: AtomicU64 = AtomicU64::new(0);
+
+/// Layer message envelope. it proves which peer sended the message between
+/// groups, and the groups/payload are not changed by others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// sender's group id.
+    pub fgid: GroupId,
+    /// receiver's group id.
+    pub tgid: GroupId,
+    /// sender's peer id.
+    pub peer: PeerId,
+    /// random number, with timestamp, make every envelope different.
+    pub nonce: u64,
+    /// unix timestamp (seconds) when generated.
+    pub timestamp: u64,
+    /// blake3 hash of the payload.
+    pub hash: [u8; 32],
+    /// sender's public key bytes.
+    pub pk: Vec<u8>,
+    /// signature bytes, signed by sender's PeerKey.
+    pub sign: Vec<u8>,
+}
+
+impl Envelope {
+    /// the bytes which need sign.
+    fn sign_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(&self.fgid.to_be_bytes());
+        bytes.extend(&self.tgid.to_be_bytes());
+        bytes.extend(self.peer.to_hex().as_bytes());
+        bytes.extend(&self.nonce.to_be_bytes());
+        bytes.extend(&self.timestamp.to_be_bytes());
+        bytes.extend(&self.hash);
+        bytes
+    }
+
+    /// serialize envelope and payload to bytes.
+    /// bytes: envelope length (u32) + envelope + payload.
+    pub fn to_bytes(&self, payload: Vec<u8>) -> Vec<u8> {
+        // envelope is plain struct, serialize will not fail.
+        let envelope = bincode::serialize(self).unwrap_or(vec![]);
+        let mut bytes = (envelope.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(envelope);
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// deserialize bytes to envelope and payload.
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Result<(Envelope, Vec<u8>)> {
+        if bytes.len() < 4 {
+            return Err(anyhow::anyhow!("layer envelope is invalid"));
+        }
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&bytes[..4]);
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        if bytes.len() < 4 + len {
+            return Err(anyhow::anyhow!("layer envelope is invalid"));
+        }
+
+        let envelope = bincode::deserialize(&bytes[4..4 + len])?;
+        let payload = bytes.split_off(4 + len);
+        Ok((envelope, payload))
+    }
+}
+
+/// generate a unsigned envelope for the payload.
+pub fn generate(fgid: GroupId, tgid: GroupId, peer: PeerId, payload: &[u8]) -> Envelope {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+
+    Envelope {
+        fgid,
+        tgid,
+        peer,
+        nonce: now.wrapping_add(NONCE.fetch_add(1, Ordering::Relaxed)),
+        timestamp: now / 1_000_000_000,
+        hash: *blake3::hash(payload).as_bytes(),
+        pk: vec![],
+        sign: vec![],
+    }
+}
+
+/// sign the envelope with the sender's PeerKey.
+pub fn sign(envelope: &mut Envelope, key: &PeerKey) {
+    envelope.pk = key.public().to_bytes();
+    envelope.sign = key.sign(&envelope.sign_bytes()).to_bytes();
+}
+
+/// verify the envelope's signature, sender and payload.
+pub fn verify(envelope: &Envelope, payload: &[u8]) -> bool {
+    if envelope.hash != *blake3::hash(payload).as_bytes() {
+        return false;
+    }
+
+    let pk = match PeerPublicKey::from_bytes(&envelope.pk) {
+        Ok(pk) => pk,
+        Err(_) => return false,
+    };
+    if pk.peer_id() != envelope.peer {
+        return false;
+    }
+
+    match PeerSignature::from_bytes(&envelope.sign) {
+        Ok(sign) => pk.verify(&envelope.sign_bytes(), &sign),
+        Err(_) => false,
+    }
+}
+
+/// Helper: this is the interface of the Layer in the network,
+/// it handles the messages between groups.
+pub trait Layer {
+    /// when receive layer message from `fgid` to `tgid`, handle it, and return HandleResult.
+    fn handle(&mut self, fgid: GroupId, tgid: GroupId, msg: RecvType) -> Result<HandleResult>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::primitives::{secp256k1::SecretKey, PeerSecretKey};
+
+    #[test]
+    fn test_layer_envelope() {
+        let sk = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let key = PeerKey::from_sec_key(PeerSecretKey::new(sk));
+        let payload = vec![1, 2, 3];
+
+        let mut envelope = generate(1, 2, key.peer_id(), &payload);
+        assert!(!verify(&envelope, &payload));
+        sign(&mut envelope, &key);
+        assert!(verify(&envelope, &payload));
+        assert!(!verify(&envelope, &[1, 2]));
+
+        let bytes = envelope.to_bytes(payload.clone());
+        let (mut new_envelope, new_payload) = Envelope::from_bytes(bytes).unwrap();
+        assert_eq!(new_payload, payload);
+        assert!(verify(&new_envelope, &new_payload));
+
+        new_envelope.fgid = 3;
+        assert!(!verify(&new_envelope, &new_payload));
+    }
+}
@@ -1,10 +1,13 @@
 //! Helper, When building the application, it is impossible to determine the data structure of the layers application, you can use this trait to constrain
 
+pub mod frame;
 pub mod group;
+pub mod membership;
 pub mod message;
 pub mod primitives;
 pub mod rpc;
 pub mod storage;
+pub mod unified;
 
 // 1. single
 // only one group, and only group's message. no layer.
@@ -20,5 +23,4 @@ pub mod storage;
 // 3. multiple
 // multiple groups, multiple layers. (some domain service).
 
-#[cfg(not(feature = "single"))]
 pub mod layer;
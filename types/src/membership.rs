@@ -0,0 +1,319 @@
+//! the members of the group, the peers join by the policy, and the member
+//! changes can be persisted to the `Storage`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::group::{Group, GroupId};
+use crate::message::{RecvType, SendType};
+use crate::primitives::{HandleResult, Peer, PeerId, Result};
+use crate::storage::{Batch, Storage};
+
+/// Helper: the policy of which peer can join the group.
+pub trait Policy {
+    /// guard if the peer can join or is valid, before check the join data.
+    fn guard(&self, peer: &PeerId) -> bool;
+
+    /// check the join data of the peer (e.g. invitation code), when it connect.
+    fn join(&mut self, peer: &PeerId, data: &[u8]) -> bool;
+}
+
+/// every peer can join the group.
+#[derive(Default)]
+pub struct Permissionless;
+
+impl Policy for Permissionless {
+    fn guard(&self, _peer: &PeerId) -> bool {
+        true
+    }
+
+    fn join(&mut self, _peer: &PeerId, _data: &[u8]) -> bool {
+        true
+    }
+}
+
+/// only the peers in the allowlist can join the group.
+#[derive(Default)]
+pub struct Allowlist(pub HashSet<PeerId>);
+
+impl Allowlist {
+    pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Self {
+        Allowlist(peers.into_iter().collect())
+    }
+
+    pub fn allow(&mut self, peer: PeerId) {
+        self.0.insert(peer);
+    }
+
+    pub fn disallow(&mut self, peer: &PeerId) {
+        self.0.remove(peer);
+    }
+}
+
+impl Policy for Allowlist {
+    fn guard(&self, peer: &PeerId) -> bool {
+        self.0.contains(peer)
+    }
+
+    fn join(&mut self, peer: &PeerId, _data: &[u8]) -> bool {
+        self.0.contains(peer)
+    }
+}
+
+/// the peer join with a invitation code in connect data,
+/// every code can be used only once.
+#[derive(Default)]
+pub struct Invitation(HashSet<Vec<u8>>);
+
+impl Invitation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// add a invitation code.
+    pub fn invite(&mut self, code: Vec<u8>) {
+        self.0.insert(code);
+    }
+
+    /// revoke the unused invitation code.
+    pub fn revoke(&mut self, code: &[u8]) {
+        self.0.remove(code);
+    }
+}
+
+impl Policy for Invitation {
+    fn guard(&self, _peer: &PeerId) -> bool {
+        true // check the code when join.
+    }
+
+    fn join(&mut self, _peer: &PeerId, data: &[u8]) -> bool {
+        self.0.remove(data)
+    }
+}
+
+/// the max members loaded from the storage in one page.
+const LOAD_PAGE: usize = 1024;
+
+/// the storage key prefix of group's members.
+fn members_prefix(gid: &GroupId) -> Vec<u8> {
+    format!("members:{}:", gid).into_bytes()
+}
+
+/// the member manager of a group, it answers the connect by the policy,
+/// and tracks the leave. the member changes are persisted when `persist`,
+/// they are merged per peer, so the changes are not more than the peers.
+pub struct Membership<P: Policy> {
+    gid: GroupId,
+    policy: P,
+    members: HashMap<PeerId, Peer>,
+    /// the peers changed since last persist.
+    changes: HashSet<PeerId>,
+}
+
+impl<P: Policy> Membership<P> {
+    pub fn new(gid: GroupId, policy: P) -> Self {
+        Membership {
+            gid,
+            policy,
+            members: HashMap::new(),
+            changes: HashSet::new(),
+        }
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    pub fn is_member(&self, peer: &PeerId) -> bool {
+        self.members.contains_key(peer)
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Peer> {
+        self.members.values()
+    }
+
+    /// add the peer to members, without check the policy.
+    pub fn add(&mut self, peer: Peer) {
+        self.changes.insert(peer.id);
+        self.members.insert(peer.id, peer);
+    }
+
+    /// remove the peer from members.
+    pub fn remove(&mut self, peer: &PeerId) -> Option<Peer> {
+        let peer = self.members.remove(peer)?;
+        self.changes.insert(peer.id);
+        Some(peer)
+    }
+
+    /// load the persisted members of the group.
+    pub async fn load<S: Storage<Key = Vec<u8>>>(&mut self, storage: &S) -> Result<()> {
+        let prefix = members_prefix(&self.gid);
+        let mut start = None;
+        loop {
+            let page = storage
+                .scan_prefix::<String>(&prefix, start.as_ref(), LOAD_PAGE)
+                .await?;
+            for (_, s) in page.items {
+                let peer = Peer::from_string(&s)?;
+                self.members.insert(peer.id, peer);
+            }
+            if page.next.is_none() {
+                return Ok(());
+            }
+            start = page.next;
+        }
+    }
+
+    /// persist the member changes since last time, all done or nothing.
+    pub async fn persist<S: Storage<Key = Vec<u8>>>(&mut self, storage: &S) -> Result<()> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        let prefix = members_prefix(&self.gid);
+        let key = |peer: &PeerId| [prefix.as_slice(), peer.to_hex().as_bytes()].concat();
+        let mut batch = Batch::new();
+        for peer in &self.changes {
+            match self.members.get(peer) {
+                Some(p) => batch.write(key(peer), &p.to_string())?,
+                None => batch.delete(key(peer)),
+            }
+        }
+        storage.batch(batch).await?;
+        self.changes.clear();
+        Ok(())
+    }
+
+    /// answer the connect of the peer. the member reconnects without join
+    /// data again, and the member which the policy revoked is removed.
+    fn join(&mut self, peer: Peer, data: &[u8]) -> HandleResult {
+        let is_ok = self.policy.guard(&peer.id)
+            && (self.is_member(&peer.id) || self.policy.join(&peer.id, data));
+        if is_ok {
+            self.add(peer.clone());
+        } else {
+            self.remove(&peer.id);
+        }
+        self.reply(SendType::Result(0, peer, is_ok, false, vec![]))
+    }
+
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+    fn reply(&self, msg: SendType) -> HandleResult {
+        HandleResult::group(msg)
+    }
+
+    #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+    fn reply(&self, msg: SendType) -> HandleResult {
+        HandleResult::group(self.gid, msg)
+    }
+}
+
+impl<P: Policy> Group for Membership<P> {
+    fn id(&self) -> &GroupId {
+        &self.gid
+    }
+
+    /// the peers which the policy guard, include the members, so the
+    /// revoked members (e.g. `Allowlist::disallow`) are rejected.
+    fn guard(&self, addr: &PeerId) -> bool {
+        self.policy.guard(addr)
+    }
+
+    /// handle the membership messages (connect, result, leave), others are
+    /// ignored, check `is_member` before handle the events.
+    fn handle(&mut self, msg: RecvType) -> Result<HandleResult> {
+        let result = match msg {
+            RecvType::Connect(peer, data) | RecvType::ResultConnect(peer, data) => {
+                self.join(peer, &data)
+            }
+            RecvType::Result(peer, is_ok, _) => {
+                if is_ok {
+                    self.add(peer);
+                }
+                HandleResult::new()
+            }
+            RecvType::Leave(peer) => {
+                self.remove(&peer.id);
+                HandleResult::new()
+            }
+            _ => HandleResult::new(),
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peer(b: u8) -> Peer {
+        let hex = format!("{:02x}", b).repeat(20);
+        Peer::peer(PeerId::from_hex(hex).unwrap())
+    }
+
+    fn is_ok(result: &HandleResult) -> bool {
+        #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+        let msg = &result.groups[0];
+        #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+        let msg = &result.groups[0].1;
+        matches!(msg, SendType::Result(_, _, true, _, _))
+    }
+
+    #[test]
+    fn test_membership() {
+        let mut group = Membership::new(1, Permissionless);
+        let result = group.handle(RecvType::Connect(peer(1), vec![])).unwrap();
+        assert!(is_ok(&result) && group.is_member(&peer(1).id));
+        group.handle(RecvType::Leave(peer(1))).unwrap();
+        assert!(!group.is_member(&peer(1).id));
+        assert_eq!(group.changes.len(), 1);
+
+        let mut group = Membership::new(1, Allowlist::new([peer(1).id]));
+        assert!(group.guard(&peer(1).id) && !group.guard(&peer(2).id));
+        let result = group.handle(RecvType::Connect(peer(2), vec![])).unwrap();
+        assert!(!is_ok(&result) && !group.is_member(&peer(2).id));
+        group.policy_mut().allow(peer(2).id);
+        let result = group.handle(RecvType::Connect(peer(2), vec![])).unwrap();
+        assert!(is_ok(&result) && group.is_member(&peer(2).id));
+
+        let mut group = Membership::new(1, Invitation::new());
+        group.policy_mut().invite(b"code".to_vec());
+        let result = group.handle(RecvType::Connect(peer(1), vec![])).unwrap();
+        assert!(!is_ok(&result));
+        let result = group
+            .handle(RecvType::Connect(peer(1), b"code".to_vec()))
+            .unwrap();
+        assert!(is_ok(&result) && group.is_member(&peer(1).id));
+        let result = group
+            .handle(RecvType::Connect(peer(2), b"code".to_vec()))
+            .unwrap();
+        assert!(!is_ok(&result));
+    }
+
+    #[test]
+    fn test_membership_revoke_and_reconnect() {
+        let mut group = Membership::new(1, Allowlist::new([peer(1).id]));
+        let result = group.handle(RecvType::Connect(peer(1), vec![])).unwrap();
+        assert!(is_ok(&result) && group.is_member(&peer(1).id));
+        group.policy_mut().disallow(&peer(1).id);
+        assert!(!group.guard(&peer(1).id));
+        let result = group.handle(RecvType::Connect(peer(1), vec![])).unwrap();
+        assert!(!is_ok(&result) && !group.is_member(&peer(1).id));
+
+        // the member reconnects without the used invitation code.
+        let mut group = Membership::new(1, Invitation::new());
+        group.policy_mut().invite(b"code".to_vec());
+        let result = group
+            .handle(RecvType::Connect(peer(1), b"code".to_vec()))
+            .unwrap();
+        assert!(is_ok(&result));
+        let result = group.handle(RecvType::Connect(peer(1), vec![])).unwrap();
+        assert!(is_ok(&result) && group.is_member(&peer(1).id));
+        group.handle(RecvType::Leave(peer(1))).unwrap();
+        let result = group.handle(RecvType::Connect(peer(1), vec![])).unwrap();
+        assert!(!is_ok(&result));
+    }
+}
@@ -4,7 +4,6 @@ use crate::primitives::{Broadcast, DeliveryType, Peer, PeerId, StreamType};
 use crate::rpc::RpcParam;
 pub use chamomile_types::message::{StateRequest, StateResponse};
 
-#[cfg(not(feature = "single"))]
 use crate::group::GroupId;
 
 /// channel message send to TDN Group.
@@ -81,15 +80,13 @@ pub enum NetworkType {
     /// Stop & close p2p network.
     NetworkStop,
     /// add group to TDN control. multiple group use.
-    #[cfg(any(feature = "multiple", feature = "full"))]
     AddGroup(GroupId),
     /// remove group from TDN control. multiple group use.
-    #[cfg(any(feature = "multiple", feature = "full"))]
     DelGroup(GroupId),
 }
 
 /// channel message send to TDN for std version.
-#[cfg(feature = "std")]
+#[cfg(tdn_mode = "std")]
 #[derive(Debug)]
 pub enum SendMessage {
     /// P2P network with same PeerId.
@@ -100,12 +97,14 @@ pub enum SendMessage {
     Layer(GroupId, SendType),
     /// RPC: connection uid, request params, is websocket.
     Rpc(u64, RpcParam, bool),
+    /// RPC: publish params to the WS connections subscribed the topic.
+    Publish(String, RpcParam),
     /// Network: Control the Network state.
     Network(NetworkType),
 }
 
 /// channel message receive from TDN for std version.
-#[cfg(feature = "std")]
+#[cfg(tdn_mode = "std")]
 #[derive(Debug)]
 pub enum ReceiveMessage {
     /// P2P network with same PeerId.
@@ -121,7 +120,7 @@ pub enum ReceiveMessage {
 }
 
 /// channel message send to TDN for single version.
-#[cfg(feature = "single")]
+#[cfg(tdn_mode = "single")]
 #[derive(Debug)]
 pub enum SendMessage {
     /// P2P network with same PeerId.
@@ -130,12 +129,14 @@ pub enum SendMessage {
     Group(SendType),
     /// RPC: connection uid, request params, is websocket.
     Rpc(u64, RpcParam, bool),
+    /// RPC: publish params to the WS connections subscribed the topic.
+    Publish(String, RpcParam),
     /// Network: Control the Network state.
     Network(NetworkType),
 }
 
 /// channel message receive from TDN for single version.
-#[cfg(feature = "single")]
+#[cfg(tdn_mode = "single")]
 #[derive(Debug)]
 pub enum ReceiveMessage {
     /// P2P network with same PeerId.
@@ -149,7 +150,7 @@ pub enum ReceiveMessage {
 }
 
 /// channel message send to TDN for multiple version.
-#[cfg(feature = "multiple")]
+#[cfg(tdn_mode = "multiple")]
 #[derive(Debug)]
 pub enum SendMessage {
     /// P2P network with same PeerId.
@@ -158,12 +159,14 @@ pub enum SendMessage {
     Group(GroupId, SendType),
     /// RPC: connection uid, request params, is websocket.
     Rpc(u64, RpcParam, bool),
+    /// RPC: publish params to the WS connections subscribed the topic.
+    Publish(String, RpcParam),
     /// Network: Control the Network state.
     Network(NetworkType),
 }
 
 /// channel message receive from TDN for multiple version.
-#[cfg(feature = "multiple")]
+#[cfg(tdn_mode = "multiple")]
 #[derive(Debug)]
 pub enum ReceiveMessage {
     /// P2P network with same PeerId.
@@ -177,7 +180,7 @@ pub enum ReceiveMessage {
 }
 
 /// channel message send to TDN for full version.
-#[cfg(feature = "full")]
+#[cfg(tdn_mode = "full")]
 #[derive(Debug)]
 pub enum SendMessage {
     /// P2P network with same PeerId.
@@ -189,12 +192,14 @@ pub enum SendMessage {
     Layer(GroupId, GroupId, SendType),
     /// RPC: connection uid, request params, is websocket.
     Rpc(u64, RpcParam, bool),
+    /// RPC: publish params to the WS connections subscribed the topic.
+    Publish(String, RpcParam),
     /// Network: Control the Network state.
     Network(NetworkType),
 }
 
 /// channel message receive from TDN for full version.
-#[cfg(feature = "full")]
+#[cfg(tdn_mode = "full")]
 #[derive(Debug)]
 pub enum ReceiveMessage {
     /// P2P network with same PeerId.
@@ -212,4 +217,9 @@ pub enum ReceiveMessage {
 
 /// packaging the rpc message. not open to ouside.
 #[derive(Debug)]
-pub struct RpcSendMessage(pub u64, pub RpcParam, pub bool);
+pub enum RpcSendMessage {
+    /// connection uid, response params, is websocket.
+    Rpc(u64, RpcParam, bool),
+    /// topic, params. push to the connections subscribed the topic.
+    Publish(String, RpcParam),
+}
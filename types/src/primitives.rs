@@ -4,7 +4,10 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 /// Type: PeerId, PeerKey
 pub use chamomile_types::{
-    key::{secp256k1, Key as PeerKey, PublicKey as PeerPublicKey, SecretKey as PeerSecretKey},
+    key::{
+        secp256k1, Key as PeerKey, PublicKey as PeerPublicKey, SecretKey as PeerSecretKey,
+        Signature as PeerSignature,
+    },
     types::{PeerId, TransportType, PEER_ID_LENGTH},
 };
 
@@ -29,6 +32,9 @@ pub const RPC_HTTP: &str = "127.0.0.1:7365";
 /// Configure file name
 pub const CONFIG_FILE_NAME: &str = "config.toml";
 
+/// Node's PeerKey file name, in the p2p db directory.
+pub const PEER_KEY_FILE_NAME: &str = "peer.key";
+
 pub const DEFAULT_SECRET: [u8; 32] = [0u8; 32];
 
 pub const DEFAULT_STORAGE_DIR_NAME: &str = ".tdn";
@@ -208,7 +214,7 @@ impl Into<DeliveryType> for P2pDeliveryType {
     }
 }
 
-#[cfg(not(feature = "single"))]
+#[cfg(not(tdn_mode = "single"))]
 use crate::group::GroupId;
 use crate::message::{NetworkType, SendType};
 use crate::rpc::RpcParam;
@@ -220,16 +226,16 @@ pub struct HandleResult {
     /// rpc tasks: [(method, params)].
     pub rpcs: Vec<RpcParam>,
     /// group tasks: [GroupSendMessage]
-    #[cfg(any(feature = "single", feature = "std"))]
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
     pub groups: Vec<SendType>,
     /// group tasks: [GroupSendMessage]
-    #[cfg(any(feature = "full", feature = "multiple"))]
+    #[cfg(any(tdn_mode = "full", tdn_mode = "multiple"))]
     pub groups: Vec<(GroupId, SendType)>,
     /// layer tasks: [LayerSendMessage]
-    #[cfg(feature = "std")]
+    #[cfg(tdn_mode = "std")]
     pub layers: Vec<(GroupId, SendType)>,
     /// layer tasks: [LayerSendMessage]
-    #[cfg(feature = "full")]
+    #[cfg(tdn_mode = "full")]
     pub layers: Vec<(GroupId, GroupId, SendType)>,
     /// network tasks: [NetworkType]
     pub networks: Vec<NetworkType>,
@@ -241,13 +247,13 @@ impl<'a> HandleResult {
             owns: vec![],
             rpcs: vec![],
             #[cfg(any(
-                feature = "single",
-                feature = "std",
-                feature = "multiple",
-                feature = "full",
+                tdn_mode = "single",
+                tdn_mode = "std",
+                tdn_mode = "multiple",
+                tdn_mode = "full",
             ))]
             groups: vec![],
-            #[cfg(any(feature = "full", feature = "std"))]
+            #[cfg(any(tdn_mode = "full", tdn_mode = "std"))]
             layers: vec![],
             networks: vec![],
         }
@@ -258,13 +264,13 @@ impl<'a> HandleResult {
             owns: vec![m],
             rpcs: vec![],
             #[cfg(any(
-                feature = "single",
-                feature = "std",
-                feature = "multiple",
-                feature = "full",
+                tdn_mode = "single",
+                tdn_mode = "std",
+                tdn_mode = "multiple",
+                tdn_mode = "full",
             ))]
             groups: vec![],
-            #[cfg(any(feature = "full", feature = "std"))]
+            #[cfg(any(tdn_mode = "full", tdn_mode = "std"))]
             layers: vec![],
             networks: vec![],
         }
@@ -275,43 +281,43 @@ impl<'a> HandleResult {
             owns: vec![],
             rpcs: vec![p],
             #[cfg(any(
-                feature = "single",
-                feature = "std",
-                feature = "multiple",
-                feature = "full",
+                tdn_mode = "single",
+                tdn_mode = "std",
+                tdn_mode = "multiple",
+                tdn_mode = "full",
             ))]
             groups: vec![],
-            #[cfg(any(feature = "full", feature = "std"))]
+            #[cfg(any(tdn_mode = "full", tdn_mode = "std"))]
             layers: vec![],
             networks: vec![],
         }
     }
 
-    #[cfg(any(feature = "single", feature = "std"))]
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
     pub fn group(m: SendType) -> Self {
         HandleResult {
             owns: vec![],
             rpcs: vec![],
             groups: vec![m],
-            #[cfg(feature = "std")]
+            #[cfg(tdn_mode = "std")]
             layers: vec![],
             networks: vec![],
         }
     }
 
-    #[cfg(any(feature = "multiple", feature = "full"))]
+    #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
     pub fn group(gid: GroupId, m: SendType) -> Self {
         HandleResult {
             owns: vec![],
             rpcs: vec![],
             groups: vec![(gid, m)],
-            #[cfg(feature = "full")]
+            #[cfg(tdn_mode = "full")]
             layers: vec![],
             networks: vec![],
         }
     }
 
-    #[cfg(feature = "std")]
+    #[cfg(tdn_mode = "std")]
     pub fn layer(gid: GroupId, m: SendType) -> Self {
         HandleResult {
             owns: vec![],
@@ -322,7 +328,7 @@ impl<'a> HandleResult {
         }
     }
 
-    #[cfg(feature = "full")]
+    #[cfg(tdn_mode = "full")]
     pub fn layer(fgid: GroupId, tgid: GroupId, m: SendType) -> Self {
         HandleResult {
             owns: vec![],
@@ -333,18 +339,34 @@ impl<'a> HandleResult {
         }
     }
 
+    /// append all the tasks of other result.
+    pub fn merge(&mut self, mut other: HandleResult) {
+        self.owns.append(&mut other.owns);
+        self.rpcs.append(&mut other.rpcs);
+        #[cfg(any(
+            tdn_mode = "single",
+            tdn_mode = "std",
+            tdn_mode = "multiple",
+            tdn_mode = "full",
+        ))]
+        self.groups.append(&mut other.groups);
+        #[cfg(any(tdn_mode = "full", tdn_mode = "std"))]
+        self.layers.append(&mut other.layers);
+        self.networks.append(&mut other.networks);
+    }
+
     pub fn network(m: NetworkType) -> Self {
         HandleResult {
             owns: vec![],
             rpcs: vec![],
             #[cfg(any(
-                feature = "single",
-                feature = "std",
-                feature = "multiple",
-                feature = "full",
+                tdn_mode = "single",
+                tdn_mode = "std",
+                tdn_mode = "multiple",
+                tdn_mode = "full",
             ))]
             groups: vec![],
-            #[cfg(any(feature = "full", feature = "std"))]
+            #[cfg(any(tdn_mode = "full", tdn_mode = "std"))]
             layers: vec![],
             networks: vec![m],
         }
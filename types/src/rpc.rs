@@ -1,3 +1,4 @@
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::future::Future;
@@ -7,15 +8,25 @@ use std::sync::Arc;
 pub use serde_json::json;
 pub type RpcParam = Value;
 
+use crate::group::GroupId;
 use crate::primitives::{HandleResult, Result};
+use crate::unified;
 
 #[derive(Debug, Clone)]
 pub enum RpcError {
     ParseError,
     InvalidRequest,
+    /// invalid request with the detail, e.g. the params can not be decoded.
+    InvalidParams(String),
     InvalidVersion,
     InvalidResponse,
     MethodNotFound(String),
+    /// the session has no scope to call the method.
+    Unauthorized(String),
+    /// the IP or method exceeds the rate limit.
+    LimitExceeded(String),
+    /// the server's queue is full, try again later.
+    Busy,
     Custom(String),
 }
 
@@ -44,7 +55,9 @@ impl From<anyhow::Error> for RpcError {
 }
 
 impl RpcError {
-    pub fn json(&self, id: u64) -> RpcParam {
+    /// the error response, id is the request's id (number, string or null).
+    pub fn json(&self, id: impl Into<RpcParam>) -> RpcParam {
+        let id = id.into();
         match self {
             RpcError::ParseError => json!({
                 "jsonrpc": "2.0",
@@ -61,6 +74,30 @@ impl RpcError {
                     "message": format!("Method {} not found", method)
                 }
             }),
+            RpcError::Unauthorized(method) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32001,
+                    "message": format!("Method {} unauthorized", method)
+                }
+            }),
+            RpcError::LimitExceeded(limited) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32005,
+                    "message": format!("Rate limit exceeded: {}", limited)
+                }
+            }),
+            RpcError::Busy => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32003,
+                    "message": "Server is busy"
+                }
+            }),
             RpcError::InvalidRequest => json!({
                 "jsonrpc": "2.0",
                 "id": id,
@@ -69,6 +106,14 @@ impl RpcError {
                     "message": "Invalid Request"
                 }
             }),
+            RpcError::InvalidParams(detail) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": -32600,
+                    "message": format!("Invalid Request: {}", detail)
+                }
+            }),
             RpcError::InvalidVersion => json!({
                 "jsonrpc": "2.0",
                 "id": id,
@@ -97,52 +142,128 @@ impl RpcError {
     }
 }
 
-pub fn parse_jsonrpc(json_string: String) -> std::result::Result<RpcParam, (RpcError, u64)> {
-    match serde_json::from_str::<RpcParam>(&json_string) {
-        Ok(mut value) => {
-            let id_res = value
-                .get("id")
-                .map(|id| {
-                    id.as_u64()
-                        .or(id.as_str().map(|sid| sid.parse::<u64>().ok()).flatten())
-                })
-                .flatten();
+/// check the request is valid JSON-RPC 2.0 request, and fill default params.
+/// if invalid, return the error and the request id.
+fn check_request(value: &mut RpcParam) -> std::result::Result<(), (RpcError, RpcParam)> {
+    if !value.is_object() {
+        return Err((RpcError::InvalidRequest, RpcParam::Null));
+    }
 
-            if id_res.is_none() {
-                return Err((RpcError::ParseError, 0));
-            }
-            let id = id_res.unwrap();
-            *value.get_mut("id").unwrap() = id.into();
+    let id = value.get("id").cloned().unwrap_or(RpcParam::Null);
+    if !(id.is_null() || id.is_number() || id.is_string()) {
+        return Err((RpcError::InvalidRequest, RpcParam::Null));
+    }
 
-            // check if json is response
-            if value.get("result").is_some() || value.get("error").is_some() {
-                return Err((RpcError::InvalidResponse, id));
-            }
+    // check if json is response
+    if value.get("result").is_some() || value.get("error").is_some() {
+        return Err((RpcError::InvalidResponse, id));
+    }
 
-            if value.get("method").is_none() || value.get("method").unwrap().as_str().is_none() {
-                return Err((RpcError::InvalidRequest, id));
-            }
+    if value.get("method").is_none() || value.get("method").unwrap().as_str().is_none() {
+        return Err((RpcError::InvalidRequest, id));
+    }
 
-            if value.get("params").is_none() {
-                value["params"] = RpcParam::Array(vec![]);
-            }
+    if value.get("params").is_none() {
+        value["params"] = RpcParam::Array(vec![]);
+    }
 
-            let jsonrpc = value
-                .get("jsonrpc")
-                .map(|v| {
-                    v.as_str()
-                        .map(|s| if s == "2.0" { Some(2) } else { None })
-                        .flatten()
-                })
-                .flatten();
+    let jsonrpc = value
+        .get("jsonrpc")
+        .map(|v| {
+            v.as_str()
+                .map(|s| if s == "2.0" { Some(2) } else { None })
+                .flatten()
+        })
+        .flatten();
+
+    if jsonrpc.is_none() {
+        return Err((RpcError::InvalidVersion, id));
+    }
+
+    Ok(())
+}
 
-            if jsonrpc.is_none() {
-                return Err((RpcError::InvalidVersion, id));
+/// parse the JSON-RPC 2.0 request or batch requests.
+/// the invalid requests in batch will keep, and replied errors by `RpcHandler`.
+pub fn parse_jsonrpc(json_string: String) -> std::result::Result<RpcParam, (RpcError, RpcParam)> {
+    match serde_json::from_str::<RpcParam>(&json_string) {
+        Ok(RpcParam::Array(mut values)) => {
+            if values.is_empty() {
+                return Err((RpcError::InvalidRequest, RpcParam::Null));
+            }
+            for value in values.iter_mut() {
+                let _ = check_request(value);
             }
+            Ok(RpcParam::Array(values))
+        }
+        Ok(mut value) => check_request(&mut value).map(|_| value),
+        Err(_e) => Err((RpcError::ParseError, RpcParam::Null)),
+    }
+}
 
-            Ok(value)
+/// check if the request is notification (without id), or batch of notifications.
+/// notifications will not be replied.
+pub fn is_notification(param: &RpcParam) -> bool {
+    match param {
+        RpcParam::Array(params) => !params.is_empty() && params.iter().all(is_notification),
+        RpcParam::Object(map) => {
+            !map.contains_key("id") && map.get("method").map(|m| m.is_string()).unwrap_or(false)
         }
-        Err(_e) => Err((RpcError::ParseError, 0)),
+        _ => false,
+    }
+}
+
+/// the OpenRPC version of `rpc.discover` document.
+pub const OPENRPC_VERSION: &str = "1.2.6";
+
+/// the metadata of method, used in the `rpc.discover` OpenRPC document.
+/// the schemas are JSON Schema.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMethodMeta {
+    pub description: Option<String>,
+    /// the positional params, (name, schema).
+    pub params: Vec<(String, RpcParam)>,
+    pub result: Option<RpcParam>,
+}
+
+impl RpcMethodMeta {
+    pub fn new(description: &str) -> Self {
+        Self {
+            description: Some(description.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    /// add the next positional param.
+    pub fn param(mut self, name: &str, schema: RpcParam) -> Self {
+        self.params.push((name.to_owned(), schema));
+        self
+    }
+
+    pub fn result(mut self, schema: RpcParam) -> Self {
+        self.result = Some(schema);
+        self
+    }
+
+    /// the OpenRPC method object.
+    fn openrpc(&self, name: &str) -> RpcParam {
+        let params: Vec<RpcParam> = self
+            .params
+            .iter()
+            .map(|(name, schema)| json!({ "name": name, "required": true, "schema": schema }))
+            .collect();
+        let mut method = json!({
+            "name": name,
+            "params": params,
+            "result": {
+                "name": "result",
+                "schema": self.result.clone().unwrap_or(json!({})),
+            }
+        });
+        if let Some(description) = &self.description {
+            method["description"] = json!(description);
+        }
+        method
     }
 }
 
@@ -184,29 +305,29 @@ pub fn parse_jsonrpc(json_string: String) -> std::result::Result<RpcParam, (RpcE
 /// ````
 pub struct RpcHandler<S: Send + Sync> {
     state: Arc<S>,
-    fns: HashMap<&'static str, Box<DynFutFn<S>>>,
-    //fns: HashMap<&'static str, BoxFuture<'static, RpcResult<'static>>>,
+    fns: HashMap<&'static str, RpcMethod<S>>,
+    scopes: HashMap<&'static str, Vec<&'static str>>,
+    metas: HashMap<&'static str, RpcMethodMeta>,
+    /// the OpenRPC info, (title, version).
+    info: (String, String),
 }
 
 type RpcResult = std::result::Result<HandleResult, RpcError>;
+type UnifiedRpcResult = std::result::Result<unified::HandleResult, RpcError>;
 type BoxFuture<RpcResult> = Pin<Box<dyn Future<Output = RpcResult> + Send>>;
 
+/// the method of the mode features, `UnifiedFutFn` is the same in all modes.
 pub trait FutFn<S>: Send + Sync + 'static {
-    #[cfg(any(feature = "single", feature = "std"))]
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
     fn call(&self, params: Vec<RpcParam>, s: Arc<S>) -> BoxFuture<RpcResult>;
 
-    #[cfg(any(feature = "multiple", feature = "full"))]
-    fn call(
-        &self,
-        gid: crate::group::GroupId,
-        params: Vec<RpcParam>,
-        s: Arc<S>,
-    ) -> BoxFuture<RpcResult>;
+    #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+    fn call(&self, gid: GroupId, params: Vec<RpcParam>, s: Arc<S>) -> BoxFuture<RpcResult>;
 }
 
 pub(crate) type DynFutFn<S> = dyn FutFn<S>;
 
-#[cfg(any(feature = "single", feature = "std"))]
+#[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
 impl<S, F: Send + Sync + 'static, Fut> FutFn<S> for F
 where
     F: Fn(Vec<RpcParam>, Arc<S>) -> Fut,
@@ -218,28 +339,65 @@ where
     }
 }
 
-#[cfg(any(feature = "multiple", feature = "full"))]
+#[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
 impl<S, F: Send + Sync + 'static, Fut> FutFn<S> for F
 where
-    F: Fn(crate::group::GroupId, Vec<RpcParam>, Arc<S>) -> Fut,
+    F: Fn(GroupId, Vec<RpcParam>, Arc<S>) -> Fut,
     Fut: Future<Output = RpcResult> + Send + 'static,
 {
-    fn call(
-        &self,
-        gid: crate::group::GroupId,
-        params: Vec<RpcParam>,
-        s: Arc<S>,
-    ) -> BoxFuture<RpcResult> {
+    fn call(&self, gid: GroupId, params: Vec<RpcParam>, s: Arc<S>) -> BoxFuture<RpcResult> {
         let fut = (self)(gid, params, s);
         Box::pin(async move { fut.await })
     }
 }
 
+/// the method of all modes, always has the request's group,
+/// and returns the unified result.
+pub trait UnifiedFutFn<S>: Send + Sync + 'static {
+    fn call(&self, gid: GroupId, params: Vec<RpcParam>, s: Arc<S>) -> BoxFuture<UnifiedRpcResult>;
+}
+
+impl<S, F: Send + Sync + 'static, Fut> UnifiedFutFn<S> for F
+where
+    F: Fn(GroupId, Vec<RpcParam>, Arc<S>) -> Fut,
+    Fut: Future<Output = UnifiedRpcResult> + Send + 'static,
+{
+    fn call(&self, gid: GroupId, params: Vec<RpcParam>, s: Arc<S>) -> BoxFuture<UnifiedRpcResult> {
+        Box::pin((self)(gid, params, s))
+    }
+}
+
+/// the added method, the legacy is converted to unified when call.
+enum RpcMethod<S> {
+    Legacy(Box<DynFutFn<S>>),
+    Unified(Box<dyn UnifiedFutFn<S>>),
+}
+
+impl<S: 'static> RpcMethod<S> {
+    async fn call(&self, gid: GroupId, params: Vec<RpcParam>, s: Arc<S>) -> UnifiedRpcResult {
+        match self {
+            RpcMethod::Legacy(f) => {
+                #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+                let res = f.call(params, s).await;
+
+                #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+                let res = f.call(gid, params, s).await;
+
+                res.map(|r| unified::HandleResult::from_legacy(r, gid))
+            }
+            RpcMethod::Unified(f) => f.call(gid, params, s).await,
+        }
+    }
+}
+
 impl<S: 'static + Send + Sync> RpcHandler<S> {
     pub fn new(state: S) -> RpcHandler<S> {
         Self {
             state: Arc::new(state),
             fns: HashMap::new(),
+            scopes: HashMap::new(),
+            metas: HashMap::new(),
+            info: ("TDN".to_owned(), env!("CARGO_PKG_VERSION").to_owned()),
         }
     }
 
@@ -247,132 +405,288 @@ impl<S: 'static + Send + Sync> RpcHandler<S> {
         Self {
             state: state,
             fns: HashMap::new(),
+            scopes: HashMap::new(),
+            metas: HashMap::new(),
+            info: ("TDN".to_owned(), env!("CARGO_PKG_VERSION").to_owned()),
         }
     }
 
     pub fn add_method(&mut self, name: &'static str, f: impl FutFn<S>) {
-        self.fns.insert(name, Box::new(f));
+        self.fns.insert(name, RpcMethod::Legacy(Box::new(f)));
     }
 
-    pub async fn handle(&self, mut param: RpcParam) -> Result<HandleResult> {
-        let id = param["id"].take().as_u64().unwrap();
-        let method_s = param["method"].take();
-        let method = method_s.as_str().unwrap();
-        let mut new_results = HandleResult::new();
+    /// add the method of all modes, it has the group of request (or the
+    /// default group of `handle_unified`), and returns the unified result.
+    pub fn add_unified_method(&mut self, name: &'static str, f: impl UnifiedFutFn<S>) {
+        self.fns.insert(name, RpcMethod::Unified(Box::new(f)));
+    }
 
-        #[cfg(any(feature = "multiple", feature = "full"))]
-        let group = if let Some(group_id) = param.get("gid").and_then(|gid_v| gid_v.as_u64()) {
-            group_id as crate::group::GroupId
-        } else {
-            new_results.rpcs.push(RpcError::InvalidRequest.json(id));
-            return Ok(new_results);
+    /// add the method with typed params and result. the params (positional
+    /// array or named object) are decoded to `P`, and the result is encoded.
+    #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+    pub fn add_typed_method<P, R, F, Fut>(&mut self, name: &'static str, f: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(P, Arc<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<R, RpcError>> + Send + 'static,
+    {
+        self.add_unified_typed_method(name, move |_gid: GroupId, p: P, s: Arc<S>| f(p, s));
+    }
+
+    /// add the method with typed params and result. the params (positional
+    /// array or named object) are decoded to `P`, and the result is encoded.
+    #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+    pub fn add_typed_method<P, R, F, Fut>(&mut self, name: &'static str, f: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(GroupId, P, Arc<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<R, RpcError>> + Send + 'static,
+    {
+        self.add_unified_typed_method(name, f);
+    }
+
+    /// add the typed method of all modes, it has the group of request.
+    pub fn add_unified_typed_method<P, R, F, Fut>(&mut self, name: &'static str, f: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(GroupId, P, Arc<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<R, RpcError>> + Send + 'static,
+    {
+        self.add_unified_method(
+            name,
+            move |gid: GroupId, params: Vec<RpcParam>, s: Arc<S>| {
+                let fut = decode_params(params).map(|p| f(gid, p, s));
+                async move { Ok(unified::HandleResult::rpc(encode_result(fut?.await?)?)) }
+            },
+        );
+    }
+
+    /// set the metadata of the method, it will be in `rpc.discover`.
+    pub fn set_method_meta(&mut self, name: &'static str, meta: RpcMethodMeta) {
+        self.metas.insert(name, meta);
+    }
+
+    /// set the title and version of the `rpc.discover` document.
+    pub fn set_info(&mut self, title: &str, version: &str) {
+        self.info = (title.to_owned(), version.to_owned());
+    }
+
+    /// the OpenRPC document of all methods.
+    pub fn openrpc(&self) -> RpcParam {
+        let mut names: Vec<&str> = self.fns.keys().copied().collect();
+        names.sort();
+        let default = RpcMethodMeta::default();
+        let methods: Vec<RpcParam> = names
+            .iter()
+            .map(|name| self.metas.get(name).unwrap_or(&default).openrpc(name))
+            .collect();
+
+        json!({
+            "openrpc": OPENRPC_VERSION,
+            "info": {
+                "title": self.info.0,
+                "version": self.info.1,
+            },
+            "methods": methods,
+        })
+    }
+
+    /// add the method which need one of the scopes to call.
+    /// the session's scopes are injected by rpc transports in request's `session`.
+    pub fn add_method_with_scopes(
+        &mut self,
+        name: &'static str,
+        scopes: &[&'static str],
+        f: impl FutFn<S>,
+    ) {
+        self.add_method(name, f);
+        self.scopes.insert(name, scopes.to_vec());
+    }
+
+    /// add the method of all modes which need one of the scopes to call.
+    pub fn add_unified_method_with_scopes(
+        &mut self,
+        name: &'static str,
+        scopes: &[&'static str],
+        f: impl UnifiedFutFn<S>,
+    ) {
+        self.add_unified_method(name, f);
+        self.scopes.insert(name, scopes.to_vec());
+    }
+
+    /// check the request's session can call the method.
+    /// no session means the request is from inner, not from rpc transports.
+    fn is_authorized(&self, method: &str, param: &RpcParam) -> bool {
+        let scopes = match self.scopes.get(method) {
+            Some(scopes) => scopes,
+            None => return true,
         };
 
-        if method == "rpcs" {
-            let mut methods: Vec<&str> = self.fns.keys().map(|v| *v).collect();
-            methods.sort();
-            let params = json!(methods);
+        match param.get("session") {
+            Some(session) => session["scopes"]
+                .as_array()
+                .map(|ss| {
+                    ss.iter()
+                        .filter_map(|s| s.as_str())
+                        .any(|s| s == "*" || scopes.contains(&s))
+                })
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// handle the request or batch requests, the result is the legacy of
+    /// the mode features. multiple and full need the `gid` in request.
+    pub async fn handle(&self, param: RpcParam) -> Result<HandleResult> {
+        #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+        let gid = Some(GroupId::default());
+
+        #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+        let gid = None;
+
+        Ok(self.handle_batch(param, gid).await?.into_legacy())
+    }
 
-            #[cfg(any(feature = "single", feature = "std"))]
-            new_results.rpcs.push(rpc_response(id, method, params));
+    /// handle the request or batch requests in all modes, `gid` is the group
+    /// when the request has no `gid`. the responses of batch will be in one
+    /// array, and notifications will not have responses.
+    pub async fn handle_unified(
+        &self,
+        param: RpcParam,
+        gid: GroupId,
+    ) -> Result<unified::HandleResult> {
+        self.handle_batch(param, Some(gid)).await
+    }
 
-            #[cfg(any(feature = "multiple", feature = "full"))]
+    async fn handle_batch(
+        &self,
+        param: RpcParam,
+        gid: Option<GroupId>,
+    ) -> Result<unified::HandleResult> {
+        let params = match param {
+            RpcParam::Array(params) => params,
+            mut param => {
+                return match check_request(&mut param) {
+                    Ok(()) => self.handle_request(param, gid).await,
+                    Err((err, id)) => Ok(unified::HandleResult::rpc(err.json(id))),
+                };
+            }
+        };
+
+        let mut new_results = unified::HandleResult::new();
+        if params.is_empty() {
             new_results
                 .rpcs
-                .push(rpc_response(id, method, params, group));
+                .push(RpcError::InvalidRequest.json(RpcParam::Null));
+            return Ok(new_results);
+        }
+
+        let mut responses = vec![];
+        for mut param in params {
+            if let Err((err, id)) = check_request(&mut param) {
+                responses.push(err.json(id));
+                continue;
+            }
+            let mut results = self.handle_request(param, gid).await?;
+            responses.append(&mut results.rpcs);
+            new_results.merge(results);
+        }
+        if !responses.is_empty() {
+            new_results.rpcs.push(RpcParam::Array(responses));
+        }
+
+        Ok(new_results)
+    }
+
+    async fn handle_request(
+        &self,
+        mut param: RpcParam,
+        default_gid: Option<GroupId>,
+    ) -> Result<unified::HandleResult> {
+        let id_s = param.get_mut("id").map(|id| id.take());
+        let is_notification = id_s.is_none();
+        let id = id_s.unwrap_or(RpcParam::Null);
+        let method_s = param["method"].take();
+        let method = method_s.as_str().unwrap_or("");
+        let mut new_results = unified::HandleResult::new();
+
+        // the response has the gid when the request has.
+        let request_gid = param.get("gid").and_then(|gid_v| gid_v.as_u64());
+        let group = match request_gid.or(default_gid) {
+            Some(gid) => gid,
+            None => {
+                if !is_notification {
+                    new_results.rpcs.push(RpcError::InvalidRequest.json(id));
+                }
+                return Ok(new_results);
+            }
+        };
 
+        if !self.is_authorized(method, &param) {
+            if !is_notification {
+                new_results
+                    .rpcs
+                    .push(RpcError::Unauthorized(method.to_owned()).json(id));
+            }
             return Ok(new_results);
         }
 
-        if let RpcParam::Array(params) = param["params"].take() {
+        // built-in methods.
+        let builtin = match method {
+            "rpcs" => {
+                let mut methods: Vec<&str> = self.fns.keys().map(|v| *v).collect();
+                methods.sort();
+                Some(json!(methods))
+            }
+            "rpc.discover" => Some(self.openrpc()),
+            _ => None,
+        };
+
+        if let Some(params) = builtin {
+            if !is_notification {
+                let res = method_response(id, method, params, request_gid);
+                new_results.rpcs.push(res);
+            }
+            return Ok(new_results);
+        }
+
+        // named params will be the only one param.
+        let params = match param["params"].take() {
+            RpcParam::Array(params) => Some(params),
+            RpcParam::Object(params) => Some(vec![RpcParam::Object(params)]),
+            _ => None,
+        };
+
+        if let Some(params) = params {
             match self.fns.get(method) {
-                Some(f) => {
-                    #[cfg(any(feature = "single", feature = "std"))]
-                    let res = f.call(params, self.state.clone()).await;
-
-                    #[cfg(any(feature = "multiple", feature = "full"))]
-                    let res = f.call(group, params, self.state.clone()).await;
-
-                    #[cfg(any(feature = "single", feature = "multiple"))]
-                    match res {
-                        Ok(HandleResult {
-                            owns,
-                            rpcs,
-                            groups,
-                            networks,
-                        }) => {
-                            new_results.owns = owns;
-                            new_results.groups = groups;
-                            new_results.networks = networks;
-
-                            for params in rpcs {
-                                // check when params is complete jsonrpc result.
-                                if params.is_object() && params.get("jsonrpc").is_some() {
-                                    new_results.rpcs.push(params);
-                                    continue;
-                                }
-
-                                #[cfg(feature = "single")]
-                                new_results.rpcs.push(rpc_response(id, method, params));
-
-                                #[cfg(feature = "multiple")]
-                                new_results
-                                    .rpcs
-                                    .push(rpc_response(id, method, params, group));
+                Some(f) => match f.call(group, params, self.state.clone()).await {
+                    Ok(mut results) => {
+                        let rpcs = std::mem::take(&mut results.rpcs);
+                        new_results = results;
+
+                        for params in rpcs {
+                            // check when params is complete jsonrpc result.
+                            if params.is_object() && params.get("jsonrpc").is_some() {
+                                new_results.rpcs.push(params);
+                                continue;
                             }
-                        }
-                        Err(err) => {
-                            let mut res = err.json(id);
-                            res["method"] = method.into();
-                            #[cfg(feature = "multiple")]
-                            let _ = res.as_object_mut().map(|v| {
-                                v.insert("gid".into(), group.into());
-                            });
+
+                            let res = method_response(id.clone(), method, params, request_gid);
                             new_results.rpcs.push(res);
                         }
                     }
-
-                    #[cfg(any(feature = "full", feature = "std"))]
-                    match res {
-                        Ok(HandleResult {
-                            owns,
-                            rpcs,
-                            groups,
-                            layers,
-                            networks,
-                        }) => {
-                            new_results.owns = owns;
-                            new_results.groups = groups;
-                            new_results.layers = layers;
-                            new_results.networks = networks;
-
-                            for params in rpcs {
-                                // check when params is complete jsonrpc result.
-                                if params.is_object() && params.get("jsonrpc").is_some() {
-                                    new_results.rpcs.push(params);
-                                    continue;
-                                }
-
-                                #[cfg(feature = "std")]
-                                new_results.rpcs.push(rpc_response(id, method, params));
-
-                                #[cfg(feature = "full")]
-                                new_results
-                                    .rpcs
-                                    .push(rpc_response(id, method, params, group));
-                            }
-                        }
-                        Err(err) => {
-                            let mut res = err.json(id);
-                            res["method"] = method.into();
-                            #[cfg(feature = "full")]
-                            let _ = res.as_object_mut().map(|v| {
-                                v.insert("gid".into(), group.into());
-                            });
-                            new_results.rpcs.push(res);
+                    Err(err) => {
+                        let mut res = err.json(id.clone());
+                        res["method"] = method.into();
+                        if let Some(gid) = request_gid {
+                            res["gid"] = gid.into();
                         }
+                        new_results.rpcs.push(res);
                     }
-                }
+                },
                 None => new_results
                     .rpcs
                     .push(RpcError::MethodNotFound(method.to_owned()).json(id)),
@@ -381,12 +695,66 @@ impl<S: 'static + Send + Sync> RpcHandler<S> {
             new_results.rpcs.push(RpcError::InvalidRequest.json(id))
         }
 
+        if is_notification {
+            new_results.rpcs.clear();
+        }
+
         Ok(new_results)
     }
 }
 
-#[cfg(any(feature = "single", feature = "std"))]
-pub fn rpc_response(id: u64, method: &str, params: RpcParam) -> RpcParam {
+/// the response of the method, has the gid when the request has.
+fn method_response(id: RpcParam, method: &str, result: RpcParam, gid: Option<GroupId>) -> RpcParam {
+    let mut res = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "result": result
+    });
+    if let Some(gid) = gid {
+        res["gid"] = gid.into();
+    }
+    res
+}
+
+/// decode the params to typed, named params is one object, positional
+/// params is an array. the error will has the path of the invalid field.
+pub fn decode_params<P: DeserializeOwned>(
+    mut params: Vec<RpcParam>,
+) -> std::result::Result<P, RpcError> {
+    if params.len() == 1 && params[0].is_object() {
+        let named = params.pop().unwrap_or_default();
+        match serde_path_to_error::deserialize(&named) {
+            Ok(p) => return Ok(p),
+            Err(e) => {
+                // maybe is one positional param.
+                if let Ok(p) = serde_json::from_value(RpcParam::Array(vec![named])) {
+                    return Ok(p);
+                }
+                return Err(param_error(e));
+            }
+        }
+    }
+
+    serde_path_to_error::deserialize(RpcParam::Array(params)).map_err(param_error)
+}
+
+fn param_error(e: serde_path_to_error::Error<serde_json::Error>) -> RpcError {
+    let path = e.path().to_string();
+    if path == "." {
+        RpcError::InvalidParams(e.inner().to_string())
+    } else {
+        RpcError::InvalidParams(format!("field `{}`: {}", path, e.inner()))
+    }
+}
+
+fn encode_result<R: Serialize>(result: R) -> std::result::Result<RpcParam, RpcError> {
+    serde_json::to_value(result).map_err(|e| RpcError::Custom(format!("{}", e)))
+}
+
+#[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+pub fn rpc_response(id: impl Into<RpcParam>, method: &str, params: RpcParam) -> RpcParam {
+    let id = id.into();
     json!({
         "jsonrpc": "2.0",
         "id": id,
@@ -395,13 +763,14 @@ pub fn rpc_response(id: u64, method: &str, params: RpcParam) -> RpcParam {
     })
 }
 
-#[cfg(any(feature = "multiple", feature = "full"))]
+#[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
 pub fn rpc_response(
-    id: u64,
+    id: impl Into<RpcParam>,
     method: &str,
     params: RpcParam,
     group_id: crate::group::GroupId,
 ) -> RpcParam {
+    let id = id.into();
     json!({
         "jsonrpc": "2.0",
         "id": id,
@@ -411,7 +780,7 @@ pub fn rpc_response(
     })
 }
 
-#[cfg(any(feature = "single", feature = "std"))]
+#[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
 pub fn rpc_request(id: u64, method: &str, params: Vec<RpcParam>) -> RpcParam {
     json!({
         "jsonrpc": "2.0",
@@ -421,7 +790,7 @@ pub fn rpc_request(id: u64, method: &str, params: Vec<RpcParam>) -> RpcParam {
     })
 }
 
-#[cfg(any(feature = "multiple", feature = "full"))]
+#[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
 pub fn rpc_request(
     id: u64,
     method: &str,
@@ -445,3 +814,129 @@ pub fn parse_response(mut value: RpcParam) -> std::result::Result<Value, Value>
         Err(value["error"].take())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jsonrpc() {
+        let param =
+            parse_jsonrpc(r#"{"jsonrpc":"2.0","id":"a1","method":"echo"}"#.to_owned()).unwrap();
+        assert_eq!(param["id"], json!("a1"));
+        assert_eq!(param["params"], json!([]));
+        assert!(!is_notification(&param));
+
+        let param = parse_jsonrpc(r#"{"jsonrpc":"2.0","method":"echo"}"#.to_owned()).unwrap();
+        assert!(is_notification(&param));
+
+        let param = parse_jsonrpc(
+            r#"[{"jsonrpc":"2.0","method":"a"},{"jsonrpc":"2.0","id":2,"method":"b"},1]"#
+                .to_owned(),
+        )
+        .unwrap();
+        assert_eq!(param.as_array().unwrap().len(), 3);
+        assert!(!is_notification(&param));
+
+        let (err, id) = parse_jsonrpc(r#"{"jsonrpc":"1.0","id":"x","method":"a"}"#.to_owned())
+            .err()
+            .unwrap();
+        assert_eq!(err.json(id)["id"], json!("x"));
+        assert!(parse_jsonrpc("[]".to_owned()).is_err());
+        assert!(parse_jsonrpc("{".to_owned()).is_err());
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_typed_method() {
+        #[derive(serde::Deserialize)]
+        struct Add {
+            a: i64,
+            b: i64,
+        }
+
+        let mut handler = RpcHandler::new(());
+        handler.add_typed_method("add", |p: Add, _| async move { Ok(p.a + p.b) });
+        handler.add_typed_method("hello", |(name,): (String,), _| async move {
+            Ok(format!("hello {}", name))
+        });
+
+        let call = |req: RpcParam| async { handler.handle(req).await.unwrap().rpcs.remove(0) };
+
+        let res = call(json!({"jsonrpc":"2.0","id":1,"method":"add","params":[1, 2]})).await;
+        assert_eq!(res["result"], json!(3));
+        let res = call(json!({"jsonrpc":"2.0","id":2,"method":"add","params":{"a":1,"b":2}})).await;
+        assert_eq!(res["result"], json!(3));
+        let res = call(json!({"jsonrpc":"2.0","id":3,"method":"hello","params":["tdn"]})).await;
+        assert_eq!(res["result"], json!("hello tdn"));
+
+        let res =
+            call(json!({"jsonrpc":"2.0","id":4,"method":"add","params":{"a":1,"b":"2"}})).await;
+        assert_eq!(res["error"]["code"], json!(-32600));
+        assert!(res["error"]["message"].as_str().unwrap().contains("`b`"));
+        let res = call(json!({"jsonrpc":"2.0","id":5,"method":"add","params":{"a":1}})).await;
+        assert!(res["error"]["message"].as_str().unwrap().contains("`b`"));
+    }
+
+    #[tokio::test]
+    async fn test_unified_method() {
+        let mut handler = RpcHandler::new(());
+        handler.add_unified_method("gid", |gid: GroupId, _, _| async move {
+            Ok(unified::HandleResult::rpc(json!(gid)))
+        });
+        handler.add_unified_typed_method("add", |gid, (a, b): (u64, u64), _| async move {
+            Ok(gid + a + b)
+        });
+
+        let req = json!({"jsonrpc":"2.0","id":1,"method":"gid","params":[]});
+        let res = handler.handle_unified(req, 7).await.unwrap().rpcs.remove(0);
+        assert_eq!(res["result"], json!(7));
+        assert!(res.get("gid").is_none());
+
+        let req = json!({"jsonrpc":"2.0","id":2,"method":"gid","params":[],"gid":3});
+        let res = handler.handle_unified(req, 7).await.unwrap().rpcs.remove(0);
+        assert_eq!((&res["result"], &res["gid"]), (&json!(3), &json!(3)));
+
+        let req = json!({"jsonrpc":"2.0","id":3,"method":"add","params":[1, 2]});
+        let res = handler.handle_unified(req, 7).await.unwrap().rpcs.remove(0);
+        assert_eq!(res["result"], json!(10));
+
+        // the legacy shim of the mode features.
+        let req = json!({"jsonrpc":"2.0","id":4,"method":"gid","params":[],"gid":3});
+        let res = handler.handle(req).await.unwrap().rpcs.remove(0);
+        assert_eq!(res["result"], json!(3));
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[tokio::test]
+    async fn test_rpc_discover() {
+        let mut handler = RpcHandler::new(());
+        handler.add_method("echo", |params: Vec<RpcParam>, _| async move {
+            Ok(HandleResult::rpc(json!(params)))
+        });
+        handler.add_method("ping", |_, _| async move {
+            Ok(HandleResult::rpc(json!("pong")))
+        });
+        handler.set_method_meta(
+            "echo",
+            RpcMethodMeta::new("echo the params")
+                .param("value", json!({"type": "string"}))
+                .result(json!({"type": "array"})),
+        );
+        handler.set_info("test", "1.0.0");
+
+        let req = json!({"jsonrpc":"2.0","id":1,"method":"rpc.discover"});
+        let mut res = handler.handle(req).await.unwrap().rpcs.remove(0);
+        let doc = res["result"].take();
+        assert_eq!(doc["openrpc"], json!(OPENRPC_VERSION));
+        assert_eq!(doc["info"]["title"], json!("test"));
+        let methods = doc["methods"].as_array().unwrap();
+        assert_eq!(methods.len(), 2);
+        assert_eq!(methods[0]["name"], json!("echo"));
+        assert_eq!(methods[0]["description"], json!("echo the params"));
+        assert_eq!(methods[0]["params"][0]["name"], json!("value"));
+        assert_eq!(methods[0]["result"]["schema"]["type"], json!("array"));
+        assert_eq!(methods[1]["name"], json!("ping"));
+        assert_eq!(methods[1]["params"], json!([]));
+    }
+}
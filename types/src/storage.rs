@@ -1,17 +1,118 @@
 use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+use tokio::sync::mpsc::Receiver;
 
 use crate::primitives::Result;
 
+/// the changes of the storage, send to the watchers.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StorageEvent<K> {
+    /// the key is inserted or updated.
+    Insert(K),
+    /// the key is removed.
+    Remove(K),
+}
+
+/// operation in the batch.
+#[derive(Debug, Clone)]
+pub enum BatchOp<K> {
+    /// key and serialized value.
+    Write(K, Vec<u8>),
+    Delete(K),
+}
+
+/// Atomic multi-key writes, all done or nothing.
+/// values are serialized by `bincode`.
+#[derive(Debug, Clone)]
+pub struct Batch<K> {
+    pub ops: Vec<BatchOp<K>>,
+}
+
+impl<K> Default for Batch<K> {
+    fn default() -> Self {
+        Batch { ops: vec![] }
+    }
+}
+
+impl<K> Batch<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write<T: Serialize>(&mut self, key: K, value: &T) -> Result<()> {
+        self.ops
+            .push(BatchOp::Write(key, bincode::serialize(value)?));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: K) {
+        self.ops.push(BatchOp::Delete(key));
+    }
+}
+
+/// a page of the scanned keys and values, ordered by key.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Page<K, T> {
+    pub items: Vec<(K, T)>,
+    /// the first key of the next page, none when it is the last page.
+    pub next: Option<K>,
+}
+
 pub trait Storage: 'static + Send + Sync {
     type Key: 'static + Send + Sync;
 
-    fn read<T: Serialize + DeserializeOwned>(&self, key: &Self::Key) -> Option<T>;
+    fn read<T: Serialize + DeserializeOwned + Send>(
+        &self,
+        key: &Self::Key,
+    ) -> impl Future<Output = Option<T>> + Send;
+
+    fn write<T: Serialize + DeserializeOwned + Sync>(
+        &self,
+        key: &Self::Key,
+        value: &T,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn update<T: Serialize + DeserializeOwned + Sync>(
+        &self,
+        key: &Self::Key,
+        value: &T,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn delete<T: Serialize + DeserializeOwned + Send>(
+        &self,
+        key: &Self::Key,
+    ) -> impl Future<Output = Result<T>> + Send;
+
+    /// at most `limit` keys and values which key starts with prefix, from
+    /// the `start` key (or the first), the next page starts from `page.next`.
+    fn scan_prefix<T: Serialize + DeserializeOwned + Send>(
+        &self,
+        prefix: &Self::Key,
+        start: Option<&Self::Key>,
+        limit: usize,
+    ) -> impl Future<Output = Result<Page<Self::Key, T>>> + Send;
+
+    /// at most `limit` keys and values in `[start, end)`,
+    /// the next page is `[page.next, end)`.
+    fn range<T: Serialize + DeserializeOwned + Send>(
+        &self,
+        start: &Self::Key,
+        end: &Self::Key,
+        limit: usize,
+    ) -> impl Future<Output = Result<Page<Self::Key, T>>> + Send;
 
-    fn write<T: Serialize + DeserializeOwned>(&self, key: &Self::Key, value: &T) -> Result<()>;
+    /// apply the batch atomically.
+    fn batch(&self, batch: Batch<Self::Key>) -> impl Future<Output = Result<()>> + Send;
 
-    fn update<T: Serialize + DeserializeOwned>(&self, key: &Self::Key, value: &T) -> Result<()>;
+    /// watch the changes of keys which starts with prefix.
+    /// stop watching when drop the receiver.
+    fn watch(
+        &self,
+        prefix: &Self::Key,
+    ) -> impl Future<Output = Result<Receiver<StorageEvent<Self::Key>>>> + Send;
 
-    fn delete<T: Serialize + DeserializeOwned>(&self, key: &Self::Key) -> Result<T>;
+    /// persist all changes to the disk.
+    fn flush(&self) -> impl Future<Output = Result<()>> + Send;
 
     fn keystore(&self, _key: &Self::Key, _passed: &[u8], _secret: &[u8]) -> Result<()> {
         Err(anyhow::anyhow!("unimplemented"))
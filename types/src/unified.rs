@@ -0,0 +1,296 @@
+//! the unified messages always carry the `GroupId`s, and the mode is chosen
+//! at run-time, so one workspace can host all the modes. the legacy messages
+//! of the mode features are converted to/from them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::group::GroupId;
+use crate::message::{self, NetworkType, RecvType, SendType};
+use crate::primitives::{self, new_io_error, Result};
+use crate::rpc::RpcParam;
+
+/// the run-time mode of TDN.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// only one group, no layers.
+    Single,
+    /// only one group, multiple layers.
+    Std,
+    /// multiple groups, no layers.
+    Multiple,
+    /// multiple groups, multiple layers.
+    Full,
+}
+
+#[cfg(tdn_mode = "single")]
+const LEGACY_MODE: Mode = Mode::Single;
+#[cfg(tdn_mode = "std")]
+const LEGACY_MODE: Mode = Mode::Std;
+#[cfg(tdn_mode = "multiple")]
+const LEGACY_MODE: Mode = Mode::Multiple;
+#[cfg(tdn_mode = "full")]
+const LEGACY_MODE: Mode = Mode::Full;
+
+impl Default for Mode {
+    /// the mode of the legacy messages.
+    fn default() -> Self {
+        LEGACY_MODE
+    }
+}
+
+impl Mode {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            Mode::Single => "single",
+            Mode::Std => "std",
+            Mode::Multiple => "multiple",
+            Mode::Full => "full",
+        }
+    }
+
+    pub fn has_multiple_groups(&self) -> bool {
+        matches!(self, Mode::Multiple | Mode::Full)
+    }
+
+    pub fn has_layers(&self) -> bool {
+        matches!(self, Mode::Std | Mode::Full)
+    }
+
+    /// single and std have only one group.
+    pub fn check_groups(&self, groups: &[GroupId]) -> Result<()> {
+        if !self.has_multiple_groups() && groups.len() > 1 {
+            let info = format!("{} mode has only one group", self.to_str());
+            return Err(new_io_error(&info).into());
+        }
+        Ok(())
+    }
+}
+
+/// channel message send to TDN for all modes.
+#[derive(Debug)]
+pub enum SendMessage {
+    /// P2P network with same PeerId.
+    Own(SendType),
+    /// Group: group id, msg.
+    Group(GroupId, SendType),
+    /// Layer: sender's group, receiver's group, msg. Take care of `Leave`.
+    Layer(GroupId, GroupId, SendType),
+    /// RPC: connection uid, request params, is websocket.
+    Rpc(u64, RpcParam, bool),
+    /// RPC: publish params to the WS connections subscribed the topic.
+    Publish(String, RpcParam),
+    /// Network: Control the Network state.
+    Network(NetworkType),
+}
+
+impl SendMessage {
+    /// from the legacy message, `gid` is the group of single and std.
+    pub fn from_legacy(msg: message::SendMessage, gid: GroupId) -> Self {
+        #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+        let _ = gid; // the legacy message has groups.
+
+        match msg {
+            message::SendMessage::Own(m) => SendMessage::Own(m),
+            #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+            message::SendMessage::Group(m) => SendMessage::Group(gid, m),
+            #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+            message::SendMessage::Group(g, m) => SendMessage::Group(g, m),
+            #[cfg(tdn_mode = "std")]
+            message::SendMessage::Layer(tgid, m) => SendMessage::Layer(gid, tgid, m),
+            #[cfg(tdn_mode = "full")]
+            message::SendMessage::Layer(fgid, tgid, m) => SendMessage::Layer(fgid, tgid, m),
+            message::SendMessage::Rpc(uid, params, is_ws) => SendMessage::Rpc(uid, params, is_ws),
+            message::SendMessage::Publish(topic, params) => SendMessage::Publish(topic, params),
+            message::SendMessage::Network(m) => SendMessage::Network(m),
+        }
+    }
+}
+
+/// channel message receive from TDN for all modes.
+#[derive(Debug)]
+pub enum ReceiveMessage {
+    /// P2P network with same PeerId.
+    Own(RecvType),
+    /// Group: group id, msg.
+    Group(GroupId, RecvType),
+    /// Layer: sender's group, receiver's group, msg.
+    Layer(GroupId, GroupId, RecvType),
+    /// RPC: connection uid, request params, is websocket.
+    Rpc(u64, RpcParam, bool),
+    /// when network lost all DHT network and direct stables. will tell outside.
+    NetworkLost,
+}
+
+impl ReceiveMessage {
+    /// to the legacy message, none if the legacy has no layers.
+    pub fn into_legacy(self) -> Option<message::ReceiveMessage> {
+        let msg = match self {
+            ReceiveMessage::Own(m) => message::ReceiveMessage::Own(m),
+            #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+            ReceiveMessage::Group(_, m) => message::ReceiveMessage::Group(m),
+            #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+            ReceiveMessage::Group(g, m) => message::ReceiveMessage::Group(g, m),
+            #[cfg(any(tdn_mode = "std", tdn_mode = "full"))]
+            ReceiveMessage::Layer(fgid, tgid, m) => message::ReceiveMessage::Layer(fgid, tgid, m),
+            #[cfg(any(tdn_mode = "single", tdn_mode = "multiple"))]
+            ReceiveMessage::Layer(..) => return None,
+            ReceiveMessage::Rpc(uid, params, is_ws) => {
+                message::ReceiveMessage::Rpc(uid, params, is_ws)
+            }
+            ReceiveMessage::NetworkLost => message::ReceiveMessage::NetworkLost,
+        };
+        Some(msg)
+    }
+}
+
+/// Helper: the group/layer/rpc handle result for all modes.
+#[derive(Default)]
+pub struct HandleResult {
+    /// P2P network with same PeerId.
+    pub owns: Vec<SendType>,
+    /// rpc tasks: [(method, params)].
+    pub rpcs: Vec<RpcParam>,
+    /// group tasks: [(group id, GroupSendMessage)]
+    pub groups: Vec<(GroupId, SendType)>,
+    /// layer tasks: [(sender's group, receiver's group, LayerSendMessage)]
+    pub layers: Vec<(GroupId, GroupId, SendType)>,
+    /// network tasks: [NetworkType]
+    pub networks: Vec<NetworkType>,
+}
+
+impl HandleResult {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn own(m: SendType) -> Self {
+        HandleResult {
+            owns: vec![m],
+            ..Default::default()
+        }
+    }
+
+    pub fn rpc(p: RpcParam) -> Self {
+        HandleResult {
+            rpcs: vec![p],
+            ..Default::default()
+        }
+    }
+
+    pub fn group(gid: GroupId, m: SendType) -> Self {
+        HandleResult {
+            groups: vec![(gid, m)],
+            ..Default::default()
+        }
+    }
+
+    pub fn layer(fgid: GroupId, tgid: GroupId, m: SendType) -> Self {
+        HandleResult {
+            layers: vec![(fgid, tgid, m)],
+            ..Default::default()
+        }
+    }
+
+    pub fn network(m: NetworkType) -> Self {
+        HandleResult {
+            networks: vec![m],
+            ..Default::default()
+        }
+    }
+
+    /// append all the tasks of other result.
+    pub fn merge(&mut self, mut other: HandleResult) {
+        self.owns.append(&mut other.owns);
+        self.rpcs.append(&mut other.rpcs);
+        self.groups.append(&mut other.groups);
+        self.layers.append(&mut other.layers);
+        self.networks.append(&mut other.networks);
+    }
+
+    /// from the legacy result, `gid` is the group of single and std.
+    pub fn from_legacy(result: primitives::HandleResult, gid: GroupId) -> Self {
+        #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+        let _ = gid; // the legacy result has groups.
+
+        HandleResult {
+            owns: result.owns,
+            rpcs: result.rpcs,
+            #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+            groups: result.groups.into_iter().map(|m| (gid, m)).collect(),
+            #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+            groups: result.groups,
+            #[cfg(tdn_mode = "std")]
+            layers: result
+                .layers
+                .into_iter()
+                .map(|(tgid, m)| (gid, tgid, m))
+                .collect(),
+            #[cfg(tdn_mode = "full")]
+            layers: result.layers,
+            #[cfg(any(tdn_mode = "single", tdn_mode = "multiple"))]
+            layers: vec![],
+            networks: result.networks,
+        }
+    }
+
+    /// into the legacy result, the tasks which the legacy not has are dropped.
+    pub fn into_legacy(self) -> primitives::HandleResult {
+        primitives::HandleResult {
+            owns: self.owns,
+            rpcs: self.rpcs,
+            #[cfg(any(tdn_mode = "single", tdn_mode = "std"))]
+            groups: self.groups.into_iter().map(|(_, m)| m).collect(),
+            #[cfg(any(tdn_mode = "multiple", tdn_mode = "full"))]
+            groups: self.groups,
+            #[cfg(tdn_mode = "std")]
+            layers: self
+                .layers
+                .into_iter()
+                .map(|(_, tgid, m)| (tgid, m))
+                .collect(),
+            #[cfg(tdn_mode = "full")]
+            layers: self.layers,
+            networks: self.networks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::PeerId;
+
+    #[test]
+    fn test_mode() {
+        assert!(Mode::Full.has_layers() && Mode::Full.has_multiple_groups());
+        assert!(!Mode::Single.has_layers() && !Mode::Single.has_multiple_groups());
+        assert!(Mode::Std.check_groups(&[1]).is_ok());
+        assert!(Mode::Std.check_groups(&[1, 2]).is_err());
+        assert!(Mode::Multiple.check_groups(&[1, 2]).is_ok());
+    }
+
+    #[cfg(tdn_mode = "std")]
+    #[test]
+    fn test_legacy() {
+        assert_eq!(Mode::default(), Mode::Std);
+        let event = || SendType::Event(0, PeerId::default(), vec![]);
+
+        let msg = SendMessage::from_legacy(message::SendMessage::Group(event()), 1);
+        assert!(matches!(msg, SendMessage::Group(1, _)));
+        let msg = SendMessage::from_legacy(message::SendMessage::Layer(2, event()), 1);
+        assert!(matches!(msg, SendMessage::Layer(1, 2, _)));
+
+        let msg = ReceiveMessage::Group(1, RecvType::Event(PeerId::default(), vec![]));
+        assert!(matches!(
+            msg.into_legacy(),
+            Some(message::ReceiveMessage::Group(RecvType::Event(..)))
+        ));
+
+        let mut result = primitives::HandleResult::group(event());
+        result.merge(primitives::HandleResult::layer(2, event()));
+        let result = HandleResult::from_legacy(result, 1);
+        assert_eq!(result.groups[0].0, 1);
+        assert_eq!((result.layers[0].0, result.layers[0].1), (1, 2));
+    }
+}